@@ -0,0 +1,123 @@
+use std::cmp::Reverse;
+use std::str::FromStr;
+use anyhow::anyhow;
+use chrono::{DateTime, Datelike, Duration, Utc};
+use poem_openapi::{Enum, Object};
+use scylla::IntoTypedRows;
+use strum::{Display, EnumString};
+use uuid::Uuid;
+
+use crate::db::Session;
+use crate::utils;
+
+
+#[derive(Enum, Display, EnumString, Copy, Clone, Debug)]
+#[strum(serialize_all = "snake_case", ascii_case_insensitive)]
+#[oai(rename_all = "snake_case")]
+pub enum AuditAction {
+    RemovePlaylist,
+    RemoveEntry,
+    RestorePlaylist,
+    PurgePlaylist,
+    PurgeEntry,
+    FeaturePlaylist,
+    UnfeaturePlaylist,
+}
+
+
+#[derive(Object)]
+pub struct AuditEntry {
+    pub id: Uuid,
+
+    /// The fingerprint of the superuser key which performed the action.
+    pub actor: String,
+
+    pub action: AuditAction,
+
+    /// The id of the playlist or entry the action was performed on.
+    pub target_id: Uuid,
+
+    pub created_at: DateTime<Utc>,
+}
+
+
+/// Entries are bucketed by the month they were created in so no single
+/// partition grows forever.
+fn bucket_for(dt: DateTime<Utc>) -> String {
+    format!("{}-{:02}", dt.year(), dt.month())
+}
+
+/// Records an action in the audit log.
+///
+/// This is called once the action has been carried out, so the log only
+/// contains actions which actually happened.
+pub async fn record(
+    sess: &Session,
+    actor: &str,
+    action: AuditAction,
+    target_id: Uuid,
+) -> anyhow::Result<()> {
+    let created_at = utils::now();
+
+    sess.query_prepared(
+        r#"
+        INSERT INTO audit_log (
+            bucket,
+            created_at,
+            id,
+            actor,
+            action,
+            target_id
+        ) VALUES (?, ?, ?, ?, ?, ?);
+        "#,
+        (
+            bucket_for(created_at),
+            utils::to_cql_timestamp(created_at),
+            Uuid::new_v4(),
+            actor,
+            action.to_string(),
+            target_id,
+        )
+    ).await?;
+
+    Ok(())
+}
+
+/// Gets up to `limit` of the most recent audit log entries from this month
+/// and the last, newest first.
+pub async fn get_recent_entries(sess: &Session, limit: usize) -> anyhow::Result<Vec<AuditEntry>> {
+    let now = utils::now();
+    let this_month = now.with_day(1).unwrap_or(now);
+    let buckets = vec![
+        bucket_for(this_month),
+        bucket_for(this_month - Duration::days(1)),
+    ];
+
+    let result = sess.query_prepared(
+        r#"
+        SELECT id, actor, action, target_id, created_at
+        FROM audit_log WHERE bucket IN ? PER PARTITION LIMIT ?;
+        "#,
+        (buckets, limit as i32)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    type AuditInfo = (Uuid, String, String, Uuid, chrono::Duration);
+    let mut entries: Vec<AuditEntry> = rows.into_typed::<AuditInfo>()
+        .filter_map(|v| v.ok())
+        .filter_map(|v| Some(AuditEntry {
+            id: v.0,
+            actor: v.1,
+            action: AuditAction::from_str(&v.2).ok()?,
+            target_id: v.3,
+            created_at: utils::from_cql_timestamp(v.4),
+        }))
+        .collect();
+
+    entries.sort_by_key(|v| Reverse(v.created_at));
+    entries.truncate(limit);
+
+    Ok(entries)
+}
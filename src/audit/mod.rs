@@ -0,0 +1,35 @@
+mod log;
+
+use poem::Result;
+use poem::web::Data;
+use poem_openapi::OpenApi;
+use poem_openapi::param::Query;
+use poem_openapi::payload::Json;
+
+pub use log::*;
+use crate::ApiTags;
+use crate::db::Session;
+use crate::utils::{SuperUserBearer, DEFAULT_PAGE_SIZE};
+
+
+pub struct AuditApi;
+
+#[OpenApi]
+impl AuditApi {
+    /// Get Audit Log
+    ///
+    /// Get the most recent superuser actions from the last two months, newest first.
+    #[oai(path = "/audit", method = "get", tag = "ApiTags::Audit")]
+    pub async fn get_audit_log(
+        &self,
+        #[oai(validator(minimum(value = "1"), maximum(value = "100")))]
+        limit: Query<Option<i32>>,
+        session: Data<&Session>,
+        _token: SuperUserBearer,
+    ) -> Result<Json<Vec<AuditEntry>>> {
+        let limit = limit.0.unwrap_or(DEFAULT_PAGE_SIZE) as usize;
+        let entries = log::get_recent_entries(&session, limit).await?;
+
+        Ok(Json(entries))
+    }
+}
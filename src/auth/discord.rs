@@ -53,7 +53,7 @@ pub async fn exchange_code(code: &str) -> Result<String> {
     };
 
     trace!("exchanging payload {:?}", &body);
-    let resp = client.post(&format!("{}/oauth2/token", DISCORD_URL))
+    let resp = client.post(format!("{}/oauth2/token", DISCORD_URL))
         .header(reqwest::header::CONTENT_TYPE, "application/x-www-form-urlencoded")
         .form(&body)
         .send()
@@ -90,7 +90,7 @@ pub struct UserInfo {
 pub async fn fetch_user_info(token: &str) -> Result<UserInfo> {
     let client = reqwest::Client::new();
 
-    let resp = client.get(&format!("{}/users/@me", DISCORD_URL))
+    let resp = client.get(format!("{}/users/@me", DISCORD_URL))
         .header(reqwest::header::AUTHORIZATION, bearer(token))
         .send()
         .await?;
@@ -137,7 +137,7 @@ impl Guild {
 pub async fn fetch_user_guilds(token: &str) -> Result<Vec<Guild>> {
     let client = reqwest::Client::new();
 
-    let resp = client.get(&format!("{}/users/@me/guilds", DISCORD_URL))
+    let resp = client.get(format!("{}/users/@me/guilds", DISCORD_URL))
         .header(reqwest::header::AUTHORIZATION, bearer(token))
         .send()
         .await?;
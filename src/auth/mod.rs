@@ -13,6 +13,7 @@ use rand::Rng;
 use crate::ApiTags;
 use crate::auth::discord::Guild;
 use crate::db::Session;
+use crate::users::user_info;
 
 #[derive(Object)]
 pub struct ExchangePayload {
@@ -46,7 +47,7 @@ impl AuthApi {
             })
             .collect();
 
-        session.query(
+        session.query_prepared(
             "INSERT INTO users (id, username, avatar, updated_on, access_servers) VALUES (?, ?, ?, toTimeStamp(now()), ?);",
             (user.id, user.username, user.avatar, guilds)
         ).await?;
@@ -76,9 +77,11 @@ impl AuthApi {
     ) -> Result<()> {
         session.query_prepared(
             "DELETE FROM access_tokens WHERE access_token = ?;",
-            (token.0,)
+            (token.0.clone(),)
         ).await?;
 
+        user_info::invalidate_cached_token(&token.0);
+
         Ok(())
     }
 }
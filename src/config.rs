@@ -0,0 +1,212 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use poem::http::HeaderValue;
+use reqwest::Url;
+
+use crate::webhooks::EventType;
+
+
+/// Runtime settings read from the environment at startup.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// How long in seconds a user must wait before voting on the same item again.
+    pub vote_cooldown_secs: i32,
+
+    /// The number of credits spent by a single vote.
+    pub vote_credit_cost: i32,
+
+    /// The number of credits granted by the daily claim.
+    pub daily_credit_claim: i32,
+
+    /// How often in seconds active users are given a credit.
+    pub credit_regen_interval_secs: u64,
+
+    /// The balance credit regeneration stops at, credits from other sources
+    /// can still take users above this.
+    pub credit_regen_cap: i32,
+
+    /// How often in seconds the vote leaderboard is recomputed.
+    pub leaderboard_interval_secs: u64,
+
+    /// The maximum number of playlists a single user can own.
+    pub max_playlists_per_user: i64,
+
+    /// The largest request body accepted in bytes.
+    pub max_body_bytes: usize,
+
+    /// The maximum number of clients streaming vote counts at once, across
+    /// every playlist.
+    pub max_vote_subscribers: usize,
+
+    /// The origins allowed to make cross-origin requests, if empty all
+    /// cross-origin requests are denied.
+    pub allowed_origins: Vec<String>,
+
+    /// The hosts entry `ref_link`s may point to, subdomains of these hosts
+    /// are also allowed.
+    pub allowed_link_hosts: Vec<String>,
+
+    /// Whether playlist banners are checked to be reachable images with a
+    /// `HEAD` request before they are accepted.
+    pub check_banner_urls: bool,
+
+    /// The number of requests each user, or client IP for anonymous
+    /// requests, can make per minute.
+    pub rate_limit_per_min: u32,
+
+    /// The url events are POSTed to, webhooks are disabled if this is unset.
+    pub webhook_url: Option<String>,
+
+    /// The events sent to the webhook, every event if `WEBHOOK_EVENTS` is unset.
+    pub webhook_events: Vec<EventType>,
+}
+
+impl Config {
+    /// Reads the config from the environment, falling back to the defaults
+    /// for any unset variables.
+    ///
+    /// This errors if any variable is set but malformed, rather than silently
+    /// using the default.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let vote_cooldown_secs = parse_env("VOTE_COOLDOWN_SECS", 12 * 60 * 60)?;
+        if vote_cooldown_secs <= 0 {
+            return Err(anyhow!("VOTE_COOLDOWN_SECS must be greater than 0"))
+        }
+
+        let vote_credit_cost = parse_env("VOTE_CREDIT_COST", 1)?;
+        if vote_credit_cost < 0 {
+            return Err(anyhow!("VOTE_CREDIT_COST must not be negative"))
+        }
+
+        let daily_credit_claim = parse_env("DAILY_CREDIT_CLAIM", 5)?;
+        if daily_credit_claim <= 0 {
+            return Err(anyhow!("DAILY_CREDIT_CLAIM must be greater than 0"))
+        }
+
+        let credit_regen_interval_secs = parse_env("CREDIT_REGEN_INTERVAL_SECS", 3 * 60 * 60)?;
+        if credit_regen_interval_secs == 0 {
+            return Err(anyhow!("CREDIT_REGEN_INTERVAL_SECS must be greater than 0"))
+        }
+
+        let credit_regen_cap = parse_env("CREDIT_REGEN_CAP", 20)?;
+        if credit_regen_cap < 0 {
+            return Err(anyhow!("CREDIT_REGEN_CAP must not be negative"))
+        }
+
+        let leaderboard_interval_secs = parse_env("LEADERBOARD_INTERVAL_SECS", 15 * 60)?;
+        if leaderboard_interval_secs == 0 {
+            return Err(anyhow!("LEADERBOARD_INTERVAL_SECS must be greater than 0"))
+        }
+
+        let max_playlists_per_user = parse_env("MAX_PLAYLISTS_PER_USER", 100)?;
+        if max_playlists_per_user < 0 {
+            return Err(anyhow!("MAX_PLAYLISTS_PER_USER must not be negative"))
+        }
+
+        let max_body_bytes = parse_env("MAX_BODY_BYTES", 256 * 1024)?;
+        if max_body_bytes == 0 {
+            return Err(anyhow!("MAX_BODY_BYTES must be greater than 0"))
+        }
+
+        let max_vote_subscribers = parse_env("MAX_VOTE_SUBSCRIBERS", 1000)?;
+
+        let allowed_origins = parse_list_env("ALLOWED_ORIGINS");
+        if let Some(origin) = allowed_origins.iter().find(|v| HeaderValue::from_str(v).is_err()) {
+            return Err(anyhow!("ALLOWED_ORIGINS contains an invalid origin: {:?}", origin))
+        }
+
+        let allowed_link_hosts: Vec<String> = parse_list_env_or(
+            "ALLOWED_LINK_HOSTS",
+            &["youtube.com", "youtu.be", "vimeo.com"],
+        )
+            .into_iter()
+            .map(|v| v.trim_end_matches('.').to_lowercase())
+            .collect();
+        if let Some(host) = allowed_link_hosts.iter().find(|v| v.is_empty() || v.contains('/')) {
+            return Err(anyhow!("ALLOWED_LINK_HOSTS contains an invalid host: {:?}", host))
+        }
+
+        let check_banner_urls = parse_env("CHECK_BANNER_URLS", false)?;
+
+        let rate_limit_per_min = parse_env("RATE_LIMIT_PER_MIN", 120)?;
+        if rate_limit_per_min == 0 {
+            return Err(anyhow!("RATE_LIMIT_PER_MIN must be greater than 0"))
+        }
+
+        let webhook_url = std::env::var("WEBHOOK_URL")
+            .ok()
+            .filter(|v| !v.trim().is_empty());
+        if let Some(url) = webhook_url.as_deref() {
+            let valid = Url::parse(url)
+                .map(|v| matches!(v.scheme(), "http" | "https"))
+                .unwrap_or(false);
+            if !valid {
+                return Err(anyhow!("WEBHOOK_URL must be an http or https url"))
+            }
+        }
+
+        let webhook_events = match parse_list_env("WEBHOOK_EVENTS") {
+            events if events.is_empty() => EventType::ALL.to_vec(),
+            events => events.iter()
+                .map(|v| v.parse::<EventType>()
+                    .map_err(|_| anyhow!("WEBHOOK_EVENTS contains an unknown event: {:?}", v)))
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        };
+
+        Ok(Self {
+            vote_cooldown_secs,
+            vote_credit_cost,
+            daily_credit_claim,
+            credit_regen_interval_secs,
+            credit_regen_cap,
+            leaderboard_interval_secs,
+            max_playlists_per_user,
+            max_body_bytes,
+            max_vote_subscribers,
+            allowed_origins,
+            allowed_link_hosts,
+            check_banner_urls,
+            rate_limit_per_min,
+            webhook_url,
+            webhook_events,
+        })
+    }
+}
+
+
+fn parse_env<T>(key: &str, default: T) -> anyhow::Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match std::env::var(key) {
+        Err(_) => Ok(default),
+        Ok(value) => value.parse::<T>()
+            .map_err(|e| anyhow!("invalid value for {}: {}", key, e)),
+    }
+}
+
+/// Reads a comma separated list, ignoring any empty items.
+fn parse_list_env(key: &str) -> Vec<String> {
+    std::env::var(key)
+        .map(|value| {
+            value.split(',')
+                .map(|v| v.trim())
+                .filter(|v| !v.is_empty())
+                .map(|v| v.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Reads a comma separated list like `parse_list_env`, falling back to the
+/// default if the variable is unset.
+fn parse_list_env_or(key: &str, default: &[&str]) -> Vec<String> {
+    if std::env::var_os(key).is_none() {
+        return default.iter().map(|v| v.to_string()).collect()
+    }
+
+    parse_list_env(key)
+}
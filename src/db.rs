@@ -1,11 +1,49 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Formatter};
 use std::ops::Deref;
 use std::sync::Arc;
+use std::time::Instant;
 
-use scylla::{QueryResult, SessionBuilder};
-use scylla::frame::value::ValueList;
+use bytes::Bytes;
+use futures_util::StreamExt;
+use scylla::{IntoTypedRows, QueryResult, SessionBuilder};
+use scylla::batch::{Batch, BatchType};
+use scylla::frame::value::{BatchValues, SerializedValues, ValueList};
 use scylla::prepared_statement::PreparedStatement;
 use concread::arcache::{ARCache, ARCacheBuilder};
+use uuid::Uuid;
+
+use crate::metrics;
+
+
+/// The keyspace every table is created in.
+const KEYSPACE: &str = "spooderfy";
+
+/// Columns added to tables after they were first created, as
+/// `(table, column, type)`.
+///
+/// `CREATE TABLE IF NOT EXISTS` leaves tables which already exist as they
+/// are, so these are added to them by `add_missing_columns` instead. Any
+/// column added to an existing table in `tables.cql` must also be listed
+/// here, otherwise statements using it fail to prepare on clusters created
+/// by an older version.
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("users", "last_claim", "timestamp"),
+    ("users", "age_verified", "boolean"),
+    ("playlists", "ordered_items", "list<uuid>"),
+    ("playlists", "tags", "list<text>"),
+    ("playlists", "created_at", "timestamp"),
+    ("playlists", "updated_at", "timestamp"),
+    ("playlists", "version", "int"),
+    ("playlists", "deleted_at", "timestamp"),
+    ("playlists", "share_token", "text"),
+    ("playlists", "share_expires_at", "timestamp"),
+    ("playlist_votes", "voted_on", "timestamp"),
+    ("playlist_entries", "created_at", "timestamp"),
+    ("playlist_entries", "updated_at", "timestamp"),
+    ("playlist_entries", "deleted_at", "timestamp"),
+    ("playlist_entries_votes", "voted_on", "timestamp"),
+];
 
 #[derive(Clone)]
 pub struct Session(Arc<scylla::Session>, Arc<ARCache<String, PreppedStmt>>);
@@ -22,42 +60,125 @@ impl From<scylla::Session> for Session {
 }
 
 impl Session {
-    #[instrument(skip(self, query), level = "trace")]
+    #[instrument(skip(self, query), fields(statement = %compact(query)), level = "debug")]
     pub async fn query(
         &self,
         query: &str,
         values: impl ValueList + Debug,
     ) -> anyhow::Result<QueryResult> {
         trace!("executing query {}", query);
+        let start = Instant::now();
         let result = self.0.query(query, values).await.map_err(anyhow::Error::from);
+        let elapsed = start.elapsed();
+        metrics::record_db_query("query", elapsed);
 
-        if let Err(ref e) = result {
-            error!("failed to execute query: {} due to error: {}", query, e);
+        match result {
+            Ok(_) => debug!(elapsed_ms = elapsed.as_millis() as u64, "executed query"),
+            Err(ref e) => error!(
+                statement = %compact(query),
+                elapsed_ms = elapsed.as_millis() as u64,
+                error = %e,
+                "failed to execute query",
+            ),
         }
 
         result
     }
 
-    #[instrument(skip(self, query), level = "trace")]
+    #[instrument(skip(self, query), fields(statement = %compact(query)), level = "debug")]
     pub async fn query_prepared(
         &self,
         query: &str,
         values: impl ValueList + Debug,
     ) -> anyhow::Result<QueryResult> {
+        self.query_prepared_paged(query, values, None, None).await
+    }
+
+    /// Executes a prepared statement returning at most `page_size` rows,
+    /// resuming from the given paging state if any.
+    ///
+    /// If no page size is given the statement is executed unpaged.
+    #[instrument(skip(self, query, paging_state), fields(statement = %compact(query)), level = "debug")]
+    pub async fn query_prepared_paged(
+        &self,
+        query: &str,
+        values: impl ValueList + Debug,
+        page_size: Option<i32>,
+        paging_state: Option<Bytes>,
+    ) -> anyhow::Result<QueryResult> {
+        let mut stmt = self.get_or_prepare(query).await?;
+        if let Some(page_size) = page_size {
+            stmt.set_page_size(page_size);
+        }
+
+        let start = Instant::now();
+        let result = self.0
+            .execute_paged(&stmt, values, paging_state)
+            .await
+            .map_err(anyhow::Error::from);
+        let elapsed = start.elapsed();
+        metrics::record_db_query("prepared", elapsed);
+
+        match result {
+            Ok(_) => debug!(elapsed_ms = elapsed.as_millis() as u64, "executed prepared statement"),
+            Err(ref e) => error!(
+                statement = %compact(query),
+                elapsed_ms = elapsed.as_millis() as u64,
+                error = %e,
+                "failed to execute prepared statement",
+            ),
+        }
+
+        result
+    }
+
+    /// Executes the given statements as a single logged batch, so either all
+    /// or none of them are applied.
+    #[instrument(skip(self, queries, values), fields(statements = queries.len()), level = "debug")]
+    pub async fn batch(
+        &self,
+        queries: &[&str],
+        values: impl BatchValues,
+    ) -> anyhow::Result<()> {
+        let mut batch = Batch::new(BatchType::Logged);
+        for query in queries {
+            batch.append_statement(self.get_or_prepare(query).await?);
+        }
+
+        let start = Instant::now();
+        let result = self.0
+            .batch(&batch, values)
+            .await
+            .map_err(anyhow::Error::from);
+        let elapsed = start.elapsed();
+        metrics::record_db_query("batch", elapsed);
+
+        match result {
+            Ok(_) => debug!(elapsed_ms = elapsed.as_millis() as u64, "executed batch"),
+            Err(ref e) => {
+                let statements: Vec<String> = queries.iter().map(|v| compact(v)).collect();
+                error!(
+                    statements = ?statements,
+                    elapsed_ms = elapsed.as_millis() as u64,
+                    error = %e,
+                    "failed to execute batch",
+                )
+            },
+        }
+
+        result.map(|_| ())
+    }
+
+    /// Gets the prepared statement for the query, preparing it on first use.
+    ///
+    /// Statements are cached by their query string so each one is only parsed
+    /// by the server once, after which executing it only sends the values.
+    async fn get_or_prepare(&self, query: &str) -> anyhow::Result<PreparedStatement> {
         {
             let mut reader = self.1.read();
             if let Some(prep) = reader.get(query) {
                 trace!("using cached prepared statement: {}", prep.get_statement());
-                let result = self.0
-                    .execute(prep, values)
-                    .await
-                    .map_err(anyhow::Error::from);
-
-                if let Err(ref e) = result {
-                    error!("failed to execute prepared statement: {} due to error: {}", prep.get_statement(), e);
-                }
-
-                return result
+                return Ok(prep.0.clone())
             };
         }
 
@@ -76,24 +197,38 @@ impl Session {
 
         let stmt = stmt?;
 
-        let result = self.0
-            .execute(&stmt, values)
-            .await
-            .map_err(anyhow::Error::from);
-
-        if let Err(e) = result {
-            error!("failed to execute prepared statement: {} due to error: {}", stmt.get_statement(), e);
-            return Err(e)
-        }
-
         let mut writer = self.1.write();
-        writer.insert(query.to_string(), PreppedStmt::from(stmt));
+        writer.insert(query.to_string(), PreppedStmt::from(stmt.clone()));
         writer.commit();
 
-        result
+        Ok(stmt)
     }
 }
 
+/// Collapses the whitespace in a query so it fits on a single log line.
+fn compact(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Checks if a lightweight transaction (a statement with an `IF` condition)
+/// was applied.
+pub fn was_applied(result: &QueryResult) -> bool {
+    result.rows
+        .as_ref()
+        .and_then(|rows| rows.first())
+        .and_then(|row| row.columns.first())
+        .and_then(|col| col.as_ref())
+        .and_then(|col| col.as_boolean())
+        .unwrap_or(false)
+}
+
+/// Serializes the values up front, so statements which take different
+/// values can share a batch.
+pub fn serialize_values(values: impl ValueList) -> anyhow::Result<SerializedValues> {
+    Ok(values.serialized()?.into_owned())
+}
+
+
 #[derive(Clone)]
 struct PreppedStmt(PreparedStatement);
 
@@ -124,10 +259,13 @@ pub async fn connect(node: &str) -> anyhow::Result<Session> {
         .build()
         .await?;
 
-    let _ = session.query("CREATE KEYSPACE spooderfy WITH replication = {'class': 'SimpleStrategy', 'replication_factor' : 1};", &[]).await;
-    session.use_keyspace("spooderfy", false).await?;
+    let _ = session.query(format!("CREATE KEYSPACE {} WITH replication = {{'class': 'SimpleStrategy', 'replication_factor' : 1}};", KEYSPACE), &[]).await;
+    session.use_keyspace(KEYSPACE, false).await?;
 
+    add_missing_columns(&session).await?;
     create_tables(&session).await?;
+    backfill_ordered_items(&session).await?;
+    backfill_listed_playlists(&session).await?;
 
     Ok(Session::from(session))
 }
@@ -144,4 +282,161 @@ async fn create_tables(session: &scylla::Session) -> anyhow::Result<()> {
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Adds the `ADDED_COLUMNS` which are missing from tables created by an
+/// older version, this is safe to run any number of times.
+///
+/// This runs before `create_tables` as the indexes and views it creates can
+/// depend on the new columns. Views selecting `*` pick up the new columns
+/// from their table. Tables which don't exist yet are skipped, they are
+/// created with every column.
+async fn add_missing_columns(session: &scylla::Session) -> anyhow::Result<()> {
+    let mut existing: HashMap<&str, HashSet<String>> = HashMap::new();
+
+    for (table, column, kind) in ADDED_COLUMNS {
+        if !existing.contains_key(table) {
+            existing.insert(table, get_columns(session, table).await?);
+        }
+
+        let columns = &existing[table];
+        if columns.is_empty() || columns.contains(*column) {
+            continue
+        }
+
+        info!("adding missing column {}.{}", table, column);
+        session.query(format!("ALTER TABLE {} ADD {} {};", table, column, kind), &[]).await?;
+    }
+
+    Ok(())
+}
+
+/// Copies the items of playlists saved by an older version into
+/// `ordered_items`, this is safe to run any number of times.
+///
+/// Items used to be stored in an `items` set, which can't keep the order
+/// they're given in and can't be changed into a list in place, so items are
+/// read from and written to the `ordered_items` list instead. Sets are sorted
+/// by id, so copied playlists keep the order they were always returned in.
+///
+/// Only playlists without any `ordered_items` are copied, and only if they're
+/// still empty when written, so changes saved since are never overwritten.
+/// Nothing is copied if the old `items` column doesn't exist. Older versions
+/// still write to `items`, so they must be stopped before this runs. Once it
+/// has, the old column can be dropped with `ALTER TABLE playlists DROP items;`.
+async fn backfill_ordered_items(session: &scylla::Session) -> anyhow::Result<()> {
+    if !get_columns(session, "playlists").await?.contains("items") {
+        return Ok(())
+    }
+
+    let mut rows = session
+        .query_iter("SELECT id, owner_id, items, ordered_items FROM playlists;", &[])
+        .await?
+        .into_typed::<(Uuid, i64, Option<Vec<Uuid>>, Option<Vec<Uuid>>)>();
+
+    let mut copied = 0;
+    while let Some(row) = rows.next().await {
+        let (id, owner_id, items, ordered_items) = row?;
+        let items = match (items, ordered_items) {
+            (Some(items), None) => items,
+            _ => continue,
+        };
+
+        session.query(
+            "UPDATE playlists SET ordered_items = ? WHERE id = ? AND owner_id = ? IF ordered_items = null;",
+            (items, id, owner_id)
+        ).await?;
+        copied += 1;
+    }
+
+    if copied > 0 {
+        info!("copied the items of {} playlists into ordered_items", copied);
+    }
+
+    Ok(())
+}
+
+/// Adds the public playlists saved by an older version to
+/// `listed_playlists`, this is safe to run any number of times.
+///
+/// Playlists saved before `listed_playlists` existed aren't in any of its
+/// buckets. That table is only filled by this if it is empty, afterwards it
+/// is kept up to date as playlists are listed and unlisted. Older versions
+/// don't update it, so they must be stopped before this runs.
+async fn backfill_listed_playlists(session: &scylla::Session) -> anyhow::Result<()> {
+    let result = session.query("SELECT playlist_id FROM listed_playlists LIMIT 1;", &[]).await?;
+    if !result.rows.unwrap_or_default().is_empty() {
+        return Ok(())
+    }
+
+    let mut rows = session
+        .query_iter("SELECT id, is_public, deleted_at FROM playlists;", &[])
+        .await?
+        .into_typed::<(Uuid, Option<bool>, Option<chrono::Duration>)>();
+
+    let mut listed = 0;
+    while let Some(row) = rows.next().await {
+        let (id, is_public, deleted_at) = row?;
+        if !is_public.unwrap_or_default() || deleted_at.is_some() {
+            continue
+        }
+
+        session.query(
+            "INSERT INTO listed_playlists (bucket, playlist_id) VALUES (?, ?);",
+            (crate::playlists::listed_bucket(id), id)
+        ).await?;
+        listed += 1;
+    }
+
+    if listed > 0 {
+        info!("added {} public playlists to listed_playlists", listed);
+    }
+
+    Ok(())
+}
+
+/// Gets the names of the table's columns, this is empty if the table
+/// doesn't exist.
+async fn get_columns(session: &scylla::Session, table: &str) -> anyhow::Result<HashSet<String>> {
+    let result = session.query(
+        "SELECT column_name FROM system_schema.columns WHERE keyspace_name = ? AND table_name = ?;",
+        (KEYSPACE, table)
+    ).await?;
+
+    let columns = result.rows
+        .unwrap_or_default()
+        .into_typed::<(String,)>()
+        .filter_map(|v| v.ok())
+        .map(|v| v.0)
+        .collect();
+
+    Ok(columns)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gets the body of the table's `CREATE TABLE` statement.
+    fn table_definition(table: &str) -> &'static str {
+        include_str!("./scripts/tables.cql")
+            .split("--")
+            .find(|v| v.trim().starts_with(&format!("CREATE TABLE IF NOT EXISTS {} (", table)))
+            .unwrap_or_else(|| panic!("{} is not created in tables.cql", table))
+    }
+
+    #[test]
+    fn added_columns_match_their_tables() {
+        for (table, column, kind) in ADDED_COLUMNS {
+            let definition = format!("    {} {},", column, kind);
+            assert!(
+                table_definition(table).lines().any(|v| v == definition),
+                "{}.{} is not created as {} in tables.cql",
+                table,
+                column,
+                kind,
+            );
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use poem::error::ResponseError;
+use poem::http::{header, HeaderValue, StatusCode};
+use poem::{IntoResponse, Response};
+use scylla::cql_to_rust::FromRowError;
+use scylla::transport::errors::{DbError, QueryError};
+use serde_json::json;
+
+use crate::utils::{self, FieldError};
+
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+/// How long clients are told to wait before retrying when the database
+/// is unavailable.
+const UNAVAILABLE_RETRY_AFTER_SECS: u64 = 5;
+
+
+/// An error which is rendered as a JSON body of the form
+/// `{"code": "...", "detail": "..."}`.
+///
+/// Errors returned by endpoints are converted into one of these by
+/// `ApiError::from_poem` before being sent so failures have the same shape.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    /// A query failed or returned something unexpected.
+    #[error(transparent)]
+    Database(anyhow::Error),
+
+    /// The database could not be reached or is overloaded, the request can
+    /// be retried later.
+    #[error(transparent)]
+    Unavailable(anyhow::Error),
+
+    /// The requested resource does not exist.
+    #[error("{0}")]
+    NotFound(String),
+
+    /// The request is not authenticated.
+    #[error("You must be authenticated to perform this action.")]
+    Unauthorized,
+
+    /// You lack the permissions required to perform this action.
+    #[error("You lack the permissions required to perform this action.")]
+    Forbidden,
+
+    /// Some part of the request was invalid.
+    #[error("{0}")]
+    Validation(String),
+
+    /// The request body is larger than the given number of bytes.
+    #[error("The request body cannot be larger than {0} bytes.")]
+    PayloadTooLarge(usize),
+
+    /// Request fields failed the validation rules of the schema, these are
+    /// listed in an extra `errors` array of the body.
+    #[error("Some fields of the request are invalid.")]
+    InvalidFields(Vec<FieldError>),
+
+    /// The caller has made too many requests, they can retry after the
+    /// given number of seconds.
+    #[error("Too many requests, try again in {0} seconds.")]
+    TooManyRequests(u64),
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        let unavailable = err.downcast_ref::<QueryError>()
+            .map(is_unavailable)
+            .unwrap_or(false);
+
+        if unavailable {
+            Self::Unavailable(err)
+        } else {
+            Self::Database(err)
+        }
+    }
+}
+
+/// Checks if the error is caused by the database being unreachable or
+/// unable to serve requests, rather than by the query itself.
+fn is_unavailable(err: &QueryError) -> bool {
+    match err {
+        QueryError::IoError(_) | QueryError::TimeoutError => true,
+        QueryError::DbError(err, _) => matches!(
+            err,
+            DbError::Unavailable { .. }
+                | DbError::Overloaded
+                | DbError::IsBootstrapping
+                | DbError::ReadTimeout { .. }
+                | DbError::WriteTimeout { .. }
+        ),
+        _ => false,
+    }
+}
+
+impl From<FromRowError> for ApiError {
+    fn from(err: FromRowError) -> Self {
+        Self::Database(err.into())
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::Validation(_) => StatusCode::BAD_REQUEST,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::InvalidFields(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+}
+
+impl ApiError {
+    /// The machine readable code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Database(_) => "database_error",
+            Self::Unavailable(_) => "database_unavailable",
+            Self::NotFound(_) => "not_found",
+            Self::Unauthorized => "unauthorized",
+            Self::Forbidden => "forbidden",
+            Self::Validation(_) => "validation_error",
+            Self::PayloadTooLarge(_) => "payload_too_large",
+            Self::InvalidFields(_) => "invalid_fields",
+            Self::TooManyRequests(_) => "too_many_requests",
+        }
+    }
+
+    /// Converts an error returned by an endpoint into an `ApiError` based
+    /// on its status, returning the original error if there is no
+    /// equivalent variant.
+    pub fn from_poem(err: poem::Error) -> Result<Self, poem::Error> {
+        let err = match err.downcast::<ApiError>() {
+            Ok(err) => return Ok(err),
+            Err(err) => err,
+        };
+
+        if let Some(errors) = utils::field_errors(&err) {
+            return Ok(Self::InvalidFields(errors))
+        }
+
+        let converted = match err.status() {
+            StatusCode::BAD_REQUEST => Self::Validation(err.to_string()),
+            StatusCode::UNAUTHORIZED => Self::Unauthorized,
+            StatusCode::FORBIDDEN => Self::Forbidden,
+            StatusCode::NOT_FOUND => Self::NotFound(err.to_string()),
+            StatusCode::INTERNAL_SERVER_ERROR => match err.downcast::<QueryError>() {
+                Ok(err) => Self::from(anyhow::Error::from(err)),
+                Err(err) => Self::Database(anyhow::anyhow!("{}", err)),
+            },
+            _ => return Err(err),
+        };
+
+        Ok(converted)
+    }
+
+    pub fn as_response(&self) -> Response {
+        // Database errors are logged rather than returned so internal
+        // details aren't leaked to clients.
+        let detail = match self {
+            Self::Database(_) => "An internal error occurred.".to_string(),
+            Self::Unavailable(_) => "The service is temporarily unavailable, try again later.".to_string(),
+            other => other.to_string(),
+        };
+
+        let mut body = json!({
+            "code": self.code(),
+            "detail": detail,
+        });
+
+        if let Self::InvalidFields(errors) = self {
+            body["errors"] = errors.iter()
+                .map(|v| json!({ "field": v.field, "rule": v.rule }))
+                .collect();
+        }
+
+        let body = poem::web::Json(body);
+
+        let mut resp = (self.status(), body).into_response();
+
+        let retry_after = match self {
+            Self::Unavailable(_) => Some(UNAVAILABLE_RETRY_AFTER_SECS),
+            Self::TooManyRequests(secs) => Some(*secs),
+            _ => None,
+        };
+
+        if let Some(secs) = retry_after {
+            resp.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+
+        resp
+    }
+}
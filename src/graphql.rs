@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Object, Schema};
+use async_graphql_poem::{GraphQLRequest, GraphQLResponse};
+use chrono::{DateTime, Utc};
+use poem::web::{Data, Html};
+use poem::{handler, IntoResponse};
+use uuid::Uuid;
+
+use crate::db::Session;
+use crate::errors::ApiError;
+use crate::playlists::{self, Playlist, PlaylistEntry};
+use crate::users::{playlist_info, user_info};
+use crate::utils::OptionalTokenBearer;
+
+
+/// The path queries are executed on, outside of the OpenAPI documented api.
+pub const GRAPHQL_PATH: &str = "/graphql";
+
+/// The path the GraphQL playground is served on.
+pub const PLAYGROUND_PATH: &str = "/graphql/playground";
+
+
+pub type ApiSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+pub fn build_schema() -> ApiSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription).finish()
+}
+
+
+/// Executes a GraphQL query.
+///
+/// The bearer token is passed to resolvers through the context so only the
+/// fields which need a user fail when it is missing.
+#[handler]
+pub async fn execute(
+    schema: Data<&ApiSchema>,
+    session: Data<&Session>,
+    token: OptionalTokenBearer,
+    req: GraphQLRequest,
+) -> GraphQLResponse {
+    let entries = DataLoader::new(EntryLoader(session.0.clone()), tokio::spawn);
+    let req = req.0
+        .data(session.0.clone())
+        .data(token)
+        .data(entries);
+
+    schema.execute(req).await.into()
+}
+
+/// Serves the GraphQL playground.
+#[handler]
+pub fn playground() -> impl IntoResponse {
+    Html(playground_source(GraphQLPlaygroundConfig::new(GRAPHQL_PATH)))
+}
+
+/// Batches the entry lookups of every playlist in a query into as few
+/// queries as possible.
+struct EntryLoader(Session);
+
+#[async_graphql::async_trait::async_trait]
+impl Loader<Uuid> for EntryLoader {
+    type Value = Arc<PlaylistEntry>;
+    type Error = Arc<ApiError>;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let entries = playlists::get_entries_with_ids(&self.0, keys).await?;
+
+        Ok(entries.into_iter().map(|v| (v.id, Arc::new(v))).collect())
+    }
+}
+
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// The playlist with the given id, NSFW playlists are only returned to
+    /// age verified users.
+    async fn playlist(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Option<PlaylistObject>> {
+        let session = ctx.data::<Session>()?;
+        let allow_nsfw = can_view_nsfw(ctx).await?;
+        let playlist = playlists::get_playlist_by_id(session, id).await?
+            .filter(|v| allow_nsfw | !v.nsfw);
+
+        Ok(playlist.map(PlaylistObject))
+    }
+
+    /// The entries with the given ids in the same order, ids which don't
+    /// exist are skipped as are NSFW entries unless the user is age verified.
+    async fn entries(&self, ctx: &Context<'_>, ids: Vec<Uuid>) -> async_graphql::Result<Vec<EntryObject>> {
+        let session = ctx.data::<Session>()?;
+        let allow_nsfw = can_view_nsfw(ctx).await?;
+        let entries = playlists::get_entries_with_ids(session, &ids).await?;
+
+        Ok(entries.into_iter()
+            .filter(|v| allow_nsfw | !v.nsfw)
+            .map(|v| EntryObject(Arc::new(v)))
+            .collect())
+    }
+
+    /// The playlists owned by the authenticated user.
+    async fn my_playlists(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<PlaylistObject>> {
+        let session = ctx.data::<Session>()?;
+        let token = ctx.data::<OptionalTokenBearer>()?.0
+            .as_deref()
+            .ok_or(ApiError::Unauthorized)?;
+
+        match playlist_info::get_playlists_for_token(session, token, None, None).await? {
+            None => Err(ApiError::Unauthorized.into()),
+            Some(page) => Ok(page.playlists.into_iter().map(PlaylistObject).collect()),
+        }
+    }
+}
+
+async fn can_view_nsfw(ctx: &Context<'_>) -> async_graphql::Result<bool> {
+    let session = ctx.data::<Session>()?;
+    let token = ctx.data::<OptionalTokenBearer>()?.0.as_deref();
+
+    Ok(user_info::can_view_nsfw_for_token(session, token).await?)
+}
+
+
+pub struct PlaylistObject(Playlist);
+
+#[Object(name = "Playlist")]
+impl PlaylistObject {
+    async fn id(&self) -> Uuid {
+        self.0.id
+    }
+
+    /// The owner's Discord id, as a string because it can exceed the range
+    /// of a GraphQL `Int`.
+    async fn owner_id(&self) -> String {
+        self.0.owner_id.to_string()
+    }
+
+    async fn banner(&self) -> Option<&str> {
+        self.0.banner.as_deref()
+    }
+
+    async fn description(&self) -> Option<&str> {
+        self.0.description.as_deref()
+    }
+
+    async fn is_public(&self) -> bool {
+        self.0.is_public
+    }
+
+    async fn items(&self) -> &[Uuid] {
+        &self.0.items
+    }
+
+    async fn item_count(&self) -> usize {
+        self.0.item_count
+    }
+
+    async fn nsfw(&self) -> bool {
+        self.0.nsfw
+    }
+
+    async fn title(&self) -> &str {
+        &self.0.title
+    }
+
+    async fn votes(&self) -> i32 {
+        self.0.votes
+    }
+
+    async fn tags(&self) -> &[String] {
+        &self.0.tags
+    }
+
+    async fn created_at(&self) -> Option<DateTime<Utc>> {
+        self.0.created_at
+    }
+
+    async fn updated_at(&self) -> Option<DateTime<Utc>> {
+        self.0.updated_at
+    }
+
+    async fn version(&self) -> i32 {
+        self.0.version
+    }
+
+    /// The full entries for each of the playlist `items`, in the same order.
+    ///
+    /// Entries are loaded together for every playlist in the query.
+    async fn entries(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<EntryObject>> {
+        let loader = ctx.data::<DataLoader<EntryLoader>>()?;
+        let mut found = loader.load_many(self.0.items.iter().copied()).await?;
+
+        let entries = self.0.items.iter()
+            .filter_map(|id| found.remove(id))
+            .map(EntryObject)
+            .collect();
+
+        Ok(entries)
+    }
+}
+
+
+pub struct EntryObject(Arc<PlaylistEntry>);
+
+#[Object(name = "PlaylistEntry")]
+impl EntryObject {
+    async fn id(&self) -> Uuid {
+        self.0.id
+    }
+
+    /// The owner's Discord id, as a string because it can exceed the range
+    /// of a GraphQL `Int`.
+    async fn owner_id(&self) -> String {
+        self.0.owner_id.to_string()
+    }
+
+    async fn description(&self) -> Option<&str> {
+        self.0.description.as_deref()
+    }
+
+    async fn is_public(&self) -> bool {
+        self.0.is_public
+    }
+
+    async fn nsfw(&self) -> bool {
+        self.0.nsfw
+    }
+
+    async fn ref_link(&self) -> Option<&str> {
+        self.0.ref_link.as_deref()
+    }
+
+    async fn title(&self) -> &str {
+        &self.0.title
+    }
+
+    async fn votes(&self) -> i32 {
+        self.0.votes
+    }
+
+    async fn created_at(&self) -> Option<DateTime<Utc>> {
+        self.0.created_at
+    }
+
+    async fn updated_at(&self) -> Option<DateTime<Utc>> {
+        self.0.updated_at
+    }
+}
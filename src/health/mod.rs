@@ -0,0 +1,50 @@
+use poem::Result;
+use poem::web::Data;
+use poem_openapi::{ApiResponse, OpenApi};
+use poem_openapi::payload::Json;
+use serde_json::{json, Value};
+
+use crate::ApiTags;
+use crate::db::Session;
+
+
+#[derive(ApiResponse)]
+pub enum HealthResponse {
+    /// The service is healthy.
+    #[oai(status = 200)]
+    Ok(Json<Value>),
+
+    /// The service is running but cannot serve requests.
+    #[oai(status = 503)]
+    Unavailable(Json<Value>),
+}
+
+
+pub struct HealthApi;
+
+#[OpenApi]
+impl HealthApi {
+    /// Liveness Check
+    ///
+    /// Returns 200 as long as the process is up and accepting requests.
+    #[oai(path = "/health", method = "get", tag = "ApiTags::Health")]
+    pub async fn health(&self) -> Result<HealthResponse> {
+        Ok(HealthResponse::Ok(Json(json!({ "status": "ok" }))))
+    }
+
+    /// Readiness Check
+    ///
+    /// Returns 200 if the database is responding to queries, otherwise 503.
+    #[oai(path = "/health/ready", method = "get", tag = "ApiTags::Health")]
+    pub async fn ready(&self, session: Data<&Session>) -> Result<HealthResponse> {
+        let resp = match session.query_prepared("SELECT now() FROM system.local;", &[]).await {
+            Ok(_) => HealthResponse::Ok(Json(json!({ "status": "ok" }))),
+            Err(e) => {
+                warn!("readiness check failed: {}", e);
+                HealthResponse::Unavailable(Json(json!({ "status": "unavailable" })))
+            },
+        };
+
+        Ok(resp)
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
-use reqwest::StatusCode;
+use std::time::Duration;
+use reqwest::{header, StatusCode};
 use anyhow::anyhow;
 use serde::{Serialize, Deserialize};
 use serde_json::Value;
@@ -12,6 +13,10 @@ lazy_static! {
     };
 }
 
+/// How long the `HEAD` request made by `is_reachable_image` waits for a
+/// response.
+const HEAD_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
 
 #[derive(Serialize)]
 struct UploadPayload {
@@ -37,6 +42,28 @@ struct UploadResponse {
 }
 
 
+/// Checks the url responds to a `HEAD` request with a 2xx status and an
+/// `image/*` content type, any request errors count as unreachable.
+pub async fn is_reachable_image(client: &reqwest::Client, image_url: &str) -> bool {
+    let resp = client.head(image_url)
+        .timeout(HEAD_CHECK_TIMEOUT)
+        .send()
+        .await;
+
+    let resp = match resp {
+        Err(_) => return false,
+        Ok(v) => v,
+    };
+
+    let is_image = resp.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("image/"))
+        .unwrap_or(false);
+
+    resp.status().is_success() && is_image
+}
+
 pub async fn fetch_and_upload(image_url: &str) -> anyhow::Result<Option<String>> {
     let client = reqwest::Client::new();
 
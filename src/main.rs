@@ -5,26 +5,42 @@ extern crate tracing;
 extern crate lazy_static;
 
 
+mod audit;
 mod users;
+mod config;
 mod db;
+mod errors;
+mod health;
 mod auth;
 mod notifications;
 mod utils;
 mod rooms;
 mod playlists;
+mod reports;
 mod images;
+mod graphql;
+mod metrics;
+mod rate_limit;
+mod webhooks;
+#[allow(dead_code)]
 mod rtc;
 
 use std::sync::Arc;
 use std::time::Duration;
 use poem::{Endpoint, EndpointExt, IntoResponse, Request, Response, Result, Route, Server};
+use poem::error::NotFoundError;
 use poem::listener::TcpListener;
-use poem::http::Method;
+use poem::http::{header, Method, StatusCode};
 use poem_openapi::{OpenApiService, Tags};
 
 use concread::arcache::{ARCache, ARCacheBuilder};
-use poem::middleware::Cors;
+use poem::middleware::{Compression, Cors};
+use tokio::io::AsyncReadExt;
+use tokio::sync::watch;
 use tokio::time::Instant;
+use tracing::Instrument;
+
+use crate::errors::ApiError;
 
 #[derive(Tags)]
 pub enum ApiTags {
@@ -33,6 +49,9 @@ pub enum ApiTags {
     Notifications,
     Rooms,
     Playlists,
+    Reports,
+    Audit,
+    Health,
     Rtc,
 }
 
@@ -43,12 +62,27 @@ async fn main() -> anyhow::Result<()> {
     }
     tracing_subscriber::fmt::init();
 
+    let config = config::Config::from_env()?;
     let session = db::connect("127.0.0.1:9042").await?;
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let credit_regen = users::credit_regen::spawn(session.clone(), &config, shutdown_rx.clone());
+    let leaderboard = playlists::leaderboard::Leaderboard::default();
+    let leaderboard_updates = playlists::leaderboard::spawn(
+        session.clone(),
+        &config,
+        leaderboard.clone(),
+        shutdown_rx.clone(),
+    );
+    let client = reqwest::Client::new();
+    let (webhooks, webhook_deliveries) = webhooks::spawn(client.clone(), &config, shutdown_rx.clone());
+    let rate_limiter = rate_limit::RateLimiter::new(config.rate_limit_per_min);
+    let rate_limit_cleanup = rate_limit::spawn_cleanup(rate_limiter.clone(), shutdown_rx);
     let cache: ARCache<String, String> = ARCacheBuilder::new()
         .set_size(1024, 10)
         .build()
         .unwrap();
 
+    let vote_updates = playlists::live::VoteUpdates::new(config.max_vote_subscribers);
     let api_service = OpenApiService::new(
         (
             users::UsersApi,
@@ -56,6 +90,9 @@ async fn main() -> anyhow::Result<()> {
             notifications::NotificationsApi,
             rooms::RoomsApi,
             playlists::PlaylistsApi,
+            reports::ReportsApi,
+            audit::AuditApi,
+            health::HealthApi,
         ),
         "Spooderfy API",
         "1.0.0"
@@ -66,18 +103,26 @@ async fn main() -> anyhow::Result<()> {
     let ui = api_service.redoc();
     let spec = api_service.spec();
 
+    // Metrics are left uncompressed as not every scraper understands brotli.
     let app = Route::new()
-        .nest("/api/v0", api_service)
-        .nest("/ui", ui)
-        .at("/spec", poem::endpoint::make_sync(move |_| spec.clone()))
-        .with(
-            Cors::new()
-                .allow_origins(["http://127.0.0.1:3000", "http://localhost:3000"])
-                .allow_methods([Method::GET, Method::POST, Method::DELETE, Method::PUT, Method::OPTIONS])
-                .allow_credentials(true)
-        )
+        .nest("/api/v0", api_service.with(Compression::new()))
+        .nest("/ui", ui.with(Compression::new()))
+        .at("/spec", poem::endpoint::make_sync(move |_| spec.clone()).with(Compression::new()))
+        .at(graphql::GRAPHQL_PATH, poem::post(graphql::execute).with(Compression::new()))
+        .at(graphql::PLAYGROUND_PATH, poem::get(graphql::playground))
+        .at(playlists::feed::FEED_PATH, playlists::feed::render.with(Compression::new()))
+        .at(playlists::live::VOTE_STREAM_PATH, poem::get(playlists::live::stream_votes))
+        .at(metrics::METRICS_PATH, poem::endpoint::make_sync(|_| metrics::render()));
+
+    let app = with_middleware(app, &config, rate_limiter)
         .around(log)
         .data(session)
+        .data(config)
+        .data(client)
+        .data(webhooks)
+        .data(leaderboard)
+        .data(vote_updates)
+        .data(graphql::build_schema())
         .data(Arc::new(cache));
 
     Server::new(TcpListener::bind("127.0.0.1:8000"))
@@ -85,55 +130,273 @@ async fn main() -> anyhow::Result<()> {
             app,
             async move {
                 let _ = tokio::signal::ctrl_c().await;
+                let _ = shutdown_tx.send(true);
             },
             Some(Duration::from_secs(2)),
         )
         .await?;
 
+    credit_regen.await?;
+    leaderboard_updates.await?;
+    webhook_deliveries.await?;
+    rate_limit_cleanup.await?;
+
     Ok(())
 }
 
+/// Wraps the routes in the middleware every request goes through.
+///
+/// Errors are rendered inside of `Cors`, which would otherwise turn them into
+/// plain text responses for cross-origin requests, so anything which can
+/// reject a request must sit inside of it too.
+fn with_middleware<E: Endpoint + 'static>(
+    routes: E,
+    config: &config::Config,
+    rate_limiter: rate_limit::RateLimiter,
+) -> impl Endpoint<Output = Response> {
+    let max_body_bytes = config.max_body_bytes;
+
+    routes
+        .around(move |ep, req| limit_body(ep, req, max_body_bytes))
+        .around(move |ep, req| rate_limit::limit(ep, req, rate_limiter.clone()))
+        .around(render_errors)
+        .with(
+            // Poem allows any origin when none are given, the fallback makes
+            // sure an empty list denies everything instead.
+            Cors::new()
+                .allow_origins(config.allowed_origins.clone())
+                .allow_origins_fn(|_| false)
+                .allow_methods([Method::GET, Method::POST, Method::DELETE, Method::PUT, Method::PATCH, Method::OPTIONS])
+                .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE, header::IF_NONE_MATCH])
+                .expose_headers([header::ETAG, header::RETRY_AFTER])
+                .allow_credentials(true)
+        )
+}
+
+/// Marks the response to a request which didn't match any route, so `log`
+/// can record it without its path.
+#[derive(Clone, Copy)]
+struct Unmatched;
+
+/// Renders errors returned by endpoints as the JSON body of their `ApiError`.
+async fn render_errors<E: Endpoint>(next: E, req: Request) -> Result<Response> {
+    match next.call(req).await {
+        Ok(r) => Ok(r.into_response()),
+        Err(e) => Ok(error_response(e)),
+    }
+}
+
+fn error_response(e: poem::Error) -> Response {
+    if e.status().as_u16() >= 500 {
+        error!("{}", &e);
+    }
+
+    let matched = !e.is::<NotFoundError>();
+
+    let mut resp = match ApiError::from_poem(e) {
+        Ok(err) => err.as_response(),
+        Err(e) => e.as_response(),
+    };
+
+    if !matched {
+        resp.extensions_mut().insert(Unmatched);
+    }
+
+    resp
+}
+
+/// Rejects requests with a body larger than `max_bytes` before they reach an
+/// endpoint.
+///
+/// Bodies without a `Content-Length` are read up to the limit first so
+/// chunked uploads can't get around it.
+async fn limit_body<E: Endpoint>(next: E, mut req: Request, max_bytes: usize) -> Result<E::Output> {
+    let content_length = req.headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+
+    match content_length {
+        Some(length) if length > max_bytes => return Err(ApiError::PayloadTooLarge(max_bytes).into()),
+        Some(_) => {},
+        None => {
+            let mut body = Vec::new();
+            req.take_body()
+                .into_async_read()
+                .take(max_bytes as u64 + 1)
+                .read_to_end(&mut body)
+                .await
+                .map_err(|e| poem::Error::new(e, StatusCode::BAD_REQUEST))?;
+
+            if body.len() > max_bytes {
+                return Err(ApiError::PayloadTooLarge(max_bytes).into())
+            }
+
+            req.set_body(body);
+        },
+    }
+
+    next.call(req).await
+}
+
 async fn log<E: Endpoint>(next: E, req: Request) -> Result<Response> {
     let method = req.method().clone();
     let path = req.uri().clone();
 
+    // The user id is recorded by `get_user_id_from_token` once resolved.
+    let span = info_span!(
+        "request",
+        method = method.as_str(),
+        route = path.path(),
+        user_id = tracing::field::Empty,
+    );
+
     let start = Instant::now();
-    let res = next.call(req).await;
+    let res = next.call(req).instrument(span.clone()).await;
     let elapsed = start.elapsed();
+    let _entered = span.enter();
 
-    match res {
-        Ok(r) => {
-            let resp = r.into_response();
+    // Most errors are already rendered by `render_errors`, only those from
+    // the middleware outside of it, such as `Cors` rejections, reach here.
+    let resp = match res {
+        Ok(r) => r.into_response(),
+        Err(e) => error_response(e),
+    };
 
-            info!(
-                "{} -> {} {} [ {:?} ] - {:?}",
-                method.as_str(),
-                resp.status().as_u16(),
-                resp.status().canonical_reason().unwrap_or(""),
-                elapsed,
-                path.path(),
-            );
+    info!(
+        "{} -> {} {} [ {:?} ] - {:?}",
+        method.as_str(),
+        resp.status().as_u16(),
+        resp.status().canonical_reason().unwrap_or(""),
+        elapsed,
+        path.path(),
+    );
 
-            Ok(resp)
-        },
-        Err(e) => {
+    let matched = resp.extensions().get::<Unmatched>().is_none();
+    metrics::record_request(method.as_str(), path.path(), matched, resp.status(), elapsed);
 
-            let resp = e.as_response();
+    Ok(resp)
+}
 
-            if resp.status().as_u16() >= 500 {
-                error!("{}", &e);
-            }
 
-            info!(
-                "{} -> {} {} [ {:?} ] - {:?}",
-                method.as_str(),
-                resp.status().as_u16(),
-                resp.status().canonical_reason().unwrap_or(""),
-                elapsed,
-                path.path(),
-            );
-
-            Err(e)
-        }
+#[cfg(test)]
+mod tests {
+    use poem::handler;
+    use serde_json::Value;
+
+    use super::*;
+
+    const ORIGIN: &str = "https://spooderfy.com";
+    const MAX_BODY_BYTES: usize = 16;
+
+    #[handler]
+    fn missing() -> Result<()> {
+        Err(ApiError::NotFound("Playlist does not exist.".to_string()).into())
+    }
+
+    #[handler]
+    fn echo(body: Vec<u8>) -> Vec<u8> {
+        body
+    }
+
+    #[handler]
+    fn unavailable() -> Result<()> {
+        Err(ApiError::Unavailable(anyhow::anyhow!("no connections")).into())
+    }
+
+    fn app() -> impl Endpoint<Output = Response> {
+        let mut config = config::Config::from_env().unwrap();
+        config.allowed_origins = vec![ORIGIN.to_string()];
+        config.max_body_bytes = MAX_BODY_BYTES;
+
+        let routes = Route::new()
+            .at("/echo", poem::post(echo))
+            .at("/missing", poem::get(missing))
+            .at("/unavailable", poem::get(unavailable));
+
+        let rate_limiter = rate_limit::RateLimiter::new(config.rate_limit_per_min);
+        with_middleware(routes, &config, rate_limiter)
+    }
+
+    fn cross_origin(path: &str) -> Request {
+        Request::builder()
+            .uri_str(path)
+            .header(header::ORIGIN, ORIGIN)
+            .finish()
+    }
+
+    #[tokio::test]
+    async fn cross_origin_errors_are_json() {
+        let resp = app().call(cross_origin("/missing")).await.unwrap();
+
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], ORIGIN);
+
+        let body: Value = resp.into_body().into_json().await.unwrap();
+        assert_eq!(body["code"], "not_found");
+        assert_eq!(body["detail"], "Playlist does not exist.");
+    }
+
+    #[tokio::test]
+    async fn cross_origin_errors_keep_their_headers() {
+        let resp = app().call(cross_origin("/unavailable")).await.unwrap();
+
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(resp.headers().contains_key(header::RETRY_AFTER));
+
+        let body: Value = resp.into_body().into_json().await.unwrap();
+        assert_eq!(body["code"], "database_unavailable");
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_are_rejected() {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri_str("/echo")
+            .header(header::ORIGIN, ORIGIN)
+            .header(header::CONTENT_LENGTH, MAX_BODY_BYTES + 1)
+            .body(vec![0u8; MAX_BODY_BYTES + 1]);
+        let resp = app().call(req).await.unwrap();
+
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(resp.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], ORIGIN);
+
+        let body: Value = resp.into_body().into_json().await.unwrap();
+        assert_eq!(body["code"], "payload_too_large");
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_without_a_length_are_rejected() {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri_str("/echo")
+            .header(header::ORIGIN, ORIGIN)
+            .body(vec![0u8; MAX_BODY_BYTES + 1]);
+        let resp = app().call(req).await.unwrap();
+
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn bodies_within_the_limit_are_passed_on() {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri_str("/echo")
+            .body(vec![1u8; MAX_BODY_BYTES]);
+        let resp = app().call(req).await.unwrap();
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.into_body().into_vec().await.unwrap(), vec![1u8; MAX_BODY_BYTES]);
+    }
+
+    #[tokio::test]
+    async fn unmatched_routes_are_marked() {
+        let resp = app().call(cross_origin("/nothing-here")).await.unwrap();
+
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert!(resp.extensions().get::<Unmatched>().is_some());
+
+        let body: Value = resp.into_body().into_json().await.unwrap();
+        assert_eq!(body["code"], "not_found");
     }
 }
@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use poem::http::StatusCode;
+use prometheus::{
+    register_histogram_vec,
+    register_int_counter,
+    register_int_counter_vec,
+    Encoder,
+    HistogramVec,
+    IntCounter,
+    IntCounterVec,
+    TextEncoder,
+};
+use uuid::Uuid;
+
+/// The path the metrics are exported on, requests to it are not recorded.
+pub const METRICS_PATH: &str = "/metrics";
+
+/// The route label used for requests which didn't match any route, so
+/// scanners probing random paths can't create new series.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+lazy_static! {
+    static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "http_requests_total",
+        "The number of requests handled by route and status.",
+        &["method", "route", "status"]
+    ).unwrap();
+
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "http_request_duration_seconds",
+        "How long requests took to handle by route.",
+        &["method", "route"]
+    ).unwrap();
+
+    static ref DB_QUERY_DURATION: HistogramVec = register_histogram_vec!(
+        "db_query_duration_seconds",
+        "How long database round trips took by kind of statement.",
+        &["kind"]
+    ).unwrap();
+
+    static ref VOTES: IntCounterVec = register_int_counter_vec!(
+        "votes_total",
+        "The number of upvotes cast by the kind of content voted on.",
+        &["target"]
+    ).unwrap();
+
+    static ref CREDITS_SPENT: IntCounter = register_int_counter!(
+        "vote_credits_spent_total",
+        "The number of vote credits deducted from users."
+    ).unwrap();
+
+    static ref TOKEN_CACHE_LOOKUPS: IntCounterVec = register_int_counter_vec!(
+        "token_cache_lookups_total",
+        "The number of access token lookups by whether they were cached.",
+        &["result"]
+    ).unwrap();
+}
+
+
+/// Records a handled request.
+///
+/// Path segments which are ids are replaced with `{id}` so the number of
+/// series stays bounded.
+pub fn record_request(method: &str, path: &str, matched: bool, status: StatusCode, elapsed: Duration) {
+    if path == METRICS_PATH {
+        return
+    }
+
+    let route = if matched {
+        route_template(path)
+    } else {
+        UNMATCHED_ROUTE.to_string()
+    };
+
+    HTTP_REQUESTS
+        .with_label_values(&[method, &route, status.as_str()])
+        .inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[method, &route])
+        .observe(elapsed.as_secs_f64());
+}
+
+/// Records how long a database round trip took, `kind` is one of
+/// `query`, `prepared` or `batch`.
+pub fn record_db_query(kind: &str, elapsed: Duration) {
+    DB_QUERY_DURATION
+        .with_label_values(&[kind])
+        .observe(elapsed.as_secs_f64());
+}
+
+/// Records an upvote on the given kind of content.
+pub fn record_vote(target: &str) {
+    VOTES.with_label_values(&[target]).inc();
+}
+
+/// Records credits being deducted from a user.
+pub fn record_credits_spent(amount: i32) {
+    CREDITS_SPENT.inc_by(amount.max(0) as u64);
+}
+
+/// Records an access token lookup which was either answered by the token
+/// cache or had to query the database.
+pub fn record_token_cache_lookup(hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    TOKEN_CACHE_LOOKUPS.with_label_values(&[result]).inc();
+}
+
+/// Renders all metrics in the Prometheus text format.
+pub fn render() -> String {
+    let mut buffer = vec![];
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        error!("failed to encode metrics: {}", e);
+    }
+
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+fn route_template(path: &str) -> String {
+    path.split('/')
+        .map(|segment| {
+            if segment.parse::<Uuid>().is_ok() || segment.parse::<i64>().is_ok() {
+                "{id}"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -77,7 +77,7 @@ impl NotificationsApi {
             Some(v) => v.map_err(anyhow::Error::from)?.0,
         };
 
-        session.query(
+        session.query_prepared(
             r#"
             INSERT INTO notifications (
                 id,
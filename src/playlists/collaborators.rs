@@ -0,0 +1,46 @@
+use uuid::Uuid;
+
+use crate::db::Session;
+use crate::errors::ApiResult;
+use super::Playlist;
+
+
+/// Adds the user as a collaborator on the playlist.
+pub async fn add_collaborator(sess: &Session, playlist_id: Uuid, user_id: i64) -> ApiResult<()> {
+    sess.query_prepared(
+        "INSERT INTO playlist_collaborators (playlist_id, user_id) VALUES (?, ?);",
+        (playlist_id, user_id)
+    ).await?;
+
+    Ok(())
+}
+
+/// Removes every collaborator of the given playlist.
+pub async fn remove_collaborators(sess: &Session, playlist_id: Uuid) -> ApiResult<()> {
+    sess.query_prepared(
+        "DELETE FROM playlist_collaborators WHERE playlist_id = ?;",
+        (playlist_id,)
+    ).await?;
+
+    Ok(())
+}
+
+/// Checks if the user is listed as a collaborator on the playlist.
+pub async fn is_collaborator(sess: &Session, playlist_id: Uuid, user_id: i64) -> ApiResult<bool> {
+    let result = sess.query_prepared(
+        "SELECT user_id FROM playlist_collaborators WHERE playlist_id = ? AND user_id = ?;",
+        (playlist_id, user_id)
+    ).await?;
+
+    Ok(result.rows.map(|v| !v.is_empty()).unwrap_or(false))
+}
+
+/// Checks if the user can edit the playlist, either as the owner or as one
+/// of its collaborators.
+pub async fn can_edit(sess: &Session, playlist: &Playlist, user_id: i64) -> ApiResult<bool> {
+    if *playlist.owner_id == user_id {
+        return Ok(true)
+    }
+
+    is_collaborator(sess, playlist.id, user_id).await
+}
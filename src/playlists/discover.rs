@@ -0,0 +1,205 @@
+use std::cmp::Reverse;
+use anyhow::anyhow;
+use futures_util::future::try_join_all;
+use poem_openapi::Enum;
+use rand::Rng;
+use rand::seq::SliceRandom;
+use scylla::IntoTypedRows;
+use uuid::Uuid;
+
+use crate::db::Session;
+use crate::errors::ApiResult;
+use super::{playlist, trending, Playlist};
+
+
+/// The number of partitions the listed playlists are spread over in
+/// `listed_playlists`, so reads and writes of the public set don't all land
+/// on the same replicas.
+pub const LISTED_BUCKETS: i32 = 32;
+
+/// The maximum number of public playlists considered when building a
+/// discovery page.
+///
+/// Scylla cannot order the whole public set by votes, so a bounded candidate
+/// set is read from `listed_playlists`, an equal share from each bucket, and
+/// sorted in process.
+///
+/// When filtering by tag the candidates are instead read through the
+/// `playlists_by_tag` index, which covers private playlists too, so those
+/// are filtered out in process as well.
+const MAX_CANDIDATES: i32 = 1000;
+
+
+/// The number of playlists read after the random starting point when
+/// picking a random playlist, so a few NSFW playlists in a row don't cause
+/// a miss.
+const RANDOM_SCAN_SIZE: i32 = 20;
+
+
+#[derive(Enum, Copy, Clone, PartialEq, Eq)]
+#[oai(rename_all = "snake_case")]
+pub enum DiscoverySort {
+    Top,
+    New,
+    Random,
+    TopWeek,
+    TopMonth,
+}
+
+impl DiscoverySort {
+    /// The number of days of votes playlists are ranked by, or `None` if
+    /// they're not ranked by recent votes.
+    fn window_days(self) -> Option<i64> {
+        match self {
+            Self::TopWeek => Some(7),
+            Self::TopMonth => Some(30),
+            _ => None,
+        }
+    }
+}
+
+
+pub async fn discover_playlists(
+    sess: &Session,
+    sort: DiscoverySort,
+    limit: usize,
+    include_nsfw: bool,
+    tag: Option<&str>,
+) -> ApiResult<Vec<Playlist>> {
+    if let Some(days) = sort.window_days() {
+        return trending::top_playlists_in_window(sess, days, limit, include_nsfw, tag).await
+    }
+
+    let candidates = match tag {
+        None => {
+            let ids = get_listed_ids(sess, MAX_CANDIDATES / LISTED_BUCKETS).await?;
+            playlist::get_playlists_with_ids(sess, &ids).await?
+        },
+        Some(tag) => {
+            let result = sess.query_prepared_paged(
+                r#"
+                SELECT id, owner_id, banner, description, is_public, ordered_items, nsfw, title, votes, tags, created_at, updated_at, version, deleted_at
+                FROM playlists WHERE tags CONTAINS ?;
+                "#,
+                (tag,),
+                Some(MAX_CANDIDATES),
+                None,
+            ).await?;
+
+            let rows = result.rows
+                .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+            rows.into_typed::<Playlist>()
+                .filter_map(|v| v.ok())
+                .collect()
+        },
+    };
+
+    let mut candidates: Vec<Playlist> = candidates.into_iter()
+        .filter(|playlist| playlist.is_public & playlist.deleted_at.is_none())
+        .filter(|playlist| include_nsfw | !playlist.nsfw)
+        .collect();
+
+    match sort {
+        DiscoverySort::Top | DiscoverySort::TopWeek | DiscoverySort::TopMonth => {
+            candidates.sort_by_key(|v| Reverse(v.votes))
+        },
+        DiscoverySort::New => candidates.sort_by_key(|v| Reverse(v.created_at)),
+        DiscoverySort::Random => candidates.shuffle(&mut rand::thread_rng()),
+    }
+
+    candidates.truncate(limit);
+
+    Ok(candidates)
+}
+
+
+/// Picks a random public playlist.
+///
+/// Scylla has no `RANDOM()`, so a random bucket of `listed_playlists` is
+/// read from a random v4 uuid, as buckets cluster by id, and the first
+/// matching playlist from there is returned. If there is none after that
+/// point, the first playlist of every bucket is tried in a random order
+/// instead. This reads a few small pages rather than the whole table.
+///
+/// Playlists after large gaps in the id space are slightly more likely to be
+/// picked, which is fine for a "surprise me" feature.
+pub async fn random_playlist(sess: &Session, include_nsfw: bool) -> ApiResult<Option<Playlist>> {
+    let bucket = rand::thread_rng().gen_range(0..LISTED_BUCKETS);
+    let start = Uuid::new_v4();
+
+    let result = sess.query_prepared(
+        "SELECT playlist_id FROM listed_playlists WHERE bucket = ? AND playlist_id >= ? LIMIT ?;",
+        (bucket, start, RANDOM_SCAN_SIZE),
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let ids: Vec<Uuid> = rows.into_typed::<(Uuid,)>()
+        .filter_map(|v| v.ok())
+        .map(|v| v.0)
+        .collect();
+
+    if let Some(playlist) = first_matching(sess, &ids, include_nsfw).await? {
+        return Ok(Some(playlist))
+    }
+
+    let mut ids = get_listed_ids(sess, 1).await?;
+    ids.shuffle(&mut rand::thread_rng());
+
+    first_matching(sess, &ids, include_nsfw).await
+}
+
+async fn first_matching(sess: &Session, ids: &[Uuid], include_nsfw: bool) -> ApiResult<Option<Playlist>> {
+    let playlist = playlist::get_playlists_with_ids(sess, ids).await?
+        .into_iter()
+        .find(|playlist| playlist.is_public & (include_nsfw | !playlist.nsfw));
+
+    Ok(playlist)
+}
+
+
+/// Gets the `listed_playlists` bucket the playlist is kept in.
+pub fn listed_bucket(playlist_id: Uuid) -> i32 {
+    (playlist_id.as_u128() % LISTED_BUCKETS as u128) as i32
+}
+
+/// Adds the playlist to or removes it from `listed_playlists`, this is only
+/// called by `stats::listing_changed`.
+pub(super) async fn set_listed(sess: &Session, playlist_id: Uuid, listed: bool) -> ApiResult<()> {
+    let query = if listed {
+        "INSERT INTO listed_playlists (bucket, playlist_id) VALUES (?, ?);"
+    } else {
+        "DELETE FROM listed_playlists WHERE bucket = ? AND playlist_id = ?;"
+    };
+
+    sess.query_prepared(query, (listed_bucket(playlist_id), playlist_id)).await?;
+
+    Ok(())
+}
+
+/// Gets the ids of up to `per_bucket` listed playlists from every bucket,
+/// the buckets are read concurrently.
+pub(super) async fn get_listed_ids(sess: &Session, per_bucket: i32) -> ApiResult<Vec<Uuid>> {
+    let pages = try_join_all((0..LISTED_BUCKETS).map(|bucket| {
+        sess.query_prepared(
+            "SELECT playlist_id FROM listed_playlists WHERE bucket = ? LIMIT ?;",
+            (bucket, per_bucket),
+        )
+    })).await?;
+
+    let mut ids = vec![];
+    for result in pages {
+        let rows = result.rows
+            .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+        ids.extend(
+            rows.into_typed::<(Uuid,)>()
+                .filter_map(|v| v.ok())
+                .map(|v| v.0)
+        );
+    }
+
+    Ok(ids)
+}
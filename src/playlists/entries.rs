@@ -1,13 +1,23 @@
+use std::collections::HashMap;
+
 use anyhow::anyhow;
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
-use poem_openapi::Object;
-use scylla::{FromRow, IntoTypedRows};
+use poem_openapi::{Enum, Object};
+use scylla::IntoTypedRows;
+use scylla::cql_to_rust::{FromRow, FromRowError};
+use scylla::frame::response::result::Row;
+use scylla::frame::value::SerializedValues;
+
+use crate::db::{self, Session};
+use crate::errors::ApiResult;
+use crate::utils::{self, JsSafeBigInt};
 
-use crate::db::Session;
-use crate::utils::JsSafeBigInt;
+/// The maximum number of ids passed to a single `IN` query.
+const MAX_IDS_PER_QUERY: usize = 100;
 
 
-#[derive(Object, FromRow)]
+#[derive(Object)]
 pub struct PlaylistEntry {
     pub id: Uuid,
     pub owner_id: JsSafeBigInt,
@@ -17,12 +27,106 @@ pub struct PlaylistEntry {
     pub ref_link: Option<String>,
     pub title: String,
     pub votes: i32,
+
+    /// When the entry was created.
+    ///
+    /// This is missing for entries created before it was recorded.
+    pub created_at: Option<DateTime<Utc>>,
+
+    /// When the entry was last modified.
+    ///
+    /// This is missing for entries created before it was recorded.
+    pub updated_at: Option<DateTime<Utc>>,
+
+    /// If the requesting user has up-voted this entry.
+    ///
+    /// This is only present when the request is authenticated.
+    pub has_voted: Option<bool>,
+
+    /// When the entry was removed, removed entries are kept so they can be
+    /// restored but are otherwise treated as if they don't exist.
+    #[oai(skip)]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+
+#[derive(Enum, Copy, Clone, Debug, PartialEq, Eq)]
+#[oai(rename_all = "snake_case")]
+pub enum SkipReason {
+    NotFound,
+    NotOwned,
+    Referenced,
+}
+
+
+#[derive(Object, Debug)]
+pub struct SkippedEntry {
+    pub id: Uuid,
+    pub reason: SkipReason,
 }
 
 
-pub async fn get_entry_by_id(sess: &Session, id: Uuid) -> anyhow::Result<Option<PlaylistEntry>> {
+/// The outcome of deleting several entries at once.
+#[derive(Object, Debug, Default)]
+pub struct BulkDeleteSummary {
+    pub deleted: Vec<Uuid>,
+
+    /// The entries which were left as they are, along with why.
+    pub skipped: Vec<SkippedEntry>,
+}
+
+type EntryRow = (
+    Uuid,
+    JsSafeBigInt,
+    Option<String>,
+    bool,
+    bool,
+    Option<String>,
+    String,
+    i32,
+    Option<chrono::Duration>,
+    Option<chrono::Duration>,
+    Option<chrono::Duration>,
+);
+
+impl FromRow for PlaylistEntry {
+    fn from_row(row: Row) -> Result<Self, FromRowError> {
+        let v = EntryRow::from_row(row)?;
+
+        Ok(Self {
+            id: v.0,
+            owner_id: v.1,
+            description: v.2,
+            is_public: v.3,
+            nsfw: v.4,
+            ref_link: v.5,
+            title: v.6,
+            votes: v.7,
+            created_at: v.8.map(utils::from_cql_timestamp),
+            updated_at: v.9.map(utils::from_cql_timestamp),
+            has_voted: None,
+            deleted_at: v.10.map(utils::from_cql_timestamp),
+        })
+    }
+}
+
+
+/// Gets the entry with the given id, removed entries are treated as if they
+/// don't exist.
+pub async fn get_entry_by_id(sess: &Session, id: Uuid) -> ApiResult<Option<PlaylistEntry>> {
+    let entry = get_entry_including_deleted(sess, id).await?
+        .filter(|v| v.deleted_at.is_none());
+
+    Ok(entry)
+}
+
+/// Gets the entry with the given id even if it has been removed.
+pub async fn get_entry_including_deleted(sess: &Session, id: Uuid) -> ApiResult<Option<PlaylistEntry>> {
     let result = sess.query_prepared(
-        "SELECT * FROM playlist_entries WHERE id = ?;",
+        r#"
+        SELECT id, owner_id, description, is_public, nsfw, ref_link, title, votes, created_at, updated_at, deleted_at
+        FROM playlist_entries WHERE id = ?;
+        "#,
         (id,)
     ).await?;
 
@@ -38,64 +142,304 @@ pub async fn get_entry_by_id(sess: &Session, id: Uuid) -> anyhow::Result<Option<
     Ok(Some(entry))
 }
 
-pub async fn get_entries_with_ids(sess: &Session, ids: Vec<Uuid>) -> anyhow::Result<Vec<PlaylistEntry>> {
-    let result = sess.query_prepared(
-        "SELECT * FROM playlist_entries WHERE id IN ?;",
-        (ids,)
-    ).await?;
+/// Fetches the entries with the given ids, returning them in the same order
+/// as the ids.
+///
+/// Ids are queried in chunks of `MAX_IDS_PER_QUERY` and ids which no longer
+/// exist are skipped.
+pub async fn get_entries_with_ids(sess: &Session, ids: &[Uuid]) -> ApiResult<Vec<PlaylistEntry>> {
+    let mut found = HashMap::with_capacity(ids.len());
+    for chunk in ids.chunks(MAX_IDS_PER_QUERY) {
+        let result = sess.query_prepared(
+            r#"
+            SELECT id, owner_id, description, is_public, nsfw, ref_link, title, votes, created_at, updated_at, deleted_at
+            FROM playlist_entries WHERE id IN ?;
+            "#,
+            (chunk.to_vec(),)
+        ).await?;
 
-    let rows = result.rows
-        .ok_or_else(|| anyhow!("expected returned rows"))?;
+        let rows = result.rows
+            .ok_or_else(|| anyhow!("expected returned rows"))?;
 
-    let entries = rows.into_typed::<PlaylistEntry>()
-        .filter_map(|v| v.ok())
+        found.extend(
+            rows.into_typed::<PlaylistEntry>()
+                .filter_map(|v| v.ok())
+                .map(|v| (v.id, v))
+        );
+    }
+
+    let entries = ids.iter()
+        .filter_map(|id| found.remove(id))
+        .filter(|v| v.deleted_at.is_none())
         .collect();
 
     Ok(entries)
 }
 
-pub async fn upvote_playlist(sess: &Session, user_id: i64, entry_id: Uuid) -> anyhow::Result<()> {
-    sess.query_prepared(
-        "INSERT INTO playlist_entries_votes (user_id, entry_id) VALUES (?, ?);",
-        (user_id, entry_id.clone()),
-    ).await?;
+/// Inserts a single entry, the values are given by `insert_entry_values`.
+pub const INSERT_ENTRY_QUERY: &str = r#"INSERT INTO playlist_entries (
+    id,
+    owner_id,
+    description,
+    is_public,
+    nsfw,
+    ref_link,
+    title,
+    votes,
+    created_at,
+    updated_at
+) VALUES (?, ?, ?, ?, ?, ?, ?, 0, ?, ?);"#;
+
+/// The values to bind to `INSERT_ENTRY_QUERY` to insert the given entry.
+pub fn insert_entry_values(entry: &PlaylistEntry) -> ApiResult<SerializedValues> {
+    let values = db::serialize_values((
+        entry.id,
+        *entry.owner_id,
+        entry.description.as_ref(),
+        entry.is_public,
+        entry.nsfw,
+        entry.ref_link.as_ref(),
+        entry.title.as_str(),
+        entry.created_at.map(utils::to_cql_timestamp),
+        entry.updated_at.map(utils::to_cql_timestamp),
+    ))?;
+
+    Ok(values)
+}
+
+/// Inserts all of the given entries in a single batch.
+pub async fn insert_entries(sess: &Session, entries: &[PlaylistEntry]) -> ApiResult<()> {
+    let queries = vec![INSERT_ENTRY_QUERY; entries.len()];
+    let values = entries.iter()
+        .map(insert_entry_values)
+        .collect::<ApiResult<Vec<_>>>()?;
+
+    sess.batch(&queries, values).await?;
+
+    Ok(())
+}
+
+/// Writes the mutable fields of the given entry back to the database,
+/// bumping its `updated_at` time.
+pub async fn save_entry(sess: &Session, entry: &mut PlaylistEntry) -> ApiResult<()> {
+    let updated_at = utils::now();
 
     sess.query_prepared(
-        "UPDATE playlist_entries_votes SET votes = votes + 1 WHERE id = ?;",
-        (entry_id,)
+        r#"
+        UPDATE playlist_entries SET
+            description = ?,
+            is_public = ?,
+            nsfw = ?,
+            ref_link = ?,
+            title = ?,
+            updated_at = ?
+        WHERE id = ? AND owner_id = ?;
+        "#,
+        (
+            &entry.description,
+            entry.is_public,
+            entry.nsfw,
+            &entry.ref_link,
+            &entry.title,
+            utils::to_cql_timestamp(updated_at),
+            entry.id,
+            *entry.owner_id,
+        )
     ).await?;
 
+    entry.updated_at = Some(updated_at);
+
     Ok(())
 }
 
-pub async fn has_user_voted(sess: &Session, user_id: i64, entry_id: Uuid) -> anyhow::Result<bool> {
+/// Records the user's vote and increments the vote count, returning the new
+/// count or None if the user has already voted on the entry.
+///
+/// This works the same way as voting on a playlist, see `upvote_playlist`.
+pub async fn upvote_playlist(
+    sess: &Session,
+    user_id: i64,
+    entry: &PlaylistEntry,
+    cooldown_secs: i32,
+) -> ApiResult<Option<i32>> {
+    let result = sess.query_prepared(
+        "INSERT INTO playlist_entries_votes (user_id, entry_id, voted_on) VALUES (?, ?, toTimestamp(now())) IF NOT EXISTS USING TTL ?;",
+        (user_id, entry.id, cooldown_secs)
+    ).await?;
+
+    if !db::was_applied(&result) {
+        return Ok(None)
+    }
+
+    match adjust_entry_votes(sess, entry, 1).await {
+        Ok(votes) => Ok(Some(votes)),
+        Err(e) => {
+            sess.query_prepared(
+                "DELETE FROM playlist_entries_votes WHERE user_id = ? AND entry_id = ?;",
+                (user_id, entry.id),
+            ).await?;
+
+            Err(e)
+        },
+    }
+}
+
+/// Removes the user's vote and decrements the vote count, returning the new
+/// count or None if the user hasn't voted on the entry.
+pub async fn remove_entry_vote(sess: &Session, user_id: i64, entry: &PlaylistEntry) -> ApiResult<Option<i32>> {
     let result = sess.query_prepared(
-        "SELECT true FROM playlist_entries_votes WHERE user_id = ? AND entry_id = ?;",
+        "DELETE FROM playlist_entries_votes WHERE user_id = ? AND entry_id = ? IF EXISTS;",
+        (user_id, entry.id),
+    ).await?;
+
+    if !db::was_applied(&result) {
+        return Ok(None)
+    }
+
+    adjust_entry_votes(sess, entry, -1).await.map(Some)
+}
+
+/// Adds `delta` to the entry's vote count, returning the new count.
+///
+/// The count is only changed if it hasn't been modified since it was read,
+/// retrying otherwise.
+async fn adjust_entry_votes(sess: &Session, entry: &PlaylistEntry, delta: i32) -> ApiResult<i32> {
+    for _ in 0..super::playlist::MAX_VOTE_UPDATE_ATTEMPTS {
+        let result = sess.query_prepared(
+            "SELECT votes FROM playlist_entries WHERE id = ? AND owner_id = ?;",
+            (entry.id, *entry.owner_id)
+        ).await?;
+
+        let rows = result.rows
+            .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+        let current = match rows.into_typed::<(Option<i32>,)>().next() {
+            None => return Err(anyhow!("entry {} no longer exists", entry.id).into()),
+            Some(v) => v?.0,
+        };
+
+        let votes = current.unwrap_or(0) + delta;
+        let result = sess.query_prepared(
+            "UPDATE playlist_entries SET votes = ? WHERE id = ? AND owner_id = ? IF votes = ?;",
+            (votes, entry.id, *entry.owner_id, current)
+        ).await?;
+
+        if db::was_applied(&result) {
+            return Ok(votes)
+        }
+    }
+
+    Err(anyhow!("failed to update entry votes due to concurrent updates").into())
+}
+
+pub async fn has_user_voted(sess: &Session, user_id: i64, entry_id: Uuid) -> ApiResult<bool> {
+    let result = sess.query_prepared(
+        "SELECT user_id FROM playlist_entries_votes WHERE user_id = ? AND entry_id = ?;",
         (user_id, entry_id)
     ).await?;
 
     let rows = result.rows
         .ok_or_else(|| anyhow!("expected returned rows"))?;
 
-    let has_votes = Option::flatten(
-        rows.into_typed::<(bool,)>()
-            .next()
-            .map(|v| v.ok().map(|v| v.0))
-    ).unwrap_or(false);
+    Ok(!rows.is_empty())
+}
 
-    Ok(has_votes)
+/// Gets the number of seconds until the user can vote on the entry again,
+/// or None if the user has not voted on it recently.
+pub async fn get_vote_cooldown(sess: &Session, user_id: i64, entry_id: Uuid) -> ApiResult<Option<u64>> {
+    let result = sess.query_prepared(
+        "SELECT TTL(voted_on) FROM playlist_entries_votes WHERE user_id = ? AND entry_id = ?;",
+        (user_id, entry_id)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let cooldown = match rows.into_typed::<(Option<i32>,)>().next() {
+        None => return Ok(None),
+        Some(v) => v?.0.unwrap_or(0),
+    };
+
+    Ok(Some(cooldown.max(0) as u64))
 }
 
-pub async fn remove_entry(sess: &Session, entry_id: Uuid) -> anyhow::Result<()> {
-    sess.query_prepared(
-        "DELETE FROM playlists WHERE entry_id = ?;",
-        (entry_id,)
+/// Removes the entry by setting its `deleted_at` so it can be restored later.
+///
+/// This is a lightweight transaction on `deleted_at` so it returns false if
+/// the entry does not exist or was already removed.
+pub async fn remove_entry(sess: &Session, entry_id: Uuid) -> ApiResult<bool> {
+    let entry = match get_entry_by_id(sess, entry_id).await? {
+        None => return Ok(false),
+        Some(v) => v,
+    };
+
+    let result = sess.query_prepared(
+        "UPDATE playlist_entries SET deleted_at = ? WHERE id = ? AND owner_id = ? IF deleted_at = null;",
+        (utils::to_cql_timestamp(utils::now()), entry_id, *entry.owner_id)
     ).await?;
 
+    Ok(db::was_applied(&result))
+}
+
+/// Removes several entries in a single batch by setting their `deleted_at`.
+///
+/// Unlike `remove_entry` this doesn't check the entries haven't already
+/// been removed, conditional updates can't be batched across partitions.
+pub async fn remove_entries(sess: &Session, entries: &[PlaylistEntry]) -> ApiResult<()> {
+    if entries.is_empty() {
+        return Ok(())
+    }
+
+    let deleted_at = utils::to_cql_timestamp(utils::now());
+    let queries = vec!["UPDATE playlist_entries SET deleted_at = ? WHERE id = ? AND owner_id = ?;"; entries.len()];
+    let values: Vec<_> = entries.iter()
+        .map(|v| (deleted_at, v.id, *v.owner_id))
+        .collect();
+
+    sess.batch(&queries, values).await?;
+
+    Ok(())
+}
+
+/// Permanently deletes an entry, whether or not it has been removed,
+/// returning the number of playlists it was removed from.
+///
+/// The entry is removed from every playlist containing it first so no
+/// playlist is left referencing an entry which doesn't exist.
+///
+/// Votes are keyed by user so can't be removed by entry, they are left
+/// to expire with their TTL instead.
+pub async fn purge_entry(sess: &Session, entry_id: Uuid) -> ApiResult<usize> {
+    let mut playlists_updated = 0;
+    for playlist_id in super::get_playlists_referencing(sess, entry_id).await? {
+        if super::remove_entry_from_playlist(sess, playlist_id, entry_id).await? {
+            playlists_updated += 1;
+        }
+    }
+
     sess.query_prepared(
-        "DELETE FROM playlist_entries_votes WHERE entry_id = ?;",
+        "DELETE FROM playlist_entries WHERE id = ?;",
         (entry_id,)
     ).await?;
 
-    Ok(())
+    Ok(playlists_updated)
+}
+
+/// Gets the ids of every entry owned by the given user, including removed
+/// entries.
+pub async fn get_entry_ids_for_owner(sess: &Session, owner_id: i64) -> ApiResult<Vec<Uuid>> {
+    let result = sess.query_prepared(
+        "SELECT id FROM playlist_entries_by_owner WHERE owner_id = ?;",
+        (owner_id,)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let ids = rows.into_typed::<(Uuid,)>()
+        .filter_map(|v| v.ok())
+        .map(|v| v.0)
+        .collect();
+
+    Ok(ids)
 }
\ No newline at end of file
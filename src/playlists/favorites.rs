@@ -0,0 +1,61 @@
+use std::cmp::Reverse;
+use anyhow::anyhow;
+use scylla::IntoTypedRows;
+use uuid::Uuid;
+
+use crate::db::Session;
+use crate::errors::ApiResult;
+
+
+/// Adds the playlist to the user's favorites.
+///
+/// This is a lightweight transaction so favoriting a playlist twice keeps
+/// the original `created_at`.
+pub async fn add_favorite(sess: &Session, user_id: i64, playlist_id: Uuid) -> ApiResult<()> {
+    sess.query_prepared(
+        "INSERT INTO favorites (user_id, playlist_id, created_at) VALUES (?, ?, toTimestamp(now())) IF NOT EXISTS;",
+        (user_id, playlist_id)
+    ).await?;
+
+    Ok(())
+}
+
+/// Removes the playlist from the user's favorites if it is there.
+pub async fn remove_favorite(sess: &Session, user_id: i64, playlist_id: Uuid) -> ApiResult<()> {
+    sess.query_prepared(
+        "DELETE FROM favorites WHERE user_id = ? AND playlist_id = ?;",
+        (user_id, playlist_id)
+    ).await?;
+
+    Ok(())
+}
+
+/// Removes every favorite of the given user.
+pub async fn remove_favorites_for_user(sess: &Session, user_id: i64) -> ApiResult<()> {
+    sess.query_prepared(
+        "DELETE FROM favorites WHERE user_id = ?;",
+        (user_id,)
+    ).await?;
+
+    Ok(())
+}
+
+/// Gets the ids of the user's favorited playlists, most recently favorited
+/// first.
+pub async fn get_favorite_ids(sess: &Session, user_id: i64) -> ApiResult<Vec<Uuid>> {
+    let result = sess.query_prepared(
+        "SELECT playlist_id, created_at FROM favorites WHERE user_id = ?;",
+        (user_id,)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let mut favorites: Vec<(Uuid, Option<chrono::Duration>)> = rows
+        .into_typed::<(Uuid, Option<chrono::Duration>)>()
+        .filter_map(|v| v.ok())
+        .collect();
+    favorites.sort_by_key(|(_, created_at)| Reverse(*created_at));
+
+    Ok(favorites.into_iter().map(|(id, _)| id).collect())
+}
@@ -0,0 +1,109 @@
+use poem::http::header;
+use poem::web::Data;
+use poem::{handler, Response, Result};
+
+use crate::db::Session;
+use super::discover::{self, DiscoverySort};
+use super::Playlist;
+
+
+/// The path the feed is served on, outside of the documented api.
+pub const FEED_PATH: &str = "/feed.xml";
+
+/// The number of playlists included in the feed.
+const FEED_SIZE: usize = 50;
+
+/// How long in seconds clients and proxies may cache the feed for.
+const FEED_MAX_AGE_SECS: u64 = 5 * 60;
+
+
+lazy_static! {
+    /// The base url of the site playlists are linked to.
+    static ref SITE_URL: String = {
+        std::env::var("SITE_URL")
+            .unwrap_or_else(|_| "http://127.0.0.1:8000".to_string())
+            .trim_end_matches('/')
+            .to_string()
+    };
+
+    /// The base url banners are publicly served from.
+    static ref BANNER_URL: String = {
+        std::env::var("BANNER_URL")
+            .unwrap_or_else(|_| "http://127.0.0.1:7070/banners".to_string())
+            .trim_end_matches('/')
+            .to_string()
+    };
+}
+
+
+/// Renders an RSS feed of the most recently created public, non-NSFW
+/// playlists.
+#[handler]
+pub async fn render(session: Data<&Session>) -> Result<Response> {
+    let playlists = discover::discover_playlists(
+        &session,
+        DiscoverySort::New,
+        FEED_SIZE,
+        false,
+        None,
+    ).await?;
+
+    Ok(Response::builder()
+        .content_type("application/rss+xml; charset=utf-8")
+        .header(header::CACHE_CONTROL, format!("public, max-age={}", FEED_MAX_AGE_SECS))
+        .body(render_feed(&playlists)))
+}
+
+fn render_feed(playlists: &[Playlist]) -> String {
+    let mut feed = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    feed.push_str(r#"<rss version="2.0" xmlns:media="http://search.yahoo.com/mrss/"><channel>"#);
+    feed.push_str("<title>Spooderfy Playlists</title>");
+    feed.push_str(&format!("<link>{}</link>", escape(&SITE_URL)));
+    feed.push_str("<description>The newest public playlists on Spooderfy.</description>");
+
+    // Playlists created before `created_at` was recorded can't be dated so
+    // are left out.
+    for playlist in playlists.iter().filter(|v| v.created_at.is_some()) {
+        let link = format!("{}/playlists/{}", SITE_URL.as_str(), playlist.id);
+
+        feed.push_str("<item>");
+        feed.push_str(&format!("<title>{}</title>", escape(&playlist.title)));
+        feed.push_str(&format!("<link>{}</link>", escape(&link)));
+        feed.push_str(&format!(r#"<guid isPermaLink="false">{}</guid>"#, playlist.id));
+
+        if let Some(description) = playlist.description.as_deref() {
+            feed.push_str(&format!("<description>{}</description>", escape(description)));
+        }
+
+        if let Some(banner) = playlist.banner.as_deref() {
+            let url = format!("{}/{}", BANNER_URL.as_str(), banner);
+            feed.push_str(&format!(r#"<media:content url="{}" medium="image"/>"#, escape(&url)));
+        }
+
+        if let Some(created_at) = playlist.created_at {
+            feed.push_str(&format!("<pubDate>{}</pubDate>", created_at.to_rfc2822()));
+        }
+
+        feed.push_str("</item>");
+    }
+
+    feed.push_str("</channel></rss>");
+    feed
+}
+
+/// Escapes text for use in XML content and attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
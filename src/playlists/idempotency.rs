@@ -0,0 +1,79 @@
+use anyhow::anyhow;
+use scylla::IntoTypedRows;
+use strum::Display;
+use uuid::Uuid;
+
+use crate::db::{self, Session};
+use crate::errors::ApiResult;
+
+
+/// The kind of resource an idempotency key was used to create, keys are
+/// scoped by kind so reusing one on a different endpoint doesn't return the
+/// wrong resource.
+#[derive(Display, Copy, Clone, Debug)]
+#[strum(serialize_all = "lowercase")]
+pub enum IdempotentResource {
+    Playlist,
+    Entry,
+}
+
+
+/// Gets the id of the resource previously created by the user with the
+/// given key, if any.
+pub async fn get_idempotent_resource(
+    sess: &Session,
+    user_id: i64,
+    kind: IdempotentResource,
+    key: &str,
+) -> ApiResult<Option<Uuid>> {
+    let result = sess.query_prepared(
+        "SELECT resource_id FROM idempotency_keys WHERE user_id = ? AND kind = ? AND key = ?;",
+        (user_id, kind.to_string(), key)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let resource_id = rows.into_typed::<(Uuid,)>()
+        .next()
+        .transpose()?
+        .map(|v| v.0);
+
+    Ok(resource_id)
+}
+
+/// Reserves the key for the resource about to be created, returning false
+/// if the key has already been used.
+///
+/// This is a lightweight transaction so only one of several concurrent
+/// requests with the same key can reserve it.
+pub async fn reserve_idempotency_key(
+    sess: &Session,
+    user_id: i64,
+    kind: IdempotentResource,
+    key: &str,
+    resource_id: Uuid,
+) -> ApiResult<bool> {
+    let result = sess.query_prepared(
+        "INSERT INTO idempotency_keys (user_id, kind, key, resource_id) VALUES (?, ?, ?, ?) IF NOT EXISTS;",
+        (user_id, kind.to_string(), key, resource_id)
+    ).await?;
+
+    Ok(db::was_applied(&result))
+}
+
+/// Releases a reserved key so the request can be retried after the
+/// resource failed to be created.
+pub async fn release_idempotency_key(
+    sess: &Session,
+    user_id: i64,
+    kind: IdempotentResource,
+    key: &str,
+) -> ApiResult<()> {
+    sess.query_prepared(
+        "DELETE FROM idempotency_keys WHERE user_id = ? AND kind = ? AND key = ? IF EXISTS;",
+        (user_id, kind.to_string(), key)
+    ).await?;
+
+    Ok(())
+}
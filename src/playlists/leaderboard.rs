@@ -0,0 +1,144 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::anyhow;
+use poem_openapi::Object;
+use scylla::IntoTypedRows;
+use tokio::sync::{watch, RwLock};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+use crate::config::Config;
+use crate::db::Session;
+use crate::utils::JsSafeBigInt;
+
+
+/// The number of top users kept on the leaderboard.
+const MAX_LEADERBOARD_SIZE: usize = 100;
+
+/// The number of playlists fetched per page while totalling votes.
+const PLAYLISTS_PAGE_SIZE: i32 = 500;
+
+
+#[derive(Object, Clone)]
+pub struct LeaderboardEntry {
+    pub user_id: JsSafeBigInt,
+
+    /// The total votes across the user's public, non-NSFW playlists.
+    pub votes: i64,
+}
+
+/// The most recently computed leaderboard, shared between requests.
+///
+/// Totals are recomputed periodically by a full scan of the playlists
+/// rather than kept in a counter updated on every vote. A counter would
+/// also need adjusting whenever a playlist is made private, marked NSFW
+/// or removed, which is easy to get out of sync, at the cost of the
+/// leaderboard lagging behind by up to `leaderboard_interval_secs`.
+#[derive(Clone, Default)]
+pub struct Leaderboard(Arc<RwLock<Vec<LeaderboardEntry>>>);
+
+impl Leaderboard {
+    /// Gets up to `limit` users with the most votes, most votes first.
+    pub async fn top(&self, limit: usize) -> Vec<LeaderboardEntry> {
+        self.0.read().await
+            .iter()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+
+/// Spawns the task which periodically recomputes the leaderboard.
+///
+/// The first computation happens immediately so the leaderboard is
+/// populated shortly after startup. The task stops once `shutdown` is set
+/// to true.
+pub fn spawn(
+    sess: Session,
+    config: &Config,
+    leaderboard: Leaderboard,
+    mut shutdown: watch::Receiver<bool>,
+) -> JoinHandle<()> {
+    let period = Duration::from_secs(config.leaderboard_interval_secs);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {},
+                _ = shutdown.changed() => break,
+            }
+
+            match compute_leaderboard(&sess, &shutdown).await {
+                Ok(Some(entries)) => {
+                    info!("recomputed leaderboard with {} users", entries.len());
+                    *leaderboard.0.write().await = entries;
+                },
+                Ok(None) => {},
+                Err(e) => error!("failed to recompute leaderboard: {}", e),
+            }
+
+            if *shutdown.borrow() {
+                break
+            }
+        }
+
+        info!("leaderboard updates stopped");
+    })
+}
+
+/// Totals the votes of every owner's public, non-NSFW playlists, returning
+/// the top `MAX_LEADERBOARD_SIZE` owners or `None` if shutdown interrupted
+/// the scan.
+async fn compute_leaderboard(
+    sess: &Session,
+    shutdown: &watch::Receiver<bool>,
+) -> anyhow::Result<Option<Vec<LeaderboardEntry>>> {
+    let mut totals: HashMap<i64, i64> = HashMap::new();
+    let mut paging_state = None;
+
+    loop {
+        if *shutdown.borrow() {
+            return Ok(None)
+        }
+
+        let result = sess.query_prepared_paged(
+            "SELECT owner_id, is_public, nsfw, votes, deleted_at FROM playlists;",
+            &[],
+            Some(PLAYLISTS_PAGE_SIZE),
+            paging_state,
+        ).await?;
+
+        paging_state = result.paging_state;
+        let rows = result.rows
+            .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+        let playlists = rows
+            .into_typed::<(i64, bool, bool, i32, Option<chrono::Duration>)>()
+            .filter_map(|v| v.ok());
+
+        for (owner_id, is_public, nsfw, votes, deleted_at) in playlists {
+            if is_public & !nsfw & deleted_at.is_none() {
+                *totals.entry(owner_id).or_default() += votes as i64;
+            }
+        }
+
+        if paging_state.is_none() {
+            break
+        }
+    }
+
+    let mut ranked: Vec<LeaderboardEntry> = totals.into_iter()
+        .filter(|(_, votes)| *votes > 0)
+        .map(|(user_id, votes)| LeaderboardEntry { user_id: JsSafeBigInt(user_id), votes })
+        .collect();
+    ranked.sort_by_key(|v| Reverse(v.votes));
+    ranked.truncate(MAX_LEADERBOARD_SIZE);
+
+    Ok(Some(ranked))
+}
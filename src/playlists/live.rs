@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use futures_util::stream::{self, StreamExt};
+use poem::http::StatusCode;
+use poem::web::sse::{Event, SSE};
+use poem::web::{Data, Json, Query};
+use poem::{handler, IntoResponse, Response, Result};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+
+use crate::db::Session;
+use crate::errors::ApiError;
+use super::playlist;
+
+
+/// The path vote streams are served on.
+///
+/// This is routed alongside rather than through the OpenAPI service as
+/// that compresses every response, which would buffer the stream.
+pub const VOTE_STREAM_PATH: &str = "/api/v0/playlists/votes/stream";
+
+/// The number of updates buffered for each playlist, subscribers which fall
+/// further behind skip to the latest count.
+const CHANNEL_CAPACITY: usize = 16;
+
+/// How often a comment is sent on idle streams so proxies don't close them.
+const KEEP_ALIVE_SECS: u64 = 15;
+
+
+/// Publishes playlist vote counts to the clients streaming them.
+///
+/// A channel is only kept for playlists which currently have subscribers.
+#[derive(Clone)]
+pub struct VoteUpdates(Arc<VoteUpdatesInner>);
+
+struct VoteUpdatesInner {
+    channels: Mutex<HashMap<Uuid, broadcast::Sender<i32>>>,
+    subscribers: AtomicUsize,
+    max_subscribers: usize,
+}
+
+impl VoteUpdates {
+    pub fn new(max_subscribers: usize) -> Self {
+        Self(Arc::new(VoteUpdatesInner {
+            channels: Mutex::new(HashMap::new()),
+            subscribers: AtomicUsize::new(0),
+            max_subscribers,
+        }))
+    }
+
+    /// Sends the playlist's new vote count to everyone streaming it.
+    pub fn publish(&self, playlist_id: Uuid, votes: i32) {
+        let channels = self.0.channels.lock().unwrap();
+        if let Some(tx) = channels.get(&playlist_id) {
+            // This only fails if every subscriber has just gone.
+            let _ = tx.send(votes);
+        }
+    }
+
+    /// Subscribes to the playlist's vote counts, returning `None` if there
+    /// are already `max_subscribers` across every playlist.
+    fn subscribe(&self, playlist_id: Uuid) -> Option<Subscription> {
+        let max = self.0.max_subscribers;
+        self.0.subscribers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n < max).then(|| n + 1))
+            .ok()?;
+
+        let rx = self.0.channels.lock().unwrap()
+            .entry(playlist_id)
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe();
+
+        Some(Subscription { updates: self.clone(), playlist_id, rx })
+    }
+}
+
+/// A single client's stream, which gives up its place and drops the
+/// playlist's channel if it was the last subscriber when the client
+/// disconnects.
+struct Subscription {
+    updates: VoteUpdates,
+    playlist_id: Uuid,
+    rx: broadcast::Receiver<i32>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.updates.0.subscribers.fetch_sub(1, Ordering::SeqCst);
+
+        // `rx` is only dropped after this, so it is still counted here.
+        let mut channels = self.updates.0.channels.lock().unwrap();
+        let unused = channels.get(&self.playlist_id)
+            .map(|tx| tx.receiver_count() <= 1)
+            .unwrap_or(false);
+
+        if unused {
+            channels.remove(&self.playlist_id);
+        }
+    }
+}
+
+
+#[derive(Deserialize)]
+pub struct StreamParams {
+    id: Uuid,
+}
+
+/// Streams the playlist's `votes` as Server-Sent Events, sending the current
+/// count straight away and again whenever it changes.
+///
+/// Each event is a JSON object of the playlist `id` and its `votes`. Only
+/// public playlists can be streamed as `EventSource` can't send a token.
+#[handler]
+pub async fn stream_votes(
+    Query(params): Query<StreamParams>,
+    session: Data<&Session>,
+    updates: Data<&VoteUpdates>,
+) -> Result<Response> {
+    // Subscribing before reading the current count means no update can be
+    // missed in between.
+    let subscription = match updates.subscribe(params.id) {
+        None => return Ok(too_many_subscribers()),
+        Some(v) => v,
+    };
+
+    let votes = match playlist::get_playlist_by_id(&session, params.id).await? {
+        Some(playlist) if playlist.is_public => playlist.votes,
+        _ => return Err(ApiError::NotFound("Playlist does not exist.".to_string()).into()),
+    };
+
+    let changes = stream::unfold(subscription, |mut subscription| async move {
+        loop {
+            match subscription.rx.recv().await {
+                Ok(votes) => return Some((votes, subscription)),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let id = params.id;
+    let events = stream::once(async move { votes })
+        .chain(changes)
+        .map(move |votes| Event::message(json!({ "id": id, "votes": votes }).to_string()));
+
+    Ok(SSE::new(events)
+        .keep_alive(Duration::from_secs(KEEP_ALIVE_SECS))
+        .into_response())
+}
+
+fn too_many_subscribers() -> Response {
+    let body = Json(json!({
+        "code": "too_many_subscribers",
+        "detail": "Too many clients are streaming votes, try again later.",
+    }));
+
+    (StatusCode::SERVICE_UNAVAILABLE, body).into_response()
+}
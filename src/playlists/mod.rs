@@ -1,48 +1,216 @@
+mod collaborators;
+mod discover;
 mod entries;
+mod favorites;
+pub mod feed;
+mod idempotency;
+pub mod leaderboard;
+pub mod live;
 mod playlist;
+mod search;
+mod sharing;
+mod similar;
+mod stats;
+mod trending;
 
+use std::collections::{HashMap, HashSet};
 use anyhow::anyhow;
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
-use poem::Result;
+use poem::{Request, Result};
 use poem::web::Data;
-use poem_openapi::{Object, OpenApi};
-use poem_openapi::param::Query;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use poem_openapi::param::{Header, Query};
 use poem_openapi::payload::Json;
+use poem_openapi::types::ToJSON;
+use reqwest::Url;
 use serde_json::Value;
+use sha2::{Digest, Sha256};
 
 pub use playlist::*;
 pub use entries::*;
+pub use discover::{listed_bucket, DiscoverySort};
+pub use favorites::remove_favorites_for_user;
+use idempotency::IdempotentResource;
+use leaderboard::{Leaderboard, LeaderboardEntry};
+use live::VoteUpdates;
+use sharing::{ShareStatus, ShareToken};
+use stats::{PlaylistCount, PlaylistStats, TagCount};
 use crate::ApiTags;
-use crate::db::Session;
-use crate::users::user_info;
-use crate::utils::{JsonResponse, SuperUserBearer, TokenBearer};
-
+use crate::audit::{self, AuditAction};
+use crate::config::Config;
+use crate::db::{self, Session};
+use crate::errors::ApiResult;
+use crate::metrics;
+use crate::webhooks::Webhooks;
+use crate::users::{playlist_info, user_info};
+use crate::utils::{
+    self,
+    JsSafeBigInt,
+    JsonResponse,
+    ModeratorBearer,
+    NormalizedString,
+    OptionalTokenBearer,
+    PageResponse,
+    PatchPayload,
+    SuperUserBearer,
+    TokenBearer,
+    DEFAULT_PAGE_SIZE,
+};
+
+/// Returned when a request is retried while the original request with the
+/// same idempotency key is still being handled.
+const IDEMPOTENCY_IN_PROGRESS: &str = "A request with this Idempotency-Key is still being processed.";
+
+/// Returned when a playlist was edited by another request after it was read.
+const PLAYLIST_MODIFIED: &str = "The playlist has been modified since it was fetched, fetch it again and retry.";
+
+/// The number of times a playlist's nsfw flag is recomputed if the playlist
+/// is edited concurrently.
+const MAX_NSFW_REFRESH_ATTEMPTS: usize = 3;
+
+/// The maximum number of items a single playlist can hold.
+const MAX_PLAYLIST_ITEMS: usize = 500;
+
+/// The maximum number of entries that can be created in a single bulk request.
+const MAX_BULK_ENTRIES: usize = 50;
+
+/// The maximum number of playlists which can be fetched at once.
+const MAX_BATCH_PLAYLISTS: usize = 100;
+
+/// The maximum number of playlists that can be imported in a single request.
+const MAX_IMPORT_PLAYLISTS: usize = 100;
+
+/// The maximum number of entries that can be imported in a single request.
+const MAX_IMPORT_ENTRIES: usize = 1000;
+
+/// The maximum length of a playlist title.
+const MAX_TITLE_LENGTH: usize = 32;
+
+/// Appended to the title of cloned playlists.
+const CLONE_SUFFIX: &str = " (copy)";
+
+/// The maximum number of tags a single playlist can have.
+const MAX_PLAYLIST_TAGS: usize = 10;
+
+/// The maximum length of a single playlist tag.
+const MAX_TAG_LENGTH: usize = 24;
+
+/// The number of tags returned by `GET /tags` unless a smaller `limit` is given.
+const MAX_TAG_CLOUD_SIZE: i32 = 200;
 
 #[derive(Object, Debug)]
 pub struct PlaylistCreationPayload {
     #[oai(validator(max_length = 32, min_length = 2))]
-    title: String,
+    title: NormalizedString,
 
     #[oai(validator(max_length = 128, min_length = 2))]
-    description: Option<String>,
+    description: Option<NormalizedString>,
 
-    #[oai(validator(max_length = 256, pattern=r"https://i\.imgur\.com/[0-9a-z]+\.jpeg|https://i\.imgur\.com/[0-9a-z]+\.png|https://i\.imgur\.com/[0-9a-z]+\.webp"))]
+    /// An `i.imgur.com` image ending in `.jpg`, `.jpeg`, `.png` or `.webp`.
+    #[oai(validator(max_length = 256, pattern=r"^https://i\.imgur\.com/[0-9a-zA-Z]+\.(?:jpg|jpeg|png|webp)$"))]
     banner: Option<String>,
 
     #[oai(default)]
     is_public: bool,
 
+    /// Up to 500 entry ids.
+    #[oai(validator(max_items = 500))]
     items: Vec<Uuid>,
+
+    /// Up to 10 tags of at most 24 characters, these are stored trimmed
+    /// and lowercased.
+    #[oai(default)]
+    tags: Vec<String>,
+
+    /// Up to 50 entries to create along with the playlist, owned by the user.
+    /// Their ids are added after `items` and returned in `created_entries`.
+    #[oai(default, validator(max_items = 50))]
+    new_entries: Vec<EntryCreationPayload>,
+}
+
+
+#[derive(Object, Debug)]
+pub struct PlaylistUpdatePayload {
+    #[oai(validator(max_length = 32, min_length = 2))]
+    title: Option<NormalizedString>,
+
+    #[oai(validator(max_length = 128, min_length = 2))]
+    description: Option<NormalizedString>,
+
+    /// An `i.imgur.com` image ending in `.jpg`, `.jpeg`, `.png` or `.webp`.
+    #[oai(validator(max_length = 256, pattern=r"^https://i\.imgur\.com/[0-9a-zA-Z]+\.(?:jpg|jpeg|png|webp)$"))]
+    banner: Option<String>,
+
+    is_public: Option<bool>,
+
+    /// Up to 10 tags of at most 24 characters, these are stored trimmed
+    /// and lowercased.
+    tags: Option<Vec<String>>,
+
+    /// The `version` of the playlist these changes were made to.
+    version: i32,
 }
 
 
 #[derive(Object, Debug)]
 pub struct EntryCreationPayload {
     #[oai(validator(max_length = 32, min_length = 2))]
-    title: String,
+    title: NormalizedString,
 
     #[oai(validator(max_length = 128, min_length = 2))]
-    description: Option<String>,
+    description: Option<NormalizedString>,
+
+    #[oai(default)]
+    is_public: bool,
+
+    #[oai(default)]
+    nsfw: bool,
+
+    /// The link must point to one of the hosts in `ALLOWED_LINK_HOSTS`.
+    #[oai(validator(max_length = 256, pattern=r"https://(?:[a-zA-Z]|[0-9]|[$-_@.&+]|[!*\(\),]|(?:%[0-9a-fA-F][0-9a-fA-F]))+"))]
+    ref_link: Option<String>,
+}
+
+
+/// A playlist from a user data export.
+///
+/// Banners are not imported as they have already been re-hosted and can't be
+/// validated again.
+#[derive(Object, Debug)]
+pub struct PlaylistImportPayload {
+    /// The id of the playlist in the export, this is replaced on import.
+    id: Uuid,
+
+    #[oai(validator(max_length = 32, min_length = 2))]
+    title: NormalizedString,
+
+    #[oai(validator(max_length = 128, min_length = 2))]
+    description: Option<NormalizedString>,
+
+    #[oai(default)]
+    is_public: bool,
+
+    /// Ids of entries in the same export are replaced with the ids of the
+    /// imported entries.
+    items: Vec<Uuid>,
+
+    #[oai(default)]
+    tags: Vec<String>,
+}
+
+
+/// An entry from a user data export.
+#[derive(Object, Debug)]
+pub struct EntryImportPayload {
+    /// The id of the entry in the export, this is replaced on import.
+    id: Uuid,
+
+    #[oai(validator(max_length = 32, min_length = 2))]
+    title: NormalizedString,
+
+    #[oai(validator(max_length = 128, min_length = 2))]
+    description: Option<NormalizedString>,
 
     #[oai(default)]
     is_public: bool,
@@ -50,10 +218,78 @@ pub struct EntryCreationPayload {
     #[oai(default)]
     nsfw: bool,
 
+    /// The link must point to one of the hosts in `ALLOWED_LINK_HOSTS`.
+    #[oai(validator(max_length = 256, pattern=r"https://(?:[a-zA-Z]|[0-9]|[$-_@.&+]|[!*\(\),]|(?:%[0-9a-fA-F][0-9a-fA-F]))+"))]
+    ref_link: Option<String>,
+}
+
+
+/// The bundle returned by `GET /users/export`, any other fields in it are
+/// ignored.
+#[derive(Object, Debug)]
+pub struct ImportPayload {
+    #[oai(default)]
+    playlists: Vec<PlaylistImportPayload>,
+
+    #[oai(default)]
+    entries: Vec<EntryImportPayload>,
+}
+
+
+#[derive(Object, Debug)]
+pub struct EntryUpdatePayload {
+    #[oai(validator(max_length = 32, min_length = 2))]
+    title: Option<NormalizedString>,
+
+    #[oai(validator(max_length = 128, min_length = 2))]
+    description: Option<NormalizedString>,
+
+    is_public: Option<bool>,
+
+    nsfw: Option<bool>,
+
+    /// The link must point to one of the hosts in `ALLOWED_LINK_HOSTS`.
     #[oai(validator(max_length = 256, pattern=r"https://(?:[a-zA-Z]|[0-9]|[$-_@.&+]|[!*\(\),]|(?:%[0-9a-fA-F][0-9a-fA-F]))+"))]
     ref_link: Option<String>,
 }
 
+#[derive(ApiResponse)]
+#[allow(clippy::large_enum_variant)]
+pub enum PlaylistResponse {
+    /// The playlist.
+    #[oai(status = 200)]
+    Ok(Json<Playlist>, #[oai(header = "ETag")] String),
+
+    /// The playlist has not changed since it was fetched with the tag
+    /// given in `If-None-Match`.
+    #[oai(status = 304)]
+    NotModified(#[oai(header = "ETag")] String),
+
+    /// The playlist does not exist.
+    #[oai(status = 404)]
+    NotFound(Json<Value>),
+}
+
+/// The result of `POST /playlists/validate`.
+#[derive(Object, Debug)]
+pub struct PlaylistValidation {
+    pub valid: bool,
+
+    /// Why the playlist would be rejected, if it would be.
+    pub detail: Option<String>,
+
+    pub invalid_items: Vec<Uuid>,
+    pub computed_nsfw: bool,
+}
+
+#[derive(ApiResponse)]
+pub enum DiscoveryResponse {
+    /// The playlists, `X-Total-Count` is the number of public playlists when
+    /// `with_total` is set.
+    #[oai(status = 200)]
+    Ok(Json<Vec<Playlist>>, #[oai(header = "X-Total-Count")] Option<i64>),
+}
+
 pub struct PlaylistsApi;
 
 #[OpenApi]
@@ -61,99 +297,199 @@ impl PlaylistsApi {
     /// Get Playlist
     ///
     /// Get a specific playlist and it's info.
+    ///
+    /// If the request is authenticated `has_voted` is set to whether the user
+    /// has up-voted the playlist.
+    ///
+    /// If `expand` is set the full entries for each item are included in `entries`.
+    ///
+    /// Fetches by anyone other than the owner count towards the playlist's views,
+    /// see `GET /playlists/stats`.
+    ///
+    /// The response has an `ETag` which changes whenever the returned playlist
+    /// does, passing it back in `If-None-Match` returns a 304 with no body if
+    /// nothing has changed.
+    ///
+    /// NSFW playlists and entries are only returned to age verified users, and
+    /// private playlists only to their owner and collaborators, for everyone
+    /// else they are treated as if they don't exist.
     #[oai(path = "/playlists", method = "get", tag = "ApiTags::Playlists")]
     pub async fn get_playlist(
         &self,
         id: Query<Uuid>,
+        expand: Query<Option<bool>>,
+        #[oai(name = "If-None-Match")]
+        if_none_match: Header<Option<String>>,
         session: Data<&Session>,
-    ) -> Result<Json<Option<Playlist>>> {
-        Ok(
-            playlist::get_playlist_by_id(&session, id.0)
-                .await
-                .map(|v| Json(v))?
-        )
-    }
+        token: OptionalTokenBearer,
+    ) -> Result<PlaylistResponse> {
+        let user_id = match token.0 {
+            None => None,
+            Some(token) => user_info::get_user_id_from_token(&session, &token).await?,
+        };
+        let allow_nsfw = user_info::can_view_nsfw(&session, user_id).await?;
 
-    /// Get Playlist Entry
-    ///
-    /// Get a specific entry and it's info.
-    #[oai(path = "/entries", method = "get", tag = "ApiTags::Playlists")]
-    pub async fn get_playlist_entry(
-        &self,
-        id: Query<Uuid>,
-        session: Data<&Session>,
-    ) -> Result<Json<Option<PlaylistEntry>>> {
-        Ok(
-            entries::get_entry_by_id(&session, id.0)
-                .await
-                .map(|v| Json(v))?
-        )
+        let mut playlist = match playlist::get_playlist_by_id(&session, id.0).await? {
+            Some(v) if (allow_nsfw || !v.nsfw) && can_view_playlist(&session, &v, user_id).await? => v,
+            _ => return Ok(PlaylistResponse::NotFound(Json(serde_json::json!({
+                "detail": "Playlist does not exist.",
+            })))),
+        };
+
+        if expand.0 == Some(true) {
+            let mut found = entries::get_entries_with_ids(&session, &playlist.items).await?;
+            found.retain(|v| allow_nsfw || !v.nsfw);
+            playlist.entries = Some(found);
+        }
+
+        if let Some(user_id) = user_id {
+            let has_voted = playlist::has_user_voted(&session, user_id, playlist.id).await?;
+            playlist.has_voted = Some(has_voted);
+        }
+
+        if user_id != Some(*playlist.owner_id) {
+            stats::spawn_record_view(&session, playlist.id);
+        }
+
+        let etag = playlist_etag(&playlist);
+        if let Some(tags) = if_none_match.0 {
+            if etag_matches(&tags, &etag) {
+                return Ok(PlaylistResponse::NotModified(etag))
+            }
+        }
+
+        Ok(PlaylistResponse::Ok(Json(playlist), etag))
     }
 
-    /// Superuser Remove Playlist
+    /// Get Playlists
     ///
-    /// Forcefully removes a playlist by a superuser.
-    #[oai(path = "/playlists/override", method = "delete", tag = "ApiTags::Playlists")]
-    pub async fn remove_playlist_superuser(
+    /// Get up to 100 playlists by id in a single request, returning them in the
+    /// same order as the ids.
+    ///
+    /// Ids which don't exist are left out, as are private playlists the user
+    /// doesn't own or collaborate on and NSFW playlists unless the user is age
+    /// verified. Unlike `GET /playlists` this doesn't set `has_voted`.
+    #[oai(path = "/playlists/batch", method = "post", tag = "ApiTags::Playlists")]
+    pub async fn get_playlists_batch(
         &self,
-        id: Query<Uuid>,
-        _token: SuperUserBearer,
+        payload: Json<Vec<Uuid>>,
         session: Data<&Session>,
-    ) -> Result<JsonResponse<Value>> {
-        playlist::remove_playlist(&session, id.0).await?;
+        token: OptionalTokenBearer,
+    ) -> Result<JsonResponse<Vec<Playlist>>> {
+        if payload.0.len() > MAX_BATCH_PLAYLISTS {
+            return Ok(JsonResponse::bad_request(format!(
+                "You cannot fetch more than {} playlists at once.",
+                MAX_BATCH_PLAYLISTS,
+            )))
+        }
 
-        Ok(JsonResponse::Ok(Json(Value::Null)))
+        let user_id = match token.0 {
+            None => None,
+            Some(token) => user_info::get_user_id_from_token(&session, &token).await?,
+        };
+        let allow_nsfw = user_info::can_view_nsfw(&session, user_id).await?;
+
+        let found = playlist::get_playlists_with_ids(&session, &payload.0).await?;
+
+        let mut playlists = Vec::with_capacity(found.len());
+        for playlist in found {
+            if playlist.nsfw & !allow_nsfw {
+                continue
+            }
+
+            if can_view_playlist(&session, &playlist, user_id).await? {
+                playlists.push(playlist);
+            }
+        }
+
+        Ok(JsonResponse::ok(playlists))
     }
 
-    /// Superuser Remove Entry
+    /// Get Shared Playlist
     ///
-    /// Forcefully removes a playlist entry by a superuser.
-    #[oai(path = "/entries/override", method = "delete", tag = "ApiTags::Playlists")]
-    pub async fn remove_entry_superuser(
+    /// Get a playlist using a share token from `POST /playlists/share`, this
+    /// returns the playlist even if it is private.
+    ///
+    /// Tokens which have expired return a 410.
+    ///
+    /// Like `GET /playlists`, NSFW playlists are only returned to age verified
+    /// users, for everyone else they are treated as if they don't exist.
+    #[oai(path = "/playlists/shared", method = "get", tag = "ApiTags::Playlists")]
+    pub async fn get_shared_playlist(
         &self,
-        id: Query<Uuid>,
-        _token: SuperUserBearer,
+        #[oai(validator(max_length = 128))]
+        token: Query<String>,
         session: Data<&Session>,
-    ) -> Result<JsonResponse<Value>> {
-        entries::remove_entry(&session, id.0).await?;
+        bearer: OptionalTokenBearer,
+    ) -> Result<JsonResponse<Playlist>> {
+        let (id, secret) = match sharing::parse_token(&token.0) {
+            None => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(v) => v,
+        };
 
-        Ok(JsonResponse::Ok(Json(Value::Null)))
+        match sharing::check_share_secret(&session, id, secret).await? {
+            ShareStatus::Valid => {},
+            ShareStatus::Expired => return Ok(JsonResponse::gone("This share link has expired.")),
+            ShareStatus::Invalid => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+        }
+
+        let user_id = match bearer.0 {
+            None => None,
+            Some(token) => user_info::get_user_id_from_token(&session, &token).await?,
+        };
+        let allow_nsfw = user_info::can_view_nsfw(&session, user_id).await?;
+
+        let playlist = playlist::get_playlist_by_id(&session, id).await?
+            .filter(|v| allow_nsfw || !v.nsfw);
+
+        match playlist {
+            None => Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(playlist) => Ok(JsonResponse::ok(playlist)),
+        }
     }
 
-    /// Delete Playlist
+    /// Share Playlist
     ///
-    /// Delete a specific playlist providing the user owns the playlist.
-    #[oai(path = "/playlists", method = "delete", tag = "ApiTags::Playlists")]
-    pub async fn delete_playlist(
+    /// Get a token which lets anyone with it view the playlist, even if it is
+    /// private. The same token is returned until it is revoked or expires.
+    ///
+    /// If `expires_in_secs` is given a new token which expires after that many
+    /// seconds, up to 30 days, replaces the current token.
+    #[oai(path = "/playlists/share", method = "post", tag = "ApiTags::Playlists")]
+    pub async fn share_playlist(
         &self,
         id: Query<Uuid>,
+        #[oai(validator(minimum(value = "1"), maximum(value = "2592000")))]
+        expires_in_secs: Query<Option<i64>>,
         session: Data<&Session>,
         token: TokenBearer,
-    ) -> Result<JsonResponse<Value>> {
+    ) -> Result<JsonResponse<ShareToken>> {
         let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
             None => return Ok(JsonResponse::unauthorized()),
             Some(v) => v,
         };
 
         let playlist = match playlist::get_playlist_by_id(&session, id.0).await? {
-            None => return Ok(JsonResponse::bad_request("Playlist does not exist.")),
-            Some(playlist) => playlist,
+            None => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(v) => v,
         };
 
         if *playlist.owner_id != user_id {
-            return Ok(JsonResponse::forbidden())
+            return Ok(deny_playlist_access(&playlist, "Playlist does not exist."))
         }
 
-        playlist::remove_playlist(&session, playlist.id).await?;
+        let expires_in = expires_in_secs.0.map(chrono::Duration::seconds);
+        let token = sharing::get_or_create_share_token(&session, &playlist, expires_in).await?;
 
-        Ok(JsonResponse::ok(Value::Null))
+        Ok(JsonResponse::ok(token))
     }
 
-    /// Delete Playlist Entry
+    /// Revoke Playlist Share
     ///
-    /// Delete a specific entry providing the user owns the entry.
-    #[oai(path = "/entries", method = "delete", tag = "ApiTags::Playlists")]
-    pub async fn delete_playlist_entry(
+    /// Revoke the playlist's share token, a new one is generated the next time
+    /// the playlist is shared.
+    #[oai(path = "/playlists/share", method = "delete", tag = "ApiTags::Playlists")]
+    pub async fn revoke_playlist_share(
         &self,
         id: Query<Uuid>,
         session: Data<&Session>,
@@ -164,155 +500,1564 @@ impl PlaylistsApi {
             Some(v) => v,
         };
 
-        let entry = match entries::get_entry_by_id(&session, id.0).await? {
-            None => return Ok(JsonResponse::bad_request("Playlist does not exist.")),
-            Some(entry) => entry,
+        let playlist = match playlist::get_playlist_by_id(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(v) => v,
         };
 
-        if *entry.owner_id != user_id {
-            return Ok(JsonResponse::forbidden())
+        if *playlist.owner_id != user_id {
+            return Ok(deny_playlist_access(&playlist, "Playlist does not exist."))
         }
 
-        entries::remove_entry(&session, entry.id).await?;
+        sharing::revoke_share_token(&session, &playlist).await?;
 
         Ok(JsonResponse::ok(Value::Null))
     }
 
-    /// Upvote Playlist
+    /// Count My Playlists
     ///
-    /// Upvote a specific playlist returning the newly updated playlist.
-    #[oai(path = "/playlists/vote", method = "post", tag = "ApiTags::Playlists")]
-    pub async fn upvote_playlist(
+    /// Get the number of playlists owned by the user, including private
+    /// playlists, without fetching them.
+    #[oai(path = "/playlists/count", method = "get", tag = "ApiTags::Playlists")]
+    pub async fn count_my_playlists(
         &self,
-        id: Query<Uuid>,
         session: Data<&Session>,
         token: TokenBearer,
-    ) -> Result<JsonResponse<Playlist>> {
+    ) -> Result<JsonResponse<PlaylistCount>> {
         let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
             None => return Ok(JsonResponse::unauthorized()),
             Some(v) => v,
         };
 
-        let mut playlist = match playlist::get_playlist_by_id(&session, id.0).await? {
-            None => return Ok(JsonResponse::bad_request("Playlist does not exist.")),
-            Some(v) => v,
-        };
+        let count = playlist::count_playlists_for_owner(&session, user_id).await?;
 
-        if playlist::has_user_voted(&session, user_id, playlist.id.clone()).await? {
-            return Ok(JsonResponse::bad_request(
-                "You have already up-voted this playlist in the last 12 hours."
-            ))
-        }
+        Ok(JsonResponse::ok(PlaylistCount { count }))
+    }
 
-        let credits = user_info::get_user_vote_credits(&session, user_id).await?;
+    /// Count Public Playlists
+    ///
+    /// Get the number of public playlists.
+    ///
+    /// Counting reads every public playlist so the count is cached for 5 minutes,
+    /// it can lag behind recently created or removed playlists.
+    #[oai(path = "/playlists/count/public", method = "get", tag = "ApiTags::Playlists")]
+    pub async fn count_public_playlists(
+        &self,
+        session: Data<&Session>,
+    ) -> Result<Json<PlaylistCount>> {
+        let count = stats::count_public_playlists(&session).await?;
 
-        if credits <= 0 {
-            return Ok(JsonResponse::bad_request("You do not have enough credits."))
-        }
+        Ok(Json(PlaylistCount { count }))
+    }
 
-        user_info::adjust_user_credits(&session, user_id, -1).await?;
-        playlist::upvote_playlist(&session, user_id, playlist.id.clone()).await?;
+    /// Get Playlist Stats
+    ///
+    /// Get the view and vote counts of a specific playlist.
+    ///
+    /// Like `GET /playlists`, NSFW playlists are only visible to age verified
+    /// users and private playlists only to their owner and collaborators, for
+    /// everyone else they are treated as if they don't exist.
+    #[oai(path = "/playlists/stats", method = "get", tag = "ApiTags::Playlists")]
+    pub async fn get_playlist_stats(
+        &self,
+        id: Query<Uuid>,
+        session: Data<&Session>,
+        token: OptionalTokenBearer,
+    ) -> Result<JsonResponse<PlaylistStats>> {
+        let user_id = match token.0 {
+            None => None,
+            Some(token) => user_info::get_user_id_from_token(&session, &token).await?,
+        };
+        let allow_nsfw = user_info::can_view_nsfw(&session, user_id).await?;
 
-        playlist.votes += 1;
+        let playlist = match playlist::get_playlist_by_id(&session, id.0).await? {
+            Some(v) if (allow_nsfw || !v.nsfw) && can_view_playlist(&session, &v, user_id).await? => v,
+            _ => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+        };
 
-        Ok(JsonResponse::ok(playlist))
+        let views = stats::get_view_count(&session, playlist.id).await?;
+
+        Ok(JsonResponse::ok(PlaylistStats {
+            id: playlist.id,
+            views,
+            votes: playlist.votes,
+        }))
     }
 
-    /// Upvote Playlist Entry
+    /// Get Playlist Entry
     ///
-    /// Upvote a specific playlist entry returning the newly updated entry.
-    #[oai(path = "/entries/vote", method = "post", tag = "ApiTags::Playlists")]
-    pub async fn upvote_entry(
+    /// Get a specific entry and it's info.
+    ///
+    /// If the request is authenticated `has_voted` is set to whether the user
+    /// has up-voted the entry.
+    ///
+    /// NSFW entries are only returned to age verified users, and private
+    /// entries only to their owner.
+    #[oai(path = "/entries", method = "get", tag = "ApiTags::Playlists")]
+    pub async fn get_playlist_entry(
         &self,
         id: Query<Uuid>,
         session: Data<&Session>,
-        token: TokenBearer,
+        token: OptionalTokenBearer,
     ) -> Result<JsonResponse<PlaylistEntry>> {
-        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
-            None => return Ok(JsonResponse::unauthorized()),
-            Some(v) => v,
+        let user_id = match token.0 {
+            None => None,
+            Some(token) => user_info::get_user_id_from_token(&session, &token).await?,
         };
+        let allow_nsfw = user_info::can_view_nsfw(&session, user_id).await?;
 
-        let mut entry = match entries::get_entry_by_id(&session, id.0).await? {
-            None => return Ok(JsonResponse::bad_request("Entry does not exist.")),
+        let entry = entries::get_entry_by_id(&session, id.0).await?
+            .filter(|v| allow_nsfw || !v.nsfw)
+            .filter(|v| v.is_public || (Some(*v.owner_id) == user_id));
+
+        let mut entry = match entry {
+            None => return Ok(JsonResponse::not_found("Entry does not exist.")),
             Some(v) => v,
         };
 
-        if entries::has_user_voted(&session, user_id, entry.id.clone()).await? {
-            return Ok(JsonResponse::bad_request(
-                "You have already up-voted this entry in the last 12 hours.",
-            ))
-        }
-
-        let credits = user_info::get_user_vote_credits(&session, user_id).await?;
-
-        if credits <= 0 {
-            return Ok(JsonResponse::bad_request("You do not have enough credits."))
+        if let Some(user_id) = user_id {
+            let has_voted = entries::has_user_voted(&session, user_id, entry.id).await?;
+            entry.has_voted = Some(has_voted);
         }
 
-        user_info::adjust_user_credits(&session, user_id, -1).await?;
-        entries::upvote_playlist(&session, user_id, entry.id.clone()).await?;
-
-        entry.votes += 1;
-
         Ok(JsonResponse::ok(entry))
     }
 
-    /// Create Playlist
+    /// Discover Playlists
     ///
-    /// Creates a playlist from the given payload, returning the fully populated
-    /// playlist information (id, etc..).
+    /// Browse public playlists, sorted by most votes (`top`), most recently
+    /// created (`new`) or at random (`random`).
     ///
-    /// Note: This will filter out items to only include valid items. E.g.
-    /// Items that are not marked as public when the playlist is public will not be included.
-    /// Items that do no exist already as entries will not be included.
-    /// If a playlist is *not* public then it will include entries that the user owns.
-    #[oai(path = "/playlists", method = "post", tag = "ApiTags::Playlists")]
-    pub async fn create_playlist(
+    /// `top_week` and `top_month` sort by the most votes received in the last
+    /// 7 or 30 days instead, setting `window_votes` to the votes received in
+    /// that time.
+    ///
+    /// NSFW playlists are excluded unless `include_nsfw` is set by an age
+    /// verified user, and only playlists with the given `tag` are included if
+    /// one is set.
+    ///
+    /// If `with_total` is set the `X-Total-Count` header has the number of
+    /// public playlists, including NSFW playlists. This is left out when
+    /// filtering by `tag` as tagged playlists aren't counted.
+    #[oai(path = "/playlists/discover", method = "get", tag = "ApiTags::Playlists")]
+    #[allow(clippy::too_many_arguments)]
+    pub async fn discover_playlists(
         &self,
-        payload: Json<PlaylistCreationPayload>,
+        sort: Query<Option<DiscoverySort>>,
+        #[oai(validator(minimum(value = "1"), maximum(value = "100")))]
+        limit: Query<Option<i32>>,
+        include_nsfw: Query<Option<bool>>,
+        tag: Query<Option<String>>,
+        with_total: Query<Option<bool>>,
         session: Data<&Session>,
-        token: TokenBearer,
-    ) -> Result<JsonResponse<Playlist>> {
-        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
-            None => return Ok(JsonResponse::unauthorized()),
-            Some(v) => v,
-        };
-
-        let items = entries::get_entries_with_ids(&session, payload.0.items).await?;
-        let is_nsfw = items.iter().any(|v|  v.nsfw);
-        let items = filter_valid_entries(user_id, payload.0.is_public, items);
-
-        if items.is_empty() {
-            return Ok(JsonResponse::bad_request("No valid playlists entries selected."))
-        }
+        token: OptionalTokenBearer,
+    ) -> Result<DiscoveryResponse> {
+        let include_nsfw = include_nsfw.0.unwrap_or(false)
+            && user_info::can_view_nsfw_for_token(&session, token.0.as_deref()).await?;
 
-        let playlist_id = Uuid::new_v4();
-        let playlist = insert_playlist(
+        let tag = tag.0.map(|v| v.trim().to_lowercase());
+        let playlists = discover::discover_playlists(
             &session,
-                playlist_id,
-                user_id,
-                payload.0.banner,
-                payload.0.description,
-                payload.0.is_public,
-                items,
-                is_nsfw,
-                payload.0.title,
-            true,
-        ).await?.ok_or_else(|| anyhow!("expected item in database after creation"))?;
+            sort.0.unwrap_or(DiscoverySort::Top),
+            limit.0.unwrap_or(DEFAULT_PAGE_SIZE) as usize,
+            include_nsfw,
+            tag.as_deref(),
+        ).await?;
 
-        Ok(JsonResponse::ok(playlist))
+        let total = if with_total.0.unwrap_or(false) & tag.is_none() {
+            Some(stats::get_public_total(&session).await?)
+        } else {
+            None
+        };
+
+        Ok(DiscoveryResponse::Ok(Json(playlists), total))
     }
 
-    /// Create Playlist Entry
+    /// Get Trending Playlists
+    ///
+    /// Get the public playlists which received the most upvotes in the last
+    /// 24 hours, most upvoted first.
+    ///
+    /// NSFW playlists are excluded unless `include_nsfw` is set by an age
+    /// verified user.
+    #[oai(path = "/playlists/trending", method = "get", tag = "ApiTags::Playlists")]
+    pub async fn get_trending_playlists(
+        &self,
+        #[oai(validator(minimum(value = "1"), maximum(value = "100")))]
+        limit: Query<Option<i32>>,
+        include_nsfw: Query<Option<bool>>,
+        session: Data<&Session>,
+        token: OptionalTokenBearer,
+    ) -> Result<Json<Vec<Playlist>>> {
+        let include_nsfw = include_nsfw.0.unwrap_or(false)
+            && user_info::can_view_nsfw_for_token(&session, token.0.as_deref()).await?;
+
+        let playlists = trending::trending_playlists(
+            &session,
+            limit.0.unwrap_or(DEFAULT_PAGE_SIZE) as usize,
+            include_nsfw,
+        ).await?;
+
+        Ok(Json(playlists))
+    }
+
+    /// Get Tags
+    ///
+    /// Get up to `limit` of the tags used by public playlists along with the
+    /// number of public playlists using each, most used first.
+    ///
+    /// Tags are stored trimmed and lowercased so differently written tags are
+    /// counted together.
+    #[oai(path = "/tags", method = "get", tag = "ApiTags::Playlists")]
+    pub async fn get_tags(
+        &self,
+        #[oai(validator(minimum(value = "1"), maximum(value = "200")))]
+        limit: Query<Option<i32>>,
+        session: Data<&Session>,
+    ) -> Result<Json<Vec<TagCount>>> {
+        let tags = stats::get_tag_counts(&session, limit.0.unwrap_or(MAX_TAG_CLOUD_SIZE) as usize).await?;
+
+        Ok(Json(tags))
+    }
+
+    /// Search Playlists
+    ///
+    /// Get up to `limit` public playlists whose titles contain `q`, ignoring
+    /// case, most voted first.
+    ///
+    /// If `fuzzy` is set mistyped queries are matched too. Each playlist is
+    /// scored by how closely its title matches `q`, from `0` to `1`, the best
+    /// matches are returned first with their `score` and those scoring below
+    /// `min_score` are left out.
+    ///
+    /// Only a bounded sample of the public playlists is searched, so matches
+    /// can be missed when there are many public playlists.
+    ///
+    /// NSFW playlists are excluded unless `include_nsfw` is set by an age
+    /// verified user.
+    #[oai(path = "/playlists/search", method = "get", tag = "ApiTags::Playlists")]
+    #[allow(clippy::too_many_arguments)]
+    pub async fn search_playlists(
+        &self,
+        #[oai(validator(min_length = 1, max_length = 64))]
+        q: Query<String>,
+        fuzzy: Query<Option<bool>>,
+        #[oai(validator(minimum(value = "0"), maximum(value = "1")))]
+        min_score: Query<Option<f64>>,
+        #[oai(validator(minimum(value = "1"), maximum(value = "100")))]
+        limit: Query<Option<i32>>,
+        include_nsfw: Query<Option<bool>>,
+        session: Data<&Session>,
+        token: OptionalTokenBearer,
+    ) -> Result<Json<Vec<Playlist>>> {
+        let include_nsfw = include_nsfw.0.unwrap_or(false)
+            && user_info::can_view_nsfw_for_token(&session, token.0.as_deref()).await?;
+
+        let playlists = search::search_playlists(
+            &session,
+            &q.0,
+            fuzzy.0.unwrap_or(false),
+            min_score.0.unwrap_or(search::DEFAULT_MIN_SCORE),
+            limit.0.unwrap_or(DEFAULT_PAGE_SIZE) as usize,
+            include_nsfw,
+        ).await?;
+
+        Ok(Json(playlists))
+    }
+
+    /// Get Similar Playlists
+    ///
+    /// Get the public playlists which share the most entries with the given
+    /// playlist, most similar first. `similarity` is set to the number of items
+    /// in common divided by the number of items in either.
+    ///
+    /// The playlist must be public or owned by the user. NSFW playlists are
+    /// excluded unless the user is age verified.
+    #[oai(path = "/playlists/similar", method = "get", tag = "ApiTags::Playlists")]
+    pub async fn get_similar_playlists(
+        &self,
+        id: Query<Uuid>,
+        #[oai(validator(minimum(value = "1"), maximum(value = "100")))]
+        limit: Query<Option<i32>>,
+        session: Data<&Session>,
+        token: OptionalTokenBearer,
+    ) -> Result<JsonResponse<Vec<Playlist>>> {
+        let user_id = match token.0 {
+            None => None,
+            Some(token) => user_info::get_user_id_from_token(&session, &token).await?,
+        };
+        let allow_nsfw = user_info::can_view_nsfw(&session, user_id).await?;
+
+        let target = playlist::get_playlist_by_id(&session, id.0).await?
+            .filter(|v| v.is_public | (user_id == Some(*v.owner_id)))
+            .filter(|v| allow_nsfw || !v.nsfw);
+
+        let target = match target {
+            None => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(v) => v,
+        };
+
+        let playlists = similar::similar_playlists(
+            &session,
+            &target,
+            limit.0.unwrap_or(DEFAULT_PAGE_SIZE) as usize,
+            allow_nsfw,
+        ).await?;
+
+        Ok(JsonResponse::ok(playlists))
+    }
+
+    /// Get Vote Leaderboard
+    ///
+    /// Get up to `limit` users whose public, non-NSFW playlists have received
+    /// the most votes in total, most votes first.
+    ///
+    /// The leaderboard is recomputed periodically so it may lag behind
+    /// recent votes.
+    #[oai(path = "/leaderboard", method = "get", tag = "ApiTags::Playlists")]
+    pub async fn get_leaderboard(
+        &self,
+        #[oai(validator(minimum(value = "1"), maximum(value = "100")))]
+        limit: Query<Option<i32>>,
+        leaderboard: Data<&Leaderboard>,
+    ) -> Json<Vec<LeaderboardEntry>> {
+        let limit = limit.0.unwrap_or(DEFAULT_PAGE_SIZE) as usize;
+        Json(leaderboard.top(limit).await)
+    }
+
+    /// Get Random Playlist
+    ///
+    /// Get a single public playlist picked at random.
+    ///
+    /// NSFW playlists are excluded unless `include_nsfw` is set by an age
+    /// verified user.
+    #[oai(path = "/playlists/random", method = "get", tag = "ApiTags::Playlists")]
+    pub async fn get_random_playlist(
+        &self,
+        include_nsfw: Query<Option<bool>>,
+        session: Data<&Session>,
+        token: OptionalTokenBearer,
+    ) -> Result<JsonResponse<Playlist>> {
+        let include_nsfw = include_nsfw.0.unwrap_or(false)
+            && user_info::can_view_nsfw_for_token(&session, token.0.as_deref()).await?;
+
+        let playlist = discover::random_playlist(&session, include_nsfw).await?;
+
+        match playlist {
+            None => Ok(JsonResponse::not_found("There are no public playlists.")),
+            Some(playlist) => Ok(JsonResponse::ok(playlist)),
+        }
+    }
+
+    /// Get My Playlists
+    ///
+    /// Get all playlists owned by the user, including private playlists.
+    #[oai(path = "/playlists/mine", method = "get", tag = "ApiTags::Playlists")]
+    pub async fn get_my_playlists(
+        &self,
+        session: Data<&Session>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Vec<Playlist>>> {
+        match playlist_info::get_playlists_for_token(&session, &token.0.token, None, None).await? {
+            None => Ok(JsonResponse::unauthorized()),
+            Some(page) => Ok(JsonResponse::ok(page.playlists)),
+        }
+    }
+
+    /// Get My Playlist Entries
+    ///
+    /// Get a page of the playlist entries owned by the user, including private
+    /// entries. The returned `next_cursor` can be passed back as the `cursor`
+    /// to get the following page, the url of the next page is also given in
+    /// the `Link` header.
+    #[oai(path = "/entries/mine", method = "get", tag = "ApiTags::Playlists")]
+    pub async fn get_my_entries(
+        &self,
+        req: &Request,
+        #[oai(validator(minimum(value = "1"), maximum(value = "100")))]
+        limit: Query<Option<i32>>,
+        cursor: Query<Option<String>>,
+        session: Data<&Session>,
+        token: TokenBearer,
+    ) -> Result<PageResponse<playlist_info::EntryPage>> {
+        let cursor = utils::parse_cursor(cursor.0.as_deref())?;
+        let limit = limit.0.unwrap_or(DEFAULT_PAGE_SIZE);
+        match playlist_info::get_playlist_entries_for_token(&session, &token.0.token, Some(limit), cursor).await? {
+            None => Ok(PageResponse::unauthorized()),
+            Some(page) => {
+                let next_cursor = page.next_cursor.clone();
+                Ok(PageResponse::ok(req, page, next_cursor.as_deref()))
+            },
+        }
+    }
+
+    /// Get My Orphaned Playlist Entries
+    ///
+    /// Get a page of the playlist entries owned by the user which are not in
+    /// any of their playlists. The returned `next_cursor` and `Link` header
+    /// work the same as `GET /entries/mine`.
+    ///
+    /// Pages may contain fewer than `limit` entries even when there are more,
+    /// only a missing `next_cursor` means there are no more pages.
+    #[oai(path = "/entries/orphans", method = "get", tag = "ApiTags::Playlists")]
+    pub async fn get_my_orphan_entries(
+        &self,
+        req: &Request,
+        #[oai(validator(minimum(value = "1"), maximum(value = "100")))]
+        limit: Query<Option<i32>>,
+        cursor: Query<Option<String>>,
+        session: Data<&Session>,
+        token: TokenBearer,
+    ) -> Result<PageResponse<playlist_info::EntryPage>> {
+        let cursor = utils::parse_cursor(cursor.0.as_deref())?;
+        let limit = limit.0.unwrap_or(DEFAULT_PAGE_SIZE);
+        match playlist_info::get_orphan_entries_for_token(&session, &token.0.token, Some(limit), cursor).await? {
+            None => Ok(PageResponse::unauthorized()),
+            Some(page) => {
+                let next_cursor = page.next_cursor.clone();
+                Ok(PageResponse::ok(req, page, next_cursor.as_deref()))
+            },
+        }
+    }
+
+    /// Get My Playlists Containing Entry
+    ///
+    /// Get the playlists owned by the user which include the given entry in
+    /// their items.
+    #[oai(path = "/entries/playlists", method = "get", tag = "ApiTags::Playlists")]
+    pub async fn get_my_playlists_with_entry(
+        &self,
+        id: Query<Uuid>,
+        session: Data<&Session>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Vec<Playlist>>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        if entries::get_entry_by_id(&session, id.0).await?.is_none() {
+            return Ok(JsonResponse::not_found("Entry does not exist."))
+        }
+
+        let ids = playlist::get_playlists_referencing(&session, id.0).await?;
+        let playlists = playlist::get_playlists_with_ids(&session, &ids)
+            .await?
+            .into_iter()
+            .filter(|v| *v.owner_id == user_id)
+            .collect();
+
+        Ok(JsonResponse::ok(playlists))
+    }
+
+    /// Superuser Remove Playlist
+    ///
+    /// Forcefully removes a playlist by a superuser.
+    ///
+    /// The playlist can be restored with `POST /playlists/restore` unless `hard`
+    /// is set, in which case it is permanently deleted.
+    ///
+    /// A 404 is returned if the playlist doesn't exist, or if it was already
+    /// removed when `hard` isn't set.
+    ///
+    /// The removal is recorded in the audit log.
+    #[oai(path = "/playlists/override", method = "delete", tag = "ApiTags::Playlists")]
+    pub async fn remove_playlist_superuser(
+        &self,
+        id: Query<Uuid>,
+        hard: Query<Option<bool>>,
+        token: SuperUserBearer,
+        session: Data<&Session>,
+    ) -> Result<JsonResponse<Value>> {
+        if hard.0.unwrap_or(false) {
+            if playlist::get_playlist_including_deleted(&session, id.0).await?.is_none() {
+                return Ok(JsonResponse::not_found("Playlist does not exist."))
+            }
+
+            playlist::purge_playlist(&session, id.0).await?;
+            audit::record(&session, &token.0, AuditAction::PurgePlaylist, id.0).await?;
+        } else {
+            if !playlist::remove_playlist(&session, id.0).await? {
+                return Ok(JsonResponse::not_found("Playlist does not exist."))
+            }
+            audit::record(&session, &token.0, AuditAction::RemovePlaylist, id.0).await?;
+        }
+
+        Ok(JsonResponse::Ok(Json(Value::Null)))
+    }
+
+    /// Superuser Restore Playlist
+    ///
+    /// Restores a playlist which was removed by its owner, a moderator or a
+    /// superuser. Permanently deleted playlists cannot be restored.
+    ///
+    /// The restore is recorded in the audit log.
+    #[oai(path = "/playlists/restore", method = "post", tag = "ApiTags::Playlists")]
+    pub async fn restore_playlist_superuser(
+        &self,
+        id: Query<Uuid>,
+        token: SuperUserBearer,
+        session: Data<&Session>,
+    ) -> Result<JsonResponse<Playlist>> {
+        let mut playlist = match playlist::get_playlist_including_deleted(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(v) => v,
+        };
+
+        if playlist.deleted_at.is_none() {
+            return Ok(JsonResponse::bad_request("This playlist has not been removed."))
+        }
+
+        playlist::restore_playlist(&session, &playlist).await?;
+        audit::record(&session, &token.0, AuditAction::RestorePlaylist, id.0).await?;
+        playlist.deleted_at = None;
+
+        Ok(JsonResponse::ok(playlist))
+    }
+
+    /// Feature Playlist
+    ///
+    /// Adds a playlist to the featured playlists by a superuser, only public
+    /// playlists can be featured.
+    ///
+    /// The change is recorded in the audit log.
+    #[oai(path = "/playlists/feature", method = "post", tag = "ApiTags::Playlists")]
+    pub async fn feature_playlist(
+        &self,
+        id: Query<Uuid>,
+        token: SuperUserBearer,
+        session: Data<&Session>,
+    ) -> Result<JsonResponse<Value>> {
+        let playlist = match playlist::get_playlist_by_id(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(v) => v,
+        };
+
+        if !playlist.is_public {
+            return Ok(JsonResponse::bad_request("Only public playlists can be featured."))
+        }
+
+        playlist::set_playlist_featured(&session, playlist.id, true).await?;
+        audit::record(&session, &token.0, AuditAction::FeaturePlaylist, id.0).await?;
+
+        Ok(JsonResponse::ok(Value::Null))
+    }
+
+    /// Unfeature Playlist
+    ///
+    /// Removes a playlist from the featured playlists by a superuser.
+    ///
+    /// The change is recorded in the audit log.
+    #[oai(path = "/playlists/feature", method = "delete", tag = "ApiTags::Playlists")]
+    pub async fn unfeature_playlist(
+        &self,
+        id: Query<Uuid>,
+        token: SuperUserBearer,
+        session: Data<&Session>,
+    ) -> Result<JsonResponse<Value>> {
+        let playlist = match playlist::get_playlist_including_deleted(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(v) => v,
+        };
+
+        playlist::set_playlist_featured(&session, playlist.id, false).await?;
+        audit::record(&session, &token.0, AuditAction::UnfeaturePlaylist, id.0).await?;
+
+        Ok(JsonResponse::ok(Value::Null))
+    }
+
+    /// Get Featured Playlists
+    ///
+    /// Get the playlists picked by the Spooderfy team, only public playlists
+    /// are included.
+    #[oai(path = "/playlists/featured", method = "get", tag = "ApiTags::Playlists")]
+    pub async fn get_featured_playlists(
+        &self,
+        session: Data<&Session>,
+    ) -> Result<Json<Vec<Playlist>>> {
+        let playlists = playlist::get_featured_playlists(&session).await?;
+
+        Ok(Json(playlists))
+    }
+
+    /// Moderator Remove Playlist
+    ///
+    /// Removes a playlist by a moderator, e.g. after it has been reported.
+    ///
+    /// Superusers are implicitly moderators.
+    #[oai(path = "/playlists/moderate", method = "delete", tag = "ApiTags::Playlists")]
+    pub async fn remove_playlist_moderator(
+        &self,
+        id: Query<Uuid>,
+        token: ModeratorBearer,
+        session: Data<&Session>,
+    ) -> Result<JsonResponse<Value>> {
+        if !playlist::remove_playlist(&session, id.0).await? {
+            return Ok(JsonResponse::not_found("Playlist does not exist."))
+        }
+
+        match token.0 {
+            None => info!("playlist {} removed by a superuser", id.0),
+            Some(user_id) => info!("playlist {} removed by moderator {}", id.0, user_id),
+        }
+
+        Ok(JsonResponse::Ok(Json(Value::Null)))
+    }
+
+    /// Superuser Remove Entry
+    ///
+    /// Forcefully removes a playlist entry by a superuser.
+    ///
+    /// If `hard` is set the entry is permanently deleted rather than only
+    /// marked as removed, it is removed from any playlists containing it first.
+    ///
+    /// A 404 is returned if the entry doesn't exist, or if it was already
+    /// removed when `hard` isn't set.
+    ///
+    /// The removal is recorded in the audit log.
+    #[oai(path = "/entries/override", method = "delete", tag = "ApiTags::Playlists")]
+    pub async fn remove_entry_superuser(
+        &self,
+        id: Query<Uuid>,
+        hard: Query<Option<bool>>,
+        token: SuperUserBearer,
+        session: Data<&Session>,
+    ) -> Result<JsonResponse<Value>> {
+        if hard.0.unwrap_or(false) {
+            if entries::get_entry_including_deleted(&session, id.0).await?.is_none() {
+                return Ok(JsonResponse::not_found("Entry does not exist."))
+            }
+
+            entries::purge_entry(&session, id.0).await?;
+            audit::record(&session, &token.0, AuditAction::PurgeEntry, id.0).await?;
+        } else {
+            if !entries::remove_entry(&session, id.0).await? {
+                return Ok(JsonResponse::not_found("Entry does not exist."))
+            }
+            audit::record(&session, &token.0, AuditAction::RemoveEntry, id.0).await?;
+        }
+
+        Ok(JsonResponse::Ok(Json(Value::Null)))
+    }
+
+    /// Delete Playlist
+    ///
+    /// Delete a specific playlist providing the user owns the playlist.
+    ///
+    /// Private playlists owned by someone else return the same 404 as a playlist
+    /// which doesn't exist so their existence isn't revealed, public playlists
+    /// return a 403.
+    #[oai(path = "/playlists", method = "delete", tag = "ApiTags::Playlists")]
+    pub async fn delete_playlist(
+        &self,
+        id: Query<Uuid>,
+        session: Data<&Session>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Value>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let playlist = match playlist::get_playlist_by_id(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(playlist) => playlist,
+        };
+
+        if *playlist.owner_id != user_id {
+            return Ok(deny_playlist_access(&playlist, "Playlist does not exist."))
+        }
+
+        if !playlist::remove_playlist(&session, playlist.id).await? {
+            return Ok(JsonResponse::not_found("Playlist does not exist."))
+        }
+
+        Ok(JsonResponse::ok(Value::Null))
+    }
+
+    /// Delete Playlist Entry
+    ///
+    /// Delete a specific entry providing the user owns the entry.
+    ///
+    /// Entries which are still in any playlists cannot be deleted, instead a 409
+    /// is returned listing the `playlists` which contain it. These must remove the
+    /// entry first.
+    #[oai(path = "/entries", method = "delete", tag = "ApiTags::Playlists")]
+    pub async fn delete_playlist_entry(
+        &self,
+        id: Query<Uuid>,
+        session: Data<&Session>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Value>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let entry = match entries::get_entry_by_id(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Entry does not exist.")),
+            Some(entry) => entry,
+        };
+
+        if *entry.owner_id != user_id {
+            return Ok(JsonResponse::forbidden())
+        }
+
+        let playlists = playlist::get_playlists_referencing(&session, entry.id).await?;
+        if !playlists.is_empty() {
+            return Ok(JsonResponse::still_referenced(
+                "This entry is still used by some playlists.",
+                &playlists,
+            ))
+        }
+
+        if !entries::remove_entry(&session, entry.id).await? {
+            return Ok(JsonResponse::not_found("Entry does not exist."))
+        }
+
+        Ok(JsonResponse::ok(Value::Null))
+    }
+
+    /// Delete Playlist Entries
+    ///
+    /// Delete up to 50 of the user's entries in a single batch, returning which
+    /// entries were `deleted` and which were `skipped` along with the reason.
+    ///
+    /// Entries which don't exist, are owned by someone else or are still in any
+    /// playlists are skipped rather than failing the request, the same as they
+    /// would be refused when deleted one at a time.
+    #[oai(path = "/entries/bulk", method = "delete", tag = "ApiTags::Playlists")]
+    pub async fn delete_entries_bulk(
+        &self,
+        payload: Json<Vec<Uuid>>,
+        session: Data<&Session>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<BulkDeleteSummary>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let mut seen = HashSet::new();
+        let ids: Vec<Uuid> = payload.0
+            .into_iter()
+            .filter(|id| seen.insert(*id))
+            .collect();
+
+        if ids.is_empty() {
+            return Ok(JsonResponse::bad_request("No entries given."))
+        }
+
+        if ids.len() > MAX_BULK_ENTRIES {
+            return Ok(JsonResponse::bad_request(format!(
+                "You cannot delete more than {} entries at once.",
+                MAX_BULK_ENTRIES,
+            )))
+        }
+
+        let mut found: HashMap<Uuid, PlaylistEntry> = entries::get_entries_with_ids(&session, &ids).await?
+            .into_iter()
+            .map(|v| (v.id, v))
+            .collect();
+
+        let mut summary = BulkDeleteSummary::default();
+        let mut removable = Vec::with_capacity(found.len());
+        for id in ids {
+            let reason = match found.remove(&id) {
+                None => SkipReason::NotFound,
+                Some(entry) if *entry.owner_id != user_id => SkipReason::NotOwned,
+                Some(entry) => {
+                    if playlist::get_playlists_referencing(&session, id).await?.is_empty() {
+                        removable.push(entry);
+                        continue
+                    }
+
+                    SkipReason::Referenced
+                },
+            };
+
+            summary.skipped.push(SkippedEntry { id, reason });
+        }
+
+        entries::remove_entries(&session, &removable).await?;
+        summary.deleted = removable.into_iter().map(|v| v.id).collect();
+
+        Ok(JsonResponse::ok(summary))
+    }
+
+    /// Upvote Playlist
+    ///
+    /// Upvote a specific playlist returning the newly updated playlist.
+    ///
+    /// Private playlists can't be voted on and return the same 404 as a
+    /// playlist which doesn't exist, before any other check, so their
+    /// existence isn't revealed.
+    ///
+    /// The new count is sent to everyone streaming the playlist's votes.
+    ///
+    /// If the user can't afford the vote a 400 with the `INSUFFICIENT_CREDITS`
+    /// code is returned along with their current `balance`.
+    #[oai(path = "/playlists/vote", method = "post", tag = "ApiTags::Playlists")]
+    pub async fn upvote_playlist(
+        &self,
+        id: Query<Uuid>,
+        session: Data<&Session>,
+        config: Data<&Config>,
+        updates: Data<&VoteUpdates>,
+        webhooks: Data<&Webhooks>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Playlist>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let mut playlist = match playlist::get_playlist_by_id(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(v) => v,
+        };
+
+        if !playlist.is_public {
+            return Ok(JsonResponse::not_found("Playlist does not exist."))
+        }
+
+        if *playlist.owner_id == user_id {
+            return Ok(JsonResponse::bad_request("You cannot vote on your own content."))
+        }
+
+        if let Some(cooldown) = playlist::get_vote_cooldown(&session, user_id, playlist.id).await? {
+            return Ok(JsonResponse::too_many_requests(
+                "You have already up-voted this playlist recently.",
+                cooldown,
+            ))
+        }
+
+        if !user_info::spend_user_credits(&session, user_id, config.vote_credit_cost).await? {
+            let balance = user_info::get_user_vote_credits(&session, user_id).await?;
+            return Ok(JsonResponse::insufficient_credits(balance, config.vote_credit_cost))
+        }
+
+        // A concurrent vote by the same user can get in after the cooldown
+        // check, in which case this one is refunded.
+        let votes = match playlist::upvote_playlist(&session, user_id, &playlist, config.vote_cooldown_secs).await {
+            Ok(Some(votes)) => votes,
+            Ok(None) => {
+                user_info::adjust_user_credits(&session, user_id, config.vote_credit_cost).await?;
+                let cooldown = playlist::get_vote_cooldown(&session, user_id, playlist.id).await?;
+                return Ok(JsonResponse::too_many_requests(
+                    "You have already up-voted this playlist recently.",
+                    cooldown.unwrap_or(0),
+                ))
+            },
+            Err(e) => {
+                user_info::adjust_user_credits(&session, user_id, config.vote_credit_cost).await?;
+                return Err(e.into())
+            },
+        };
+
+        if let Err(e) = trending::record_vote(&session, playlist.id, utils::now(), 1).await {
+            warn!("failed to record trending vote for playlist {}: {}", playlist.id, e);
+        }
+
+        playlist.votes = votes;
+        metrics::record_vote("playlist");
+        updates.publish(playlist.id, playlist.votes);
+        webhooks.votes_changed(playlist.id, playlist.votes);
+
+        Ok(JsonResponse::ok(playlist))
+    }
+
+    /// Upvote Playlist Entry
+    ///
+    /// Upvote a specific playlist entry returning the newly updated entry.
+    ///
+    /// If the user can't afford the vote a 400 with the `INSUFFICIENT_CREDITS`
+    /// code is returned along with their current `balance`.
+    #[oai(path = "/entries/vote", method = "post", tag = "ApiTags::Playlists")]
+    pub async fn upvote_entry(
+        &self,
+        id: Query<Uuid>,
+        session: Data<&Session>,
+        config: Data<&Config>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<PlaylistEntry>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let mut entry = match entries::get_entry_by_id(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Entry does not exist.")),
+            Some(v) => v,
+        };
+
+        if *entry.owner_id == user_id {
+            return Ok(JsonResponse::bad_request("You cannot vote on your own content."))
+        }
+
+        if let Some(cooldown) = entries::get_vote_cooldown(&session, user_id, entry.id).await? {
+            return Ok(JsonResponse::too_many_requests(
+                "You have already up-voted this entry recently.",
+                cooldown,
+            ))
+        }
+
+        if !user_info::spend_user_credits(&session, user_id, config.vote_credit_cost).await? {
+            let balance = user_info::get_user_vote_credits(&session, user_id).await?;
+            return Ok(JsonResponse::insufficient_credits(balance, config.vote_credit_cost))
+        }
+
+        let votes = match entries::upvote_playlist(&session, user_id, &entry, config.vote_cooldown_secs).await {
+            Ok(Some(votes)) => votes,
+            Ok(None) => {
+                user_info::adjust_user_credits(&session, user_id, config.vote_credit_cost).await?;
+                let cooldown = entries::get_vote_cooldown(&session, user_id, entry.id).await?;
+                return Ok(JsonResponse::too_many_requests(
+                    "You have already up-voted this entry recently.",
+                    cooldown.unwrap_or(0),
+                ))
+            },
+            Err(e) => {
+                user_info::adjust_user_credits(&session, user_id, config.vote_credit_cost).await?;
+                return Err(e.into())
+            },
+        };
+
+        entry.votes = votes;
+        metrics::record_vote("entry");
+
+        Ok(JsonResponse::ok(entry))
+    }
+
+    /// Remove Playlist Vote
+    ///
+    /// Removes the user's upvote from a specific playlist, refunding the credit
+    /// and returning the newly updated playlist.
+    ///
+    /// The new count is sent to everyone streaming the playlist's votes.
+    #[oai(path = "/playlists/vote", method = "delete", tag = "ApiTags::Playlists")]
+    pub async fn remove_playlist_vote(
+        &self,
+        id: Query<Uuid>,
+        session: Data<&Session>,
+        config: Data<&Config>,
+        updates: Data<&VoteUpdates>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Playlist>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let mut playlist = match playlist::get_playlist_by_id(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(v) => v,
+        };
+
+        let voted_on = match playlist::get_vote_time(&session, user_id, playlist.id).await? {
+            None => return Ok(JsonResponse::bad_request("You have not up-voted this playlist.")),
+            Some(v) => v,
+        };
+
+        // Only the request which removes the vote refunds it.
+        let votes = match playlist::remove_playlist_vote(&session, user_id, &playlist).await? {
+            None => return Ok(JsonResponse::bad_request("You have not up-voted this playlist.")),
+            Some(v) => v,
+        };
+        user_info::adjust_user_credits(&session, user_id, config.vote_credit_cost).await?;
+
+        if let Err(e) = trending::record_vote(&session, playlist.id, voted_on, -1).await {
+            warn!("failed to remove trending vote for playlist {}: {}", playlist.id, e);
+        }
+
+        playlist.votes = votes;
+        updates.publish(playlist.id, playlist.votes);
+
+        Ok(JsonResponse::ok(playlist))
+    }
+
+    /// Remove Playlist Entry Vote
+    ///
+    /// Removes the user's upvote from a specific playlist entry, refunding the credit
+    /// and returning the newly updated entry.
+    #[oai(path = "/entries/vote", method = "delete", tag = "ApiTags::Playlists")]
+    pub async fn remove_entry_vote(
+        &self,
+        id: Query<Uuid>,
+        session: Data<&Session>,
+        config: Data<&Config>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<PlaylistEntry>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let mut entry = match entries::get_entry_by_id(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Entry does not exist.")),
+            Some(v) => v,
+        };
+
+        if !entries::has_user_voted(&session, user_id, entry.id).await? {
+            return Ok(JsonResponse::bad_request("You have not up-voted this entry."))
+        }
+
+        let votes = match entries::remove_entry_vote(&session, user_id, &entry).await? {
+            None => return Ok(JsonResponse::bad_request("You have not up-voted this entry.")),
+            Some(v) => v,
+        };
+        user_info::adjust_user_credits(&session, user_id, config.vote_credit_cost).await?;
+
+        entry.votes = votes;
+
+        Ok(JsonResponse::ok(entry))
+    }
+
+    /// Get Favorite Playlists
+    ///
+    /// Get the playlists the user has favorited, most recently favorited first.
+    ///
+    /// Favorites which have since been deleted or made private are left out.
+    #[oai(path = "/favorites", method = "get", tag = "ApiTags::Playlists")]
+    pub async fn get_favorites(
+        &self,
+        session: Data<&Session>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Vec<Playlist>>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let ids = favorites::get_favorite_ids(&session, user_id).await?;
+        let playlists = playlist::get_playlists_with_ids(&session, &ids).await?
+            .into_iter()
+            .filter(|v| v.is_public | (*v.owner_id == user_id))
+            .collect();
+
+        Ok(JsonResponse::ok(playlists))
+    }
+
+    /// Favorite Playlist
+    ///
+    /// Add a playlist to the user's favorites, favoriting a playlist which is
+    /// already a favorite does nothing.
+    ///
+    /// Private playlists owned by someone else return the same 404 as a playlist
+    /// which doesn't exist so their existence isn't revealed.
+    #[oai(path = "/favorites", method = "post", tag = "ApiTags::Playlists")]
+    pub async fn add_favorite(
+        &self,
+        id: Query<Uuid>,
+        session: Data<&Session>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Value>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let playlist = match playlist::get_playlist_by_id(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(v) => v,
+        };
+
+        if !playlist.is_public & (*playlist.owner_id != user_id) {
+            return Ok(deny_playlist_access(&playlist, "Playlist does not exist."))
+        }
+
+        favorites::add_favorite(&session, user_id, playlist.id).await?;
+
+        Ok(JsonResponse::ok(Value::Null))
+    }
+
+    /// Unfavorite Playlist
+    ///
+    /// Remove a playlist from the user's favorites.
+    #[oai(path = "/favorites", method = "delete", tag = "ApiTags::Playlists")]
+    pub async fn remove_favorite(
+        &self,
+        id: Query<Uuid>,
+        session: Data<&Session>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Value>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        favorites::remove_favorite(&session, user_id, id.0).await?;
+
+        Ok(JsonResponse::ok(Value::Null))
+    }
+
+    /// Create Playlist
+    ///
+    /// Creates a playlist from the given payload, returning the fully populated
+    /// playlist information (id, etc..).
+    ///
+    /// Note: This will filter out items to only include valid items. E.g.
+    /// Items that are not marked as public when the playlist is public will not be included.
+    /// If a playlist is *not* public then it will include entries that the user owns.
+    /// Duplicate items are removed, and the request is rejected with the list of
+    /// `invalid_items` if any items do not exist as entries.
+    ///
+    /// Any `new_entries` are created in the same batch as the playlist, so either
+    /// all of them are created or none are, and their ids are returned in
+    /// `created_entries`.
+    ///
+    /// If an `Idempotency-Key` is given and the user already created a playlist
+    /// with the same key in the last 24 hours, that playlist is returned instead
+    /// of creating another.
+    ///
+    /// If `unique_title` is set and the user already owns a playlist with the
+    /// same title, ignoring case and surrounding whitespace, a 409 is returned
+    /// with the existing playlist's `id` instead.
+    #[allow(clippy::too_many_arguments)]
+    #[oai(path = "/playlists", method = "post", tag = "ApiTags::Playlists")]
+    pub async fn create_playlist(
+        &self,
+        payload: Json<PlaylistCreationPayload>,
+        unique_title: Query<Option<bool>>,
+        #[oai(name = "Idempotency-Key", validator(max_length = 255))]
+        idempotency_key: Header<Option<String>>,
+        session: Data<&Session>,
+        config: Data<&Config>,
+        client: Data<&reqwest::Client>,
+        webhooks: Data<&Webhooks>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Playlist>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let idempotency_key = idempotency_key.0;
+        if let Some(key) = idempotency_key.as_deref() {
+            let existing = idempotency::get_idempotent_resource(
+                &session,
+                user_id,
+                IdempotentResource::Playlist,
+                key,
+            ).await?;
+
+            if let Some(id) = existing {
+                return Ok(replay_playlist(&session, id).await?)
+            }
+        }
+
+        if unique_title.0.unwrap_or(false) {
+            if let Some(id) = playlist::find_playlist_with_title(&session, user_id, &payload.0.title).await? {
+                return Ok(JsonResponse::already_exists("You already own a playlist with this title.", id))
+            }
+        }
+
+        let check = check_new_playlist(&session, &config, &client, user_id, &payload.0).await?;
+        match check.rejection {
+            None => {},
+            Some(Rejection::BadRequest(msg)) => return Ok(JsonResponse::bad_request(msg)),
+            Some(Rejection::InvalidItems(msg)) => {
+                return Ok(JsonResponse::invalid_items(msg, &check.invalid_items))
+            },
+        }
+
+        let created_at = utils::now();
+        let new_entries: Vec<PlaylistEntry> = payload.0.new_entries
+            .into_iter()
+            .map(|v| PlaylistEntry {
+                id: Uuid::new_v4(),
+                owner_id: JsSafeBigInt(user_id),
+                description: v.description.map(NormalizedString::into_inner),
+                is_public: v.is_public,
+                nsfw: v.nsfw,
+                ref_link: v.ref_link,
+                title: v.title.into_inner(),
+                votes: 0,
+                created_at: Some(created_at),
+                updated_at: Some(created_at),
+                has_voted: None,
+                deleted_at: None,
+            })
+            .collect();
+
+        let PlaylistCheck { tags, mut items, is_nsfw, .. } = check;
+        let created_entries: Vec<Uuid> = new_entries.iter().map(|v| v.id).collect();
+        items.extend(created_entries.iter().copied());
+
+        let playlist_id = Uuid::new_v4();
+        if let Some(key) = idempotency_key.as_deref() {
+            let reserved = idempotency::reserve_idempotency_key(
+                &session,
+                user_id,
+                IdempotentResource::Playlist,
+                key,
+                playlist_id,
+            ).await?;
+
+            if !reserved {
+                let existing = idempotency::get_idempotent_resource(
+                    &session,
+                    user_id,
+                    IdempotentResource::Playlist,
+                    key,
+                ).await?;
+
+                return match existing {
+                    None => Ok(JsonResponse::conflict(IDEMPOTENCY_IN_PROGRESS)),
+                    Some(id) => Ok(replay_playlist(&session, id).await?),
+                }
+            }
+        }
+
+        let result = async {
+            let banner = upload_banner(payload.0.banner).await?;
+
+            if new_entries.is_empty() {
+                let playlist = insert_playlist(
+                    &session,
+                    playlist_id,
+                    user_id,
+                    banner,
+                    payload.0.description.map(NormalizedString::into_inner),
+                    payload.0.is_public,
+                    items,
+                    is_nsfw,
+                    payload.0.title.into_inner(),
+                    tags,
+                    Some(created_at),
+                    created_at,
+                    true,
+                ).await?.ok_or_else(|| anyhow!("expected item in database after creation"))?;
+
+                playlist::update_entry_refs(&session, playlist.id, &[], &playlist.items).await?;
+
+                return Ok::<_, poem::Error>(playlist)
+            }
+
+            let mut playlist = insert_playlist_with_entries(
+                &session,
+                playlist_id,
+                user_id,
+                banner,
+                payload.0.description.map(NormalizedString::into_inner),
+                payload.0.is_public,
+                items,
+                is_nsfw,
+                payload.0.title.into_inner(),
+                tags,
+                created_at,
+                &new_entries,
+            ).await?.ok_or_else(|| anyhow!("expected item in database after creation"))?;
+            playlist.created_entries = Some(created_entries);
+
+            Ok(playlist)
+        }.await;
+
+        let playlist = match (result, idempotency_key.as_deref()) {
+            (Ok(playlist), _) => playlist,
+            (Err(e), None) => return Err(e),
+            (Err(e), Some(key)) => {
+                idempotency::release_idempotency_key(
+                    &session,
+                    user_id,
+                    IdempotentResource::Playlist,
+                    key,
+                ).await?;
+                return Err(e)
+            },
+        };
+
+        webhooks.playlist_created(playlist.id, user_id);
+
+        Ok(JsonResponse::ok(playlist))
+    }
+
+    /// Validate Playlist
+    ///
+    /// Runs every check `POST /playlists` would make on the payload without
+    /// creating anything, returning if it is `valid` along with the `detail` of
+    /// the first failed check.
+    ///
+    /// `invalid_items` lists the `items` which don't exist as entries and
+    /// `computed_nsfw` is what the playlist's `nsfw` would be set to.
+    #[oai(path = "/playlists/validate", method = "post", tag = "ApiTags::Playlists")]
+    pub async fn validate_playlist(
+        &self,
+        payload: Json<PlaylistCreationPayload>,
+        session: Data<&Session>,
+        config: Data<&Config>,
+        client: Data<&reqwest::Client>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<PlaylistValidation>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let check = check_new_playlist(&session, &config, &client, user_id, &payload.0).await?;
+        let detail = check.rejection.map(|v| match v {
+            Rejection::BadRequest(msg) | Rejection::InvalidItems(msg) => msg,
+        });
+
+        Ok(JsonResponse::ok(PlaylistValidation {
+            valid: detail.is_none(),
+            detail,
+            invalid_items: check.invalid_items,
+            computed_nsfw: check.is_nsfw,
+        }))
+    }
+
+    /// Import Playlists
+    ///
+    /// Recreates the playlists and entries from a `GET /users/export` bundle under
+    /// the user, returning the ids of the created playlists.
+    ///
+    /// Every playlist and entry is given a new id and playlist `items` which refer
+    /// to entries in the bundle are updated to match. Items referring to other
+    /// entries are kept if the user could add them to the playlist themselves.
+    ///
+    /// The whole bundle is validated before anything is created, at most 100
+    /// playlists and 1000 entries can be imported at once.
+    #[oai(path = "/playlists/import", method = "post", tag = "ApiTags::Playlists")]
+    pub async fn import_playlists(
+        &self,
+        payload: Json<ImportPayload>,
+        session: Data<&Session>,
+        config: Data<&Config>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Vec<Uuid>>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let ImportPayload { playlists, entries: imported_entries } = payload.0;
+        if playlists.len() > MAX_IMPORT_PLAYLISTS {
+            return Ok(JsonResponse::bad_request(format!(
+                "You cannot import more than {} playlists at once.",
+                MAX_IMPORT_PLAYLISTS,
+            )))
+        }
+
+        if imported_entries.len() > MAX_IMPORT_ENTRIES {
+            return Ok(JsonResponse::bad_request(format!(
+                "You cannot import more than {} entries at once.",
+                MAX_IMPORT_ENTRIES,
+            )))
+        }
+
+        for entry in imported_entries.iter() {
+            if let Err(msg) = check_ref_link(entry.ref_link.as_deref(), &config.allowed_link_hosts) {
+                return Ok(JsonResponse::bad_request(msg))
+            }
+        }
+
+        let owned = playlist::count_playlists_for_owner(&session, user_id).await?;
+        if owned + playlists.len() as i64 > config.max_playlists_per_user {
+            return Ok(JsonResponse::bad_request(format!(
+                "You cannot own more than {} playlists.",
+                config.max_playlists_per_user,
+            )))
+        }
+
+        let created_at = utils::now();
+        let mut remapped = HashMap::with_capacity(imported_entries.len());
+        let new_entries: Vec<PlaylistEntry> = imported_entries
+            .into_iter()
+            .map(|v| {
+                let id = Uuid::new_v4();
+                remapped.insert(v.id, id);
+
+                PlaylistEntry {
+                    id,
+                    owner_id: JsSafeBigInt(user_id),
+                    description: v.description.map(NormalizedString::into_inner),
+                    is_public: v.is_public,
+                    nsfw: v.nsfw,
+                    ref_link: v.ref_link,
+                    title: v.title.into_inner(),
+                    votes: 0,
+                    created_at: Some(created_at),
+                    updated_at: Some(created_at),
+                    has_voted: None,
+                    deleted_at: None,
+                }
+            })
+            .collect();
+
+        let mut seen = HashSet::new();
+        let external: Vec<Uuid> = playlists.iter()
+            .flat_map(|v| v.items.iter())
+            .filter(|v| !remapped.contains_key(*v) && seen.insert(**v))
+            .copied()
+            .collect();
+        let external = entries::get_entries_with_ids(&session, &external).await?;
+
+        let known: HashMap<Uuid, &PlaylistEntry> = new_entries.iter()
+            .chain(external.iter())
+            .map(|v| (v.id, v))
+            .collect();
+
+        let mut pending = Vec::with_capacity(playlists.len());
+        for mut imported in playlists {
+            let tags = match normalize_tags(std::mem::take(&mut imported.tags)) {
+                Err(msg) => return Ok(JsonResponse::bad_request(msg)),
+                Ok(tags) => tags,
+            };
+
+            let mut seen = HashSet::new();
+            let items: Vec<&PlaylistEntry> = imported.items.iter()
+                .map(|v| remapped.get(v).unwrap_or(v))
+                .filter(|v| seen.insert(**v))
+                .filter_map(|v| known.get(v).copied())
+                .filter(|v| is_valid_entry(user_id, imported.is_public, v))
+                .collect();
+
+            if items.is_empty() {
+                return Ok(JsonResponse::bad_request(format!(
+                    "The playlist {:?} has no valid playlist entries.",
+                    imported.title,
+                )))
+            }
+
+            if items.len() > MAX_PLAYLIST_ITEMS {
+                return Ok(JsonResponse::bad_request(format!(
+                    "A playlist cannot contain more than {} items.",
+                    MAX_PLAYLIST_ITEMS,
+                )))
+            }
+
+            let is_nsfw = items.iter().any(|v| v.nsfw);
+            let items: Vec<Uuid> = items.into_iter().map(|v| v.id).collect();
+            pending.push((imported, items, is_nsfw, tags));
+        }
+
+        if !new_entries.is_empty() {
+            entries::insert_entries(&session, &new_entries).await?;
+        }
+
+        let mut created = Vec::with_capacity(pending.len());
+        for (imported, items, is_nsfw, tags) in pending {
+            let playlist_id = Uuid::new_v4();
+            insert_playlist(
+                &session,
+                playlist_id,
+                user_id,
+                None,
+                imported.description.map(NormalizedString::into_inner),
+                imported.is_public,
+                items.clone(),
+                is_nsfw,
+                imported.title.into_inner(),
+                tags,
+                Some(created_at),
+                created_at,
+                false,
+            ).await?;
+
+            playlist::update_entry_refs(&session, playlist_id, &[], &items).await?;
+            created.push(playlist_id);
+        }
+
+        Ok(JsonResponse::ok(created))
+    }
+
+    /// Clone Playlist
+    ///
+    /// Copies a public playlist, or one owned by the user, into a new private
+    /// playlist owned by the user, returning the new playlist.
+    ///
+    /// Private playlists owned by someone else return the same 404 as a playlist
+    /// which doesn't exist so their existence isn't revealed.
+    ///
+    /// Note: Items the user would not be able to add themselves, i.e. private
+    /// entries owned by someone else, are not copied.
+    #[oai(path = "/playlists/clone", method = "post", tag = "ApiTags::Playlists")]
+    pub async fn clone_playlist(
+        &self,
+        id: Query<Uuid>,
+        session: Data<&Session>,
+        config: Data<&Config>,
+        webhooks: Data<&Webhooks>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Playlist>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let source = match playlist::get_playlist_by_id(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(v) => v,
+        };
+
+        if !source.is_public & (*source.owner_id != user_id) {
+            return Ok(deny_playlist_access(&source, "Playlist does not exist."))
+        }
+
+        let owned = playlist::count_playlists_for_owner(&session, user_id).await?;
+        if owned >= config.max_playlists_per_user {
+            return Ok(JsonResponse::bad_request(format!(
+                "You cannot own more than {} playlists.",
+                config.max_playlists_per_user,
+            )))
+        }
+
+        let entries = entries::get_entries_with_ids(&session, &source.items).await?;
+        let entries: Vec<PlaylistEntry> = entries.into_iter()
+            .filter(|v| v.is_public | (*v.owner_id == user_id))
+            .collect();
+        let is_nsfw = entries.iter().any(|v| v.nsfw);
+        let valid: HashSet<Uuid> = entries.into_iter().map(|v| v.id).collect();
+        let items: Vec<Uuid> = source.items.into_iter()
+            .filter(|v| valid.contains(v))
+            .collect();
+
+        if items.is_empty() {
+            return Ok(JsonResponse::bad_request("No valid playlists entries selected."))
+        }
+
+        // The title is truncated so the suffix stays within the title length limit.
+        let title: String = source.title.chars().take(MAX_TITLE_LENGTH - CLONE_SUFFIX.len()).collect();
+        let created_at = utils::now();
+        let playlist = insert_playlist(
+            &session,
+            Uuid::new_v4(),
+            user_id,
+            source.banner,
+            source.description,
+            false,
+            items,
+            is_nsfw,
+            format!("{}{}", title, CLONE_SUFFIX),
+            source.tags,
+            Some(created_at),
+            created_at,
+            true,
+        ).await?.ok_or_else(|| anyhow!("expected item in database after creation"))?;
+
+        playlist::update_entry_refs(&session, playlist.id, &[], &playlist.items).await?;
+        webhooks.playlist_created(playlist.id, user_id);
+
+        Ok(JsonResponse::ok(playlist))
+    }
+
+    /// Create Playlist Entry
     ///
     /// Creates a playlist entry from the given payload, returning the fully populated
     /// playlist entry (id, etc..).
+    ///
+    /// If an `Idempotency-Key` is given and the user already created an entry
+    /// with the same key in the last 24 hours, that entry is returned instead
+    /// of creating another.
     #[oai(path = "/entries", method = "post", tag = "ApiTags::Playlists")]
     pub async fn create_entry(
         &self,
         payload: Json<EntryCreationPayload>,
+        #[oai(name = "Idempotency-Key", validator(max_length = 255))]
+        idempotency_key: Header<Option<String>>,
         session: Data<&Session>,
+        config: Data<&Config>,
         token: TokenBearer,
     ) -> Result<JsonResponse<PlaylistEntry>> {
         let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
@@ -320,22 +2065,127 @@ impl PlaylistsApi {
             Some(v) => v,
         };
 
+        if let Err(msg) = check_ref_link(payload.0.ref_link.as_deref(), &config.allowed_link_hosts) {
+            return Ok(JsonResponse::bad_request(msg))
+        }
+
         let entry_id = Uuid::new_v4();
-        let entry = insert_entry(
+        if let Some(key) = idempotency_key.0.as_deref() {
+            let reserved = idempotency::reserve_idempotency_key(
+                &session,
+                user_id,
+                IdempotentResource::Entry,
+                key,
+                entry_id,
+            ).await?;
+
+            if !reserved {
+                let existing = idempotency::get_idempotent_resource(
+                    &session,
+                    user_id,
+                    IdempotentResource::Entry,
+                    key,
+                ).await?;
+
+                return match existing {
+                    None => Ok(JsonResponse::conflict(IDEMPOTENCY_IN_PROGRESS)),
+                    Some(id) => match entries::get_entry_by_id(&session, id).await? {
+                        None => Ok(JsonResponse::conflict(IDEMPOTENCY_IN_PROGRESS)),
+                        Some(entry) => Ok(JsonResponse::ok(entry)),
+                    },
+                }
+            }
+        }
+
+        let result = insert_entry(
             &session,
             entry_id,
             user_id,
-            payload.0.description,
+            payload.0.description.map(NormalizedString::into_inner),
             payload.0.is_public,
             payload.0.nsfw,
             payload.0.ref_link,
-            payload.0.title,
-            true,
-        ).await?.ok_or_else(|| anyhow!("expected item in database after creation"))?;
+            payload.0.title.into_inner(),
+            utils::now(),
+        ).await;
+
+        let entry = match (result, idempotency_key.0.as_deref()) {
+            (Ok(entry), _) => entry.ok_or_else(|| anyhow!("expected item in database after creation"))?,
+            (Err(e), None) => return Err(e.into()),
+            (Err(e), Some(key)) => {
+                idempotency::release_idempotency_key(
+                    &session,
+                    user_id,
+                    IdempotentResource::Entry,
+                    key,
+                ).await?;
+                return Err(e.into())
+            },
+        };
 
         Ok(JsonResponse::ok(entry))
     }
 
+    /// Create Playlist Entries
+    ///
+    /// Creates up to 50 playlist entries from the given payloads in a single batch,
+    /// returning the fully populated playlist entries in the same order.
+    ///
+    /// Note: If any payload is invalid no entries will be created.
+    #[oai(path = "/entries/bulk", method = "post", tag = "ApiTags::Playlists")]
+    pub async fn create_entries_bulk(
+        &self,
+        payload: Json<Vec<EntryCreationPayload>>,
+        session: Data<&Session>,
+        config: Data<&Config>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Vec<PlaylistEntry>>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        if payload.0.is_empty() {
+            return Ok(JsonResponse::bad_request("No entries given."))
+        }
+
+        if payload.0.len() > MAX_BULK_ENTRIES {
+            return Ok(JsonResponse::bad_request(format!(
+                "You cannot create more than {} entries at once.",
+                MAX_BULK_ENTRIES,
+            )))
+        }
+
+        for entry in payload.0.iter() {
+            if let Err(msg) = check_ref_link(entry.ref_link.as_deref(), &config.allowed_link_hosts) {
+                return Ok(JsonResponse::bad_request(msg))
+            }
+        }
+
+        let created_at = utils::now();
+        let entries: Vec<PlaylistEntry> = payload.0
+            .into_iter()
+            .map(|v| PlaylistEntry {
+                id: Uuid::new_v4(),
+                owner_id: JsSafeBigInt(user_id),
+                description: v.description.map(NormalizedString::into_inner),
+                is_public: v.is_public,
+                nsfw: v.nsfw,
+                ref_link: v.ref_link,
+                title: v.title.into_inner(),
+                votes: 0,
+                created_at: Some(created_at),
+                updated_at: Some(created_at),
+                has_voted: None,
+                deleted_at: None,
+            })
+            .collect();
+
+        entries::insert_entries(&session, &entries).await?;
+
+        Ok(JsonResponse::ok(entries))
+    }
+
     /// Update Playlist
     ///
     /// Updates a playlist from the given payload, returning the updated, fully populated
@@ -345,12 +2195,335 @@ impl PlaylistsApi {
     /// Items that are not marked as public when the playlist is public will not be included.
     /// Items that do no exist already as entries will not be included.
     /// If a playlist is *not* public then it will include entries that the user owns.
+    ///
+    /// `new_entries` can only be given when creating a playlist, a 400 is returned
+    /// if any are given here. Create them with `POST /entries/bulk` first instead.
     #[oai(path = "/playlists", method = "put", tag = "ApiTags::Playlists")]
     pub async fn update_playlist(
         &self,
         id: Query<Uuid>,
         payload: Json<PlaylistCreationPayload>,
         session: Data<&Session>,
+        config: Data<&Config>,
+        client: Data<&reqwest::Client>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Playlist>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let mut playlist = match playlist::get_playlist_by_id(&session, id.0).await? {
+            Some(p) => p,
+            None => return Ok(JsonResponse::not_found("No playlist exists with this id.")),
+        };
+
+        if *playlist.owner_id != user_id {
+            return Ok(deny_playlist_access(&playlist, "No playlist exists with this id."))
+        }
+
+        if !payload.0.new_entries.is_empty() {
+            return Ok(JsonResponse::bad_request(
+                "Entries cannot be created when updating a playlist, create them first.",
+            ))
+        }
+
+        let tags = match normalize_tags(payload.0.tags) {
+            Err(msg) => return Ok(JsonResponse::bad_request(msg)),
+            Ok(tags) => tags,
+        };
+
+        if let Err(msg) = check_banner(&client, &config, payload.0.banner.as_deref()).await {
+            return Ok(JsonResponse::bad_request(msg))
+        }
+
+        let items = entries::get_entries_with_ids(&session, &payload.0.items).await?;
+
+        let is_nsfw = items.iter().any(|v|  v.nsfw);
+        let items = filter_valid_entries(user_id, payload.0.is_public, items);
+
+        if items.is_empty() {
+            return Ok(JsonResponse::bad_request("No valid playlists entries selected."))
+        }
+
+        let previous_items = playlist.items.clone();
+        let previous_listing = stats::listed_tags(&playlist).map(|v| v.to_vec());
+        playlist.banner = upload_banner(payload.0.banner).await?;
+        playlist.items = items;
+        playlist.title = payload.0.title.into_inner();
+        playlist.description = payload.0.description.map(NormalizedString::into_inner);
+        playlist.is_public = payload.0.is_public;
+        playlist.nsfw = is_nsfw;
+        playlist.tags = tags;
+
+        if !playlist::save_playlist(&session, &mut playlist).await? {
+            return Ok(JsonResponse::conflict(PLAYLIST_MODIFIED))
+        }
+
+        playlist::update_entry_refs(&session, playlist.id, &previous_items, &playlist.items).await?;
+        stats::listing_changed(&session, playlist.id, previous_listing.as_deref(), stats::listed_tags(&playlist)).await?;
+
+        Ok(JsonResponse::ok(playlist))
+    }
+
+    /// Edit Playlist
+    ///
+    /// Edits the metadata of a playlist, returning the updated, fully populated
+    /// playlist information.
+    ///
+    /// Only the fields provided are changed, explicitly passing `null` for the
+    /// `banner` or `description` will clear them.
+    ///
+    /// Note: Making a private playlist public will remove any items which are not
+    /// themselves public.
+    ///
+    /// The `version` the changes were made to must be given, if the playlist has
+    /// been edited since then a 409 is returned and it should be fetched again.
+    #[oai(path = "/playlists", method = "patch", tag = "ApiTags::Playlists")]
+    pub async fn patch_playlist(
+        &self,
+        id: Query<Uuid>,
+        payload: Json<PatchPayload<PlaylistUpdatePayload>>,
+        session: Data<&Session>,
+        config: Data<&Config>,
+        client: Data<&reqwest::Client>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Playlist>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let mut playlist = match playlist::get_playlist_by_id(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(playlist) => playlist,
+        };
+
+        if !collaborators::can_edit(&session, &playlist, user_id).await? {
+            return Ok(deny_playlist_access(&playlist, "Playlist does not exist."))
+        }
+
+        if payload.0.inner.version != playlist.version {
+            return Ok(JsonResponse::conflict(PLAYLIST_MODIFIED))
+        }
+
+        if let Err(msg) = check_banner(&client, &config, payload.0.inner.banner.as_deref()).await {
+            return Ok(JsonResponse::bad_request(msg))
+        }
+
+        let previous_items = playlist.items.clone();
+        let previous_listing = stats::listed_tags(&playlist).map(|v| v.to_vec());
+        let clear_banner = payload.0.is_cleared("banner");
+        let clear_description = payload.0.is_cleared("description");
+        let update = payload.0.inner;
+
+        if let Some(is_public) = update.is_public {
+            if is_public & !playlist.is_public {
+                let items = entries::get_entries_with_ids(&session, &playlist.items).await?;
+                let valid: HashSet<Uuid> = filter_valid_entries(*playlist.owner_id, is_public, items)
+                    .into_iter()
+                    .collect();
+
+                playlist.items.retain(|v| valid.contains(v));
+
+                if playlist.items.is_empty() {
+                    return Ok(JsonResponse::bad_request("No valid playlists entries selected."))
+                }
+            }
+
+            playlist.is_public = is_public;
+        }
+
+        if update.banner.is_some() {
+            playlist.banner = upload_banner(update.banner).await?;
+        } else if clear_banner {
+            playlist.banner = None;
+        }
+
+        if update.description.is_some() | clear_description {
+            playlist.description = update.description.map(NormalizedString::into_inner);
+        }
+
+        if let Some(title) = update.title {
+            playlist.title = title.into_inner();
+        }
+
+        if let Some(tags) = update.tags {
+            playlist.tags = match normalize_tags(tags) {
+                Err(msg) => return Ok(JsonResponse::bad_request(msg)),
+                Ok(tags) => tags,
+            };
+        }
+
+        if !playlist::save_playlist(&session, &mut playlist).await? {
+            return Ok(JsonResponse::conflict(PLAYLIST_MODIFIED))
+        }
+        playlist::update_entry_refs(&session, playlist.id, &previous_items, &playlist.items).await?;
+        stats::listing_changed(&session, playlist.id, previous_listing.as_deref(), stats::listed_tags(&playlist)).await?;
+
+        Ok(JsonResponse::ok(playlist))
+    }
+
+    /// Add Playlist Collaborator
+    ///
+    /// Lets another user edit the playlist, collaborators can change its details
+    /// and add, remove or reorder its items but only the owner can delete it.
+    ///
+    /// Only the owner can add collaborators.
+    #[oai(path = "/playlists/collaborators", method = "post", tag = "ApiTags::Playlists")]
+    pub async fn add_playlist_collaborator(
+        &self,
+        id: Query<Uuid>,
+        user: Query<JsSafeBigInt>,
+        session: Data<&Session>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Value>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let playlist = match playlist::get_playlist_by_id(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(playlist) => playlist,
+        };
+
+        if *playlist.owner_id != user_id {
+            return Ok(deny_playlist_access(&playlist, "Playlist does not exist."))
+        }
+
+        if *user.0 == user_id {
+            return Ok(JsonResponse::bad_request("You cannot add yourself as a collaborator."))
+        }
+
+        if user_info::get_user_from_id(&session, *user.0).await?.is_none() {
+            return Ok(JsonResponse::not_found("This user does not exist."))
+        }
+
+        collaborators::add_collaborator(&session, playlist.id, *user.0).await?;
+
+        Ok(JsonResponse::ok(Value::Null))
+    }
+
+    /// Add Playlist Items
+    ///
+    /// Appends the given entries to the end of a playlist, returning the updated
+    /// playlist.
+    ///
+    /// Note: This follows the same filtering rules as creating a playlist, items
+    /// which are invalid or already in the playlist will not be added.
+    #[oai(path = "/playlists/items", method = "post", tag = "ApiTags::Playlists")]
+    pub async fn add_playlist_items(
+        &self,
+        id: Query<Uuid>,
+        items: Json<Vec<Uuid>>,
+        session: Data<&Session>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Playlist>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let mut playlist = match playlist::get_playlist_by_id(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(playlist) => playlist,
+        };
+
+        if !collaborators::can_edit(&session, &playlist, user_id).await? {
+            return Ok(deny_playlist_access(&playlist, "Playlist does not exist."))
+        }
+
+        let entries = entries::get_entries_with_ids(&session, &items.0).await?;
+        let valid: HashSet<Uuid> = filter_valid_entries(*playlist.owner_id, playlist.is_public, entries)
+            .into_iter()
+            .collect();
+
+        let previous_items = playlist.items.clone();
+        let mut existing: HashSet<Uuid> = playlist.items.iter().copied().collect();
+        let num_items = playlist.items.len();
+        for item in items.0 {
+            if valid.contains(&item) & existing.insert(item) {
+                playlist.items.push(item);
+            }
+        }
+
+        if playlist.items.len() == num_items {
+            return Ok(JsonResponse::bad_request("No valid playlists entries selected."))
+        }
+
+        if playlist.items.len() > MAX_PLAYLIST_ITEMS {
+            return Ok(JsonResponse::bad_request(format!(
+                "A playlist cannot contain more than {} items.",
+                MAX_PLAYLIST_ITEMS,
+            )))
+        }
+
+        let entries = entries::get_entries_with_ids(&session, &playlist.items).await?;
+        playlist.nsfw = entries.iter().any(|v| v.nsfw);
+
+        if !playlist::save_playlist(&session, &mut playlist).await? {
+            return Ok(JsonResponse::conflict(PLAYLIST_MODIFIED))
+        }
+        playlist::update_entry_refs(&session, playlist.id, &previous_items, &playlist.items).await?;
+
+        Ok(JsonResponse::ok(playlist))
+    }
+
+    /// Remove Playlist Item
+    ///
+    /// Removes a single entry from a playlist, returning the updated playlist.
+    #[oai(path = "/playlists/items", method = "delete", tag = "ApiTags::Playlists")]
+    pub async fn remove_playlist_item(
+        &self,
+        id: Query<Uuid>,
+        item_id: Query<Uuid>,
+        session: Data<&Session>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Playlist>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let mut playlist = match playlist::get_playlist_by_id(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(playlist) => playlist,
+        };
+
+        if !collaborators::can_edit(&session, &playlist, user_id).await? {
+            return Ok(deny_playlist_access(&playlist, "Playlist does not exist."))
+        }
+
+        if !playlist.items.contains(&item_id.0) {
+            return Ok(JsonResponse::bad_request("This entry is not in the playlist."))
+        }
+
+        let previous_items = playlist.items.clone();
+        playlist.items.retain(|v| *v != item_id.0);
+
+        let entries = entries::get_entries_with_ids(&session, &playlist.items).await?;
+        playlist.nsfw = entries.iter().any(|v| v.nsfw);
+
+        if !playlist::save_playlist(&session, &mut playlist).await? {
+            return Ok(JsonResponse::conflict(PLAYLIST_MODIFIED))
+        }
+        playlist::update_entry_refs(&session, playlist.id, &previous_items, &playlist.items).await?;
+
+        Ok(JsonResponse::ok(playlist))
+    }
+
+    /// Reorder Playlist Items
+    ///
+    /// Sets the order of the items in a playlist, returning the updated playlist.
+    ///
+    /// The given order must contain exactly the items already in the playlist.
+    #[oai(path = "/playlists/items/order", method = "put", tag = "ApiTags::Playlists")]
+    pub async fn reorder_playlist_items(
+        &self,
+        id: Query<Uuid>,
+        order: Json<Vec<Uuid>>,
+        session: Data<&Session>,
         token: TokenBearer,
     ) -> Result<JsonResponse<Playlist>> {
         let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
@@ -358,60 +2531,102 @@ impl PlaylistsApi {
             Some(v) => v,
         };
 
-        let mut playlist = match playlist::get_playlist_by_id(&session, id.0.clone()).await? {
+        let mut playlist = match playlist::get_playlist_by_id(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Playlist does not exist.")),
+            Some(playlist) => playlist,
+        };
+
+        if !collaborators::can_edit(&session, &playlist, user_id).await? {
+            return Ok(deny_playlist_access(&playlist, "Playlist does not exist."))
+        }
+
+        let mut current = playlist.items.clone();
+        let mut requested = order.0.clone();
+        current.sort_unstable();
+        requested.sort_unstable();
+
+        if current != requested {
+            return Ok(JsonResponse::bad_request(
+                "The given order must contain exactly the items in the playlist.",
+            ))
+        }
+
+        playlist.items = order.0;
+        if !playlist::save_playlist(&session, &mut playlist).await? {
+            return Ok(JsonResponse::conflict(PLAYLIST_MODIFIED))
+        }
+
+        Ok(JsonResponse::ok(playlist))
+    }
+
+    /// Update Playlist Entry
+    ///
+    /// Updates a playlist entry from the given payload, returning the updated, fully populated
+    /// playlist entry (id, etc..).
+    ///
+    /// The entry's votes are kept. Changing the `nsfw` flag recomputes the flag of any
+    /// playlists containing this entry.
+    #[oai(path = "/entries", method = "put", tag = "ApiTags::Playlists")]
+    pub async fn update_entry(
+        &self,
+        id: Query<Uuid>,
+        payload: Json<EntryCreationPayload>,
+        session: Data<&Session>,
+        config: Data<&Config>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<PlaylistEntry>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let mut entry = match entries::get_entry_by_id(&session, id.0).await? {
             Some(p) => p,
-            None => return Ok(JsonResponse::bad_request("No playlist exists with this id.")),
+            None => return Ok(JsonResponse::not_found("No playlist entry exists with this id.")),
         };
 
-        if *playlist.owner_id != user_id {
+        if *entry.owner_id != user_id {
             return Ok(JsonResponse::forbidden())
         }
 
-        let items = entries::get_entries_with_ids(&session, payload.0.items).await?;
+        if let Err(msg) = check_ref_link(payload.0.ref_link.as_deref(), &config.allowed_link_hosts) {
+            return Ok(JsonResponse::bad_request(msg))
+        }
 
-        let is_nsfw = items.iter().any(|v|  v.nsfw);
-        let items = filter_valid_entries(user_id, payload.0.is_public, items);
+        let nsfw_changed = payload.0.nsfw != entry.nsfw;
 
-        if items.is_empty() {
-            return Ok(JsonResponse::bad_request("No valid playlists entries selected."))
-        }
+        entry.title = payload.0.title.into_inner();
+        entry.ref_link = payload.0.ref_link;
+        entry.is_public = payload.0.is_public;
+        entry.nsfw = payload.0.nsfw;
+        entry.description = payload.0.description.map(NormalizedString::into_inner);
 
-        insert_playlist(
-            &session,
-                id.0,
-                user_id,
-                payload.0.banner.clone(),
-                payload.0.description.clone(),
-                payload.0.is_public.clone(),
-                items.clone(),
-                is_nsfw.clone(),
-                payload.0.title.clone(),
-            false,
-        ).await?;
+        entries::save_entry(&session, &mut entry).await?;
 
-        playlist.items = items;
-        playlist.title = payload.0.title;
-        playlist.description = payload.0.description;
-        playlist.is_public = payload.0.is_public;
-        playlist.nsfw = is_nsfw;
-        playlist.banner = payload.0.banner;
+        if nsfw_changed {
+            refresh_playlists_nsfw(&session, entry.id).await?;
+        }
 
-        Ok(JsonResponse::ok(playlist))
+        Ok(JsonResponse::ok(entry))
     }
 
-    /// Update Playlist Entry
+    /// Edit Playlist Entry
     ///
-    /// Updates a playlist entry from the given payload, returning the updated, fully populated
-    /// playlist entry (id, etc..).
+    /// Edits the metadata of a playlist entry, returning the updated, fully populated
+    /// playlist entry.
     ///
-    /// NOTE: This won't update playlists that use the entry. Instead playlists will be
-    /// updated as and when requests (lazily).
-    #[oai(path = "/entries", method = "put", tag = "ApiTags::Playlists")]
-    pub async fn update_entry(
+    /// Only the fields provided are changed, explicitly passing `null` for the
+    /// `description` or `ref_link` will clear them.
+    ///
+    /// Changing the `nsfw` flag recomputes the flag of any playlists containing
+    /// this entry.
+    #[oai(path = "/entries", method = "patch", tag = "ApiTags::Playlists")]
+    pub async fn patch_entry(
         &self,
         id: Query<Uuid>,
-        payload: Json<EntryCreationPayload>,
+        payload: Json<PatchPayload<EntryUpdatePayload>>,
         session: Data<&Session>,
+        config: Data<&Config>,
         token: TokenBearer,
     ) -> Result<JsonResponse<PlaylistEntry>> {
         let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
@@ -419,48 +2634,428 @@ impl PlaylistsApi {
             Some(v) => v,
         };
 
-        let mut entry = match entries::get_entry_by_id(&session, id.0.clone()).await? {
-            Some(p) => p,
-            None => return Ok(JsonResponse::bad_request("No playlist entry exists with this id.")),
+        let mut entry = match entries::get_entry_by_id(&session, id.0).await? {
+            None => return Ok(JsonResponse::not_found("Entry does not exist.")),
+            Some(entry) => entry,
         };
 
         if *entry.owner_id != user_id {
             return Ok(JsonResponse::forbidden())
         }
 
-        insert_entry(
-            &session,
-            id.0,
-            user_id,
-            payload.0.description.clone(),
-            payload.0.is_public.clone(),
-            payload.0.nsfw.clone(),
-            payload.0.ref_link.clone(),
-            payload.0.title.clone(),
-            false,
-        ).await?;
+        let clear_description = payload.0.is_cleared("description");
+        let clear_ref_link = payload.0.is_cleared("ref_link");
+        let update = payload.0.inner;
 
-        entry.title = payload.0.title;
-        entry.ref_link = payload.0.ref_link;
-        entry.is_public = payload.0.is_public;
-        entry.nsfw = payload.0.nsfw;
-        entry.description = payload.0.description;
+        if let Some(title) = update.title {
+            entry.title = title.into_inner();
+        }
+
+        if update.description.is_some() | clear_description {
+            entry.description = update.description.map(NormalizedString::into_inner);
+        }
+
+        if update.ref_link.is_some() | clear_ref_link {
+            if let Err(msg) = check_ref_link(update.ref_link.as_deref(), &config.allowed_link_hosts) {
+                return Ok(JsonResponse::bad_request(msg))
+            }
+
+            entry.ref_link = update.ref_link;
+        }
+
+        if let Some(is_public) = update.is_public {
+            entry.is_public = is_public;
+        }
+
+        let nsfw_changed = update.nsfw
+            .map(|nsfw| nsfw != entry.nsfw)
+            .unwrap_or(false);
+
+        if let Some(nsfw) = update.nsfw {
+            entry.nsfw = nsfw;
+        }
+
+        entries::save_entry(&session, &mut entry).await?;
+
+        if nsfw_changed {
+            refresh_playlists_nsfw(&session, entry.id).await?;
+        }
 
         Ok(JsonResponse::ok(entry))
     }
 }
 
 
-#[inline]
+/// Recomputes the nsfw flag of every playlist containing the given entry,
+/// only writing the playlists whose flag has changed.
+async fn refresh_playlists_nsfw(sess: &Session, entry_id: Uuid) -> ApiResult<()> {
+    for playlist_id in playlist::get_playlists_referencing(sess, entry_id).await? {
+        refresh_playlist_nsfw(sess, playlist_id).await?;
+    }
+
+    Ok(())
+}
+
+/// Recomputes the nsfw flag of the playlist, saving it only if it changed.
+///
+/// The flag is saved with `save_playlist` so the playlist's `version` and
+/// `updated_at` change with it, a concurrent edit of the playlist can't
+/// write back the old flag and clients holding an `ETag` see the change.
+async fn refresh_playlist_nsfw(sess: &Session, playlist_id: Uuid) -> ApiResult<()> {
+    for _ in 0..MAX_NSFW_REFRESH_ATTEMPTS {
+        let mut playlist = match playlist::get_playlist_by_id(sess, playlist_id).await? {
+            None => return Ok(()),
+            Some(playlist) => playlist,
+        };
+
+        let entries = entries::get_entries_with_ids(sess, &playlist.items).await?;
+        let nsfw = entries.iter().any(|v| v.nsfw);
+        if nsfw == playlist.nsfw {
+            return Ok(())
+        }
+
+        playlist.nsfw = nsfw;
+        if playlist::save_playlist(sess, &mut playlist).await? {
+            return Ok(())
+        }
+    }
+
+    Err(anyhow!("failed to refresh the nsfw flag of playlist {} due to concurrent edits", playlist_id).into())
+}
+
+
+/// The outcome of the checks made before creating a playlist, shared by
+/// `POST /playlists` and `POST /playlists/validate`.
+struct PlaylistCheck {
+    /// The normalized tags, empty if the playlist was rejected before they
+    /// were checked.
+    tags: Vec<String>,
+
+    /// The requested `items` which can be added, in order and without
+    /// duplicates. This doesn't include any `new_entries`.
+    items: Vec<Uuid>,
+
+    is_nsfw: bool,
+
+    /// The requested `items` which don't exist as entries.
+    invalid_items: Vec<Uuid>,
+
+    /// Why the playlist can't be created, if it can't.
+    rejection: Option<Rejection>,
+}
+
+enum Rejection {
+    BadRequest(String),
+
+    /// Some `items` don't exist, these are listed in `invalid_items`.
+    InvalidItems(String),
+}
+
+/// Runs every check on a new playlist, resolving its `items` to work out
+/// which are invalid and if it is NSFW.
+///
+/// Items are resolved before anything else is checked so these are known
+/// even if the playlist is rejected.
+async fn check_new_playlist(
+    sess: &Session,
+    config: &Config,
+    client: &reqwest::Client,
+    user_id: i64,
+    payload: &PlaylistCreationPayload,
+) -> ApiResult<PlaylistCheck> {
+    let mut seen = HashSet::new();
+    let requested: Vec<Uuid> = payload.items
+        .iter()
+        .copied()
+        .filter(|v| seen.insert(*v))
+        .collect();
+
+    let found = entries::get_entries_with_ids(sess, &requested).await?;
+    let resolved: HashSet<Uuid> = found.iter().map(|v| v.id).collect();
+    let invalid_items: Vec<Uuid> = requested.iter()
+        .copied()
+        .filter(|v| !resolved.contains(v))
+        .collect();
+
+    let is_nsfw = found.iter().any(|v| v.nsfw) | payload.new_entries.iter().any(|v| v.nsfw);
+    let valid: HashSet<Uuid> = filter_valid_entries(user_id, payload.is_public, found)
+        .into_iter()
+        .collect();
+    let items: Vec<Uuid> = requested.into_iter()
+        .filter(|v| valid.contains(v))
+        .collect();
+
+    let mut check = PlaylistCheck {
+        tags: vec![],
+        items,
+        is_nsfw,
+        invalid_items,
+        rejection: None,
+    };
+    check.rejection = find_rejection(sess, config, client, user_id, payload, !resolved.is_empty(), &mut check).await?;
+
+    Ok(check)
+}
+
+/// Returns the first check the new playlist fails, setting the `tags` of
+/// `check` once they are normalized.
+async fn find_rejection(
+    sess: &Session,
+    config: &Config,
+    client: &reqwest::Client,
+    user_id: i64,
+    payload: &PlaylistCreationPayload,
+    any_resolved: bool,
+    check: &mut PlaylistCheck,
+) -> ApiResult<Option<Rejection>> {
+    let owned = playlist::count_playlists_for_owner(sess, user_id).await?;
+    if owned >= config.max_playlists_per_user {
+        return Ok(Some(Rejection::BadRequest(format!(
+            "You cannot own more than {} playlists.",
+            config.max_playlists_per_user,
+        ))))
+    }
+
+    check.tags = match normalize_tags(payload.tags.clone()) {
+        Err(msg) => return Ok(Some(Rejection::BadRequest(msg))),
+        Ok(tags) => tags,
+    };
+
+    if payload.new_entries.len() > MAX_BULK_ENTRIES {
+        return Ok(Some(Rejection::BadRequest(format!(
+            "You cannot create more than {} entries at once.",
+            MAX_BULK_ENTRIES,
+        ))))
+    }
+
+    if payload.is_public & payload.new_entries.iter().any(|v| !v.is_public) {
+        return Ok(Some(Rejection::BadRequest(
+            "Only public entries can be added to a public playlist.".to_string(),
+        )))
+    }
+
+    if let Err(msg) = check_banner(client, config, payload.banner.as_deref()).await {
+        return Ok(Some(Rejection::BadRequest(msg)))
+    }
+
+    for entry in payload.new_entries.iter() {
+        if let Err(msg) = check_ref_link(entry.ref_link.as_deref(), &config.allowed_link_hosts) {
+            return Ok(Some(Rejection::BadRequest(msg)))
+        }
+    }
+
+    // Items are only reported as invalid when some of them exist, if none do
+    // the playlist is rejected for having no valid items instead.
+    if any_resolved & !check.invalid_items.is_empty() {
+        return Ok(Some(Rejection::InvalidItems("Some playlist entries do not exist.".to_string())))
+    }
+
+    let total_items = check.items.len() + payload.new_entries.len();
+    if total_items == 0 {
+        return Ok(Some(Rejection::BadRequest("No valid playlists entries selected.".to_string())))
+    }
+
+    if total_items > MAX_PLAYLIST_ITEMS {
+        return Ok(Some(Rejection::BadRequest(format!(
+            "A playlist cannot contain more than {} items.",
+            MAX_PLAYLIST_ITEMS,
+        ))))
+    }
+
+    Ok(None)
+}
+
+/// Returns the playlist created by an earlier request with the same
+/// idempotency key.
+///
+/// The key is reserved before the playlist is inserted, so if it doesn't
+/// exist yet the original request is still in progress.
+async fn replay_playlist(sess: &Session, playlist_id: Uuid) -> ApiResult<JsonResponse<Playlist>> {
+    let resp = match playlist::get_playlist_by_id(sess, playlist_id).await? {
+        None => JsonResponse::conflict(IDEMPOTENCY_IN_PROGRESS),
+        Some(playlist) => JsonResponse::ok(playlist),
+    };
+
+    Ok(resp)
+}
+
+
+/// Builds the `ETag` for a playlist response.
+///
+/// Votes and `has_voted` change without bumping `updated_at` so are included
+/// along with any expanded entries.
+fn playlist_etag(playlist: &Playlist) -> String {
+    let mut hasher = Sha256::new();
+
+    hasher.update(playlist.id.as_bytes());
+    hasher.update(format!(
+        "{:?}:{}:{:?}",
+        playlist.updated_at.map(|v| v.timestamp_millis()),
+        playlist.votes,
+        playlist.has_voted,
+    ));
+
+    for entry in playlist.entries.iter().flatten() {
+        hasher.update(entry.id.as_bytes());
+        hasher.update(format!(
+            "{:?}:{}",
+            entry.updated_at.map(|v| v.timestamp_millis()),
+            entry.votes,
+        ));
+    }
+
+    let digest: String = hasher.finalize()[..16]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    format!("\"{}\"", digest)
+}
+
+/// Checks if an `If-None-Match` header matches the given tag, the header may
+/// be `*` or a comma separated list of strong or weak tags.
+fn etag_matches(header: &str, etag: &str) -> bool {
+    header.split(',')
+        .map(|v| v.trim())
+        .any(|v| v == "*" || v.trim_start_matches("W/") == etag)
+}
+
+
+/// Trims, lowercases and de-duplicates the given tags, erroring if any tag is
+/// empty or too long, or if there are too many.
+fn normalize_tags(tags: Vec<String>) -> Result<Vec<String>, String> {
+    let mut seen = HashSet::new();
+    let tags: Vec<String> = tags.into_iter()
+        .map(|v| v.trim().to_lowercase())
+        .filter(|v| seen.insert(v.clone()))
+        .collect();
+
+    if tags.len() > MAX_PLAYLIST_TAGS {
+        return Err(format!("A playlist cannot have more than {} tags.", MAX_PLAYLIST_TAGS))
+    }
+
+    for tag in tags.iter() {
+        if tag.is_empty() {
+            return Err("Tags cannot be empty.".to_string())
+        }
+
+        if tag.chars().count() > MAX_TAG_LENGTH {
+            return Err(format!("Tags cannot be longer than {} characters.", MAX_TAG_LENGTH))
+        }
+    }
+
+    Ok(tags)
+}
+
+/// Checks the link points to one of the allowed hosts or a subdomain of one,
+/// returning the message to reject it with if not.
+fn check_ref_link(link: Option<&str>, allowed_hosts: &[String]) -> Result<(), String> {
+    let link = match link {
+        None => return Ok(()),
+        Some(v) => v,
+    };
+
+    let host = Url::parse(link)
+        .ok()
+        .and_then(|v| v.host_str().map(|host| host.trim_end_matches('.').to_lowercase()))
+        .ok_or_else(|| "The ref_link is not a valid url.".to_string())?;
+
+    let allowed = allowed_hosts.iter()
+        .any(|v| (host == *v) | host.ends_with(&format!(".{}", v)));
+
+    if !allowed {
+        return Err(format!("Links to {} are not allowed.", host))
+    }
+
+    Ok(())
+}
+
+
+/// Checks if the user can view the playlist, private playlists can only be
+/// viewed by their owner and collaborators.
+async fn can_view_playlist(sess: &Session, playlist: &Playlist, user_id: Option<i64>) -> ApiResult<bool> {
+    match user_id {
+        _ if playlist.is_public => Ok(true),
+        None => Ok(false),
+        Some(user_id) => collaborators::can_edit(sess, playlist, user_id).await,
+    }
+}
+
+/// The response to a user who isn't allowed to act on the playlist.
+///
+/// Private playlists return the same 404 as a playlist which doesn't exist,
+/// using the handler's `not_found` message, so their existence isn't
+/// revealed. Public playlists return a 403.
+fn deny_playlist_access<T: Send + Sync + ToJSON>(playlist: &Playlist, not_found: &str) -> JsonResponse<T> {
+    if playlist.is_public {
+        JsonResponse::forbidden()
+    } else {
+        JsonResponse::not_found(not_found)
+    }
+}
+
 fn filter_valid_entries(owner_id: i64, is_public: bool, entries: Vec<PlaylistEntry>) -> Vec<Uuid> {
     entries.into_iter()
-        .filter(|v| v.is_public | ((*v.owner_id == owner_id) & !is_public))
+        .filter(|v| is_valid_entry(owner_id, is_public, v))
         .map(|v| v.id)
         .collect()
 }
 
+/// Checks if the entry can be added to a playlist owned by `owner_id`, only
+/// public entries can be in public playlists.
+fn is_valid_entry(owner_id: i64, is_public: bool, entry: &PlaylistEntry) -> bool {
+    entry.is_public | ((*entry.owner_id == owner_id) & !is_public)
+}
+
 
-async fn insert_playlist(
+/// Checks the banner is a reachable image if `CHECK_BANNER_URLS` is enabled,
+/// returning the message to reject it with if not.
+async fn check_banner(client: &reqwest::Client, config: &Config, banner: Option<&str>) -> Result<(), String> {
+    let banner = match banner {
+        Some(v) if config.check_banner_urls => v,
+        _ => return Ok(()),
+    };
+
+    if !crate::images::is_reachable_image(client, banner).await {
+        return Err("The banner could not be fetched as an image.".to_string())
+    }
+
+    Ok(())
+}
+
+/// Uploads the banner at the given url to the image server, returning the
+/// stored banner if it was accepted.
+async fn upload_banner(banner: Option<String>) -> ApiResult<Option<String>> {
+    match banner {
+        None => Ok(None),
+        Some(url) => Ok(crate::images::fetch_and_upload(&url).await?),
+    }
+}
+
+
+const INSERT_PLAYLIST_QUERY: &str = r#"INSERT INTO playlists (
+    id,
+    owner_id,
+    banner,
+    description,
+    is_public,
+    ordered_items,
+    nsfw,
+    title,
+    tags,
+    votes,
+    created_at,
+    updated_at,
+    version
+) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, 0, ?, ?, 1)"#;
+
+/// Inserts a new playlist along with the new entries it contains and its
+/// entry references in a single batch, so either everything is created or
+/// nothing is.
+///
+/// The banner must already be uploaded, see `upload_banner`.
+#[allow(clippy::too_many_arguments)]
+async fn insert_playlist_with_entries(
     sess: &Session,
     id: Uuid,
     owner_id: i64,
@@ -470,27 +3065,66 @@ async fn insert_playlist(
     items: Vec<Uuid>,
     is_nsfw: bool,
     title: String,
-    fetch_updated: bool,
-) -> anyhow::Result<Option<Playlist>> {
-    let banner = if let Some(url) = banner {
-        crate::images::fetch_and_upload(&url).await?
-    } else {
-        None
-    };
+    tags: Vec<String>,
+    created_at: DateTime<Utc>,
+    new_entries: &[PlaylistEntry],
+) -> ApiResult<Option<Playlist>> {
+    let mut queries = Vec::with_capacity(new_entries.len() + items.len() + 1);
+    let mut values = Vec::with_capacity(queries.capacity());
+
+    for entry in new_entries {
+        queries.push(entries::INSERT_ENTRY_QUERY);
+        values.push(entries::insert_entry_values(entry)?);
+    }
 
+    for entry_id in items.iter() {
+        queries.push("INSERT INTO entry_playlist_refs (entry_id, playlist_id) VALUES (?, ?);");
+        values.push(db::serialize_values((*entry_id, id))?);
+    }
 
-    sess.query(
-        r#"INSERT INTO playlists (
-            id,
-            owner_id,
-            banner,
-            description,
-            is_public,
-            items,
-            nsfw,
-            title,
-            votes
-        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, 0)"#,
+    queries.push(INSERT_PLAYLIST_QUERY);
+    values.push(db::serialize_values((
+        id,
+        owner_id,
+        banner,
+        description,
+        is_public,
+        items,
+        is_nsfw,
+        title,
+        &tags,
+        utils::to_cql_timestamp(created_at),
+        utils::to_cql_timestamp(created_at),
+    ))?);
+
+    sess.batch(&queries, values).await?;
+    stats::listing_changed(sess, id, None, is_public.then_some(tags.as_slice())).await?;
+
+    playlist::get_playlist_by_id(sess, id).await
+}
+
+
+/// Inserts a new playlist.
+///
+/// The banner must already be uploaded, see `upload_banner`.
+#[allow(clippy::too_many_arguments)]
+async fn insert_playlist(
+    sess: &Session,
+    id: Uuid,
+    owner_id: i64,
+    banner: Option<String>,
+    description: Option<String>,
+    is_public: bool,
+    items: Vec<Uuid>,
+    is_nsfw: bool,
+    title: String,
+    tags: Vec<String>,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: DateTime<Utc>,
+    fetch_updated: bool,
+) -> ApiResult<Option<Playlist>> {
+    sess.query_prepared(
+        INSERT_PLAYLIST_QUERY,
         (
             id,
             owner_id,
@@ -500,8 +3134,12 @@ async fn insert_playlist(
             items,
             is_nsfw,
             title,
+            &tags,
+            created_at.map(utils::to_cql_timestamp),
+            utils::to_cql_timestamp(updated_at),
         )
     ).await?;
+    stats::listing_changed(sess, id, None, is_public.then_some(tags.as_slice())).await?;
 
     let res = if fetch_updated {
         playlist::get_playlist_by_id(sess, id).await?
@@ -513,6 +3151,7 @@ async fn insert_playlist(
 }
 
 
+#[allow(clippy::too_many_arguments)]
 async fn insert_entry(
     sess: &Session,
     id: Uuid,
@@ -522,9 +3161,9 @@ async fn insert_entry(
     is_nsfw: bool,
     ref_link: Option<String>,
     title: String,
-    fetch_updated: bool,
-) -> anyhow::Result<Option<PlaylistEntry>> {
-    sess.query(
+    created_at: DateTime<Utc>,
+) -> ApiResult<Option<PlaylistEntry>> {
+    sess.query_prepared(
         r#"INSERT INTO playlist_entries (
             id,
             owner_id,
@@ -533,8 +3172,10 @@ async fn insert_entry(
             nsfw,
             ref_link,
             title,
-            votes
-        ) VALUES (?, ?, ?, ?, ?, ?, ?, 0)"#,
+            votes,
+            created_at,
+            updated_at
+        ) VALUES (?, ?, ?, ?, ?, ?, ?, 0, ?, ?)"#,
         (
             id,
             owner_id,
@@ -543,14 +3184,245 @@ async fn insert_entry(
             is_nsfw,
             ref_link,
             title,
+            utils::to_cql_timestamp(created_at),
+            utils::to_cql_timestamp(created_at),
         )
     ).await?;
 
-    let res = if fetch_updated {
-        entries::get_entry_by_id(sess, id).await?
-    } else {
-        None
-    };
+    entries::get_entry_by_id(sess, id).await
+}
 
-    Ok(res)
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use poem_openapi::types::ParseFromJSON;
+    use serde_json::json;
+
+    use super::*;
+
+    fn creation_payload(items: usize) -> Value {
+        let items: Vec<Uuid> = (0..items).map(|_| Uuid::new_v4()).collect();
+        json!({
+            "title": "My playlist",
+            "items": items,
+        })
+    }
+
+    #[test]
+    fn creation_accepts_the_maximum_number_of_items() {
+        let payload = PlaylistCreationPayload::parse_from_json(creation_payload(MAX_PLAYLIST_ITEMS)).unwrap();
+        assert_eq!(payload.items.len(), MAX_PLAYLIST_ITEMS);
+    }
+
+    #[test]
+    fn creation_rejects_too_many_items() {
+        assert!(PlaylistCreationPayload::parse_from_json(creation_payload(MAX_PLAYLIST_ITEMS + 1)).is_err());
+    }
+
+    fn parses_banner(banner: &str) -> bool {
+        let mut payload = creation_payload(0);
+        payload["banner"] = json!(banner);
+        let creation = PlaylistCreationPayload::parse_from_json(payload).is_ok();
+
+        let update = PlaylistUpdatePayload::parse_from_json(json!({ "banner": banner, "version": 0 })).is_ok();
+        assert_eq!(creation, update, "creation and update disagree on {}", banner);
+
+        creation
+    }
+
+    #[test]
+    fn banners_accept_imgur_images() {
+        for banner in [
+            "https://i.imgur.com/abc123.jpg",
+            "https://i.imgur.com/ABC.jpeg",
+            "https://i.imgur.com/a1.png",
+            "https://i.imgur.com/Zz9.webp",
+        ] {
+            assert!(parses_banner(banner), "{} was rejected", banner);
+        }
+    }
+
+    #[test]
+    fn banners_reject_other_urls() {
+        for banner in [
+            "http://i.imgur.com/abc.png",
+            "https://imgur.com/abc.png",
+            "https://i.imgur.com.evil.com/abc.png",
+            "https://i.imgur.com/abc.gif",
+            "https://i.imgur.com/abc.png?x=1",
+            "https://i.imgur.com/x.png@evil.com",
+            "https://i.imgur.com/../abc.png",
+            "https://i.imgur.com/abc.png\n",
+        ] {
+            assert!(!parses_banner(banner), "{} was accepted", banner);
+        }
+    }
+
+    fn test_playlist() -> Playlist {
+        Playlist {
+            id: Uuid::new_v4(),
+            owner_id: JsSafeBigInt(1),
+            banner: None,
+            description: None,
+            is_public: true,
+            items: vec![],
+            item_count: 0,
+            nsfw: false,
+            title: "My playlist".to_string(),
+            votes: 0,
+            tags: vec![],
+            created_at: Some(utils::now()),
+            updated_at: Some(utils::now()),
+            version: 0,
+            has_voted: None,
+            entries: None,
+            created_entries: None,
+            window_votes: None,
+            similarity: None,
+            score: None,
+            deleted_at: None,
+        }
+    }
+
+    fn test_entry() -> PlaylistEntry {
+        PlaylistEntry {
+            id: Uuid::new_v4(),
+            owner_id: JsSafeBigInt(1),
+            description: None,
+            is_public: true,
+            nsfw: false,
+            ref_link: None,
+            title: "My entry".to_string(),
+            votes: 0,
+            created_at: Some(utils::now()),
+            updated_at: Some(utils::now()),
+            has_voted: None,
+            deleted_at: None,
+        }
+    }
+
+    #[test]
+    fn playlist_etags_are_stable() {
+        let playlist = test_playlist();
+        let etag = playlist_etag(&playlist);
+
+        assert_eq!(playlist_etag(&playlist), etag);
+        assert!(etag.starts_with('"') & etag.ends_with('"'));
+        assert_ne!(playlist_etag(&test_playlist()), etag);
+    }
+
+    #[test]
+    fn playlist_etags_change_with_the_response() {
+        let mut playlist = test_playlist();
+        let mut seen = vec![playlist_etag(&playlist)];
+
+        playlist.updated_at = playlist.updated_at.map(|v| v + chrono::Duration::milliseconds(1));
+        seen.push(playlist_etag(&playlist));
+
+        playlist.votes += 1;
+        seen.push(playlist_etag(&playlist));
+
+        playlist.has_voted = Some(true);
+        seen.push(playlist_etag(&playlist));
+
+        playlist.entries = Some(vec![test_entry()]);
+        seen.push(playlist_etag(&playlist));
+
+        playlist.entries.as_mut().unwrap()[0].votes += 1;
+        seen.push(playlist_etag(&playlist));
+
+        let unique: HashSet<&String> = seen.iter().collect();
+        assert_eq!(unique.len(), seen.len());
+    }
+
+    #[test]
+    fn etags_match_any_listed_tag() {
+        let etag = "\"abc\"";
+        assert!(etag_matches("\"abc\"", etag));
+        assert!(etag_matches("W/\"abc\"", etag));
+        assert!(etag_matches("\"other\", \"abc\"", etag));
+        assert!(etag_matches("*", etag));
+    }
+
+    #[test]
+    fn etags_do_not_match_other_tags() {
+        let etag = "\"abc\"";
+        assert!(!etag_matches("\"abcd\"", etag));
+        assert!(!etag_matches("abc", etag));
+        assert!(!etag_matches("", etag));
+    }
+
+    fn allowed_hosts() -> Vec<String> {
+        vec!["youtube.com".to_string(), "youtu.be".to_string()]
+    }
+
+    #[test]
+    fn ref_links_accept_allowed_hosts_and_subdomains() {
+        for link in [
+            "https://youtube.com/watch?v=abc",
+            "https://www.youtube.com/watch?v=abc",
+            "https://m.youtube.com./watch?v=abc",
+            "https://YOUTU.BE/abc",
+        ] {
+            assert_eq!(check_ref_link(Some(link), &allowed_hosts()), Ok(()), "{} was rejected", link);
+        }
+
+        assert_eq!(check_ref_link(None, &allowed_hosts()), Ok(()));
+    }
+
+    #[test]
+    fn ref_links_reject_other_hosts() {
+        assert_eq!(
+            check_ref_link(Some("https://evilyoutube.com/watch"), &allowed_hosts()),
+            Err("Links to evilyoutube.com are not allowed.".to_string()),
+        );
+        assert_eq!(
+            check_ref_link(Some("https://youtube.com.evil.com/watch"), &allowed_hosts()),
+            Err("Links to youtube.com.evil.com are not allowed.".to_string()),
+        );
+        assert_eq!(
+            check_ref_link(Some("https://youtube.com@evil.com/watch"), &allowed_hosts()),
+            Err("Links to evil.com are not allowed.".to_string()),
+        );
+    }
+
+    #[test]
+    fn ref_links_must_be_urls_with_a_host() {
+        for link in ["youtube.com/watch", "not a url", "mailto:someone@youtube.com"] {
+            assert_eq!(
+                check_ref_link(Some(link), &allowed_hosts()),
+                Err("The ref_link is not a valid url.".to_string()),
+                "{} was accepted",
+                link,
+            );
+        }
+    }
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn tags_are_trimmed_lowercased_and_deduplicated() {
+        let normalized = normalize_tags(tags(&[" Lo-Fi ", "chill", "lo-fi", "CHILL"])).unwrap();
+        assert_eq!(normalized, tags(&["lo-fi", "chill"]));
+    }
+
+    #[test]
+    fn tags_are_limited_after_deduplicating() {
+        let mut many: Vec<String> = (0..MAX_PLAYLIST_TAGS).map(|v| format!("tag{}", v)).collect();
+        many.push("TAG0".to_string());
+        assert_eq!(normalize_tags(many.clone()).unwrap().len(), MAX_PLAYLIST_TAGS);
+
+        many.push("extra".to_string());
+        assert!(normalize_tags(many).is_err());
+    }
+
+    #[test]
+    fn tags_cannot_be_empty_or_too_long() {
+        assert!(normalize_tags(tags(&["  "])).is_err());
+
+        let longest = "é".repeat(MAX_TAG_LENGTH);
+        assert_eq!(normalize_tags(vec![longest.clone()]).unwrap(), vec![longest]);
+        assert!(normalize_tags(vec!["a".repeat(MAX_TAG_LENGTH + 1)]).is_err());
+    }
+}
@@ -1,13 +1,26 @@
+use std::collections::{HashMap, HashSet};
 use anyhow::anyhow;
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
 use poem_openapi::Object;
-use scylla::{FromRow, IntoTypedRows};
+use scylla::IntoTypedRows;
+use scylla::cql_to_rust::{FromRow, FromRowError};
+use scylla::frame::response::result::Row;
 
-use crate::db::Session;
-use crate::utils::JsSafeBigInt;
+use crate::db::{self, Session};
+use crate::errors::ApiResult;
+use crate::utils::{self, JsSafeBigInt};
+use super::PlaylistEntry;
 
+/// The maximum number of ids passed to a single `IN` query.
+const MAX_IDS_PER_QUERY: usize = 100;
 
-#[derive(Object, FromRow)]
+/// The number of times removing an entry from a playlist is retried if the
+/// playlist is edited concurrently.
+const MAX_REMOVE_ATTEMPTS: usize = 3;
+
+
+#[derive(Object)]
 pub struct Playlist {
     pub id: Uuid,
     pub owner_id: JsSafeBigInt,
@@ -15,16 +28,141 @@ pub struct Playlist {
     pub description: Option<String>,
     pub is_public: bool,
     pub items: Vec<Uuid>,
+
+    /// The number of items in the playlist.
+    #[oai(read_only)]
+    pub item_count: usize,
+
     pub nsfw: bool,
     pub title: String,
     pub votes: i32,
+    pub tags: Vec<String>,
+
+    /// When the playlist was created.
+    ///
+    /// This is missing for playlists created before it was recorded.
+    pub created_at: Option<DateTime<Utc>>,
+
+    /// When the playlist was last modified.
+    ///
+    /// This is missing for playlists created before it was recorded.
+    pub updated_at: Option<DateTime<Utc>>,
+
+    /// Incremented every time the playlist is edited, this must be sent back
+    /// when editing so concurrent edits can't overwrite each other.
+    ///
+    /// This is `0` for playlists which haven't been edited since it was added.
+    #[oai(read_only)]
+    pub version: i32,
+
+    /// If the requesting user has up-voted this playlist.
+    ///
+    /// This is only present when the request is authenticated.
+    pub has_voted: Option<bool>,
+
+    /// The full entries for each of the playlist `items`, in the same order.
+    ///
+    /// This is only present when the playlist is requested with `expand=items`.
+    #[oai(read_only)]
+    pub entries: Option<Vec<PlaylistEntry>>,
+
+    /// The ids of the entries created from `new_entries`, in the same order.
+    ///
+    /// This is only present when creating a playlist with `new_entries`.
+    #[oai(read_only)]
+    pub created_entries: Option<Vec<Uuid>>,
+
+    /// The votes the playlist received within the discovery window, e.g. the
+    /// last week for `top_week`.
+    ///
+    /// This is only present when discovering with the `top_week` or `top_month` sort.
+    #[oai(read_only)]
+    pub window_votes: Option<i64>,
+
+    /// The share of items this playlist has in common with the requested
+    /// playlist, from `0` to `1`.
+    ///
+    /// This is only present when getting similar playlists.
+    #[oai(read_only)]
+    pub similarity: Option<f64>,
+
+    /// How closely the title matches the search query, from `0` to `1`.
+    ///
+    /// This is only present when searching with `fuzzy` set.
+    #[oai(read_only)]
+    pub score: Option<f64>,
+
+    /// When the playlist was removed, removed playlists are kept so they can
+    /// be restored but are otherwise treated as if they don't exist.
+    #[oai(skip)]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+type PlaylistRow = (
+    Uuid,
+    JsSafeBigInt,
+    Option<String>,
+    Option<String>,
+    bool,
+    Option<Vec<Uuid>>,
+    bool,
+    String,
+    i32,
+    Option<Vec<String>>,
+    Option<chrono::Duration>,
+    Option<chrono::Duration>,
+    Option<i32>,
+    Option<chrono::Duration>,
+);
+
+impl FromRow for Playlist {
+    fn from_row(row: Row) -> Result<Self, FromRowError> {
+        let v = PlaylistRow::from_row(row)?;
+        let items = v.5.unwrap_or_default();
+
+        Ok(Self {
+            id: v.0,
+            owner_id: v.1,
+            banner: v.2,
+            description: v.3,
+            is_public: v.4,
+            item_count: items.len(),
+            items,
+            nsfw: v.6,
+            title: v.7,
+            votes: v.8,
+            tags: v.9.unwrap_or_default(),
+            created_at: v.10.map(utils::from_cql_timestamp),
+            updated_at: v.11.map(utils::from_cql_timestamp),
+            version: v.12.unwrap_or(0),
+            has_voted: None,
+            entries: None,
+            created_entries: None,
+            window_votes: None,
+            similarity: None,
+            score: None,
+            deleted_at: v.13.map(utils::from_cql_timestamp),
+        })
+    }
 }
 
 
-pub async fn get_playlist_by_id(sess: &Session, id: Uuid) -> anyhow::Result<Option<Playlist>> {
+/// Gets the playlist with the given id, removed playlists are treated as if
+/// they don't exist.
+pub async fn get_playlist_by_id(sess: &Session, id: Uuid) -> ApiResult<Option<Playlist>> {
+    let playlist = get_playlist_including_deleted(sess, id).await?
+        .filter(|v| v.deleted_at.is_none());
+
+    Ok(playlist)
+}
+
+
+/// Gets the playlist with the given id even if it has been removed.
+pub async fn get_playlist_including_deleted(sess: &Session, id: Uuid) -> ApiResult<Option<Playlist>> {
     let result = sess.query_prepared(
         r#"
-        SELECT * FROM playlists WHERE id = ?;
+        SELECT id, owner_id, banner, description, is_public, ordered_items, nsfw, title, votes, tags, created_at, updated_at, version, deleted_at
+        FROM playlists WHERE id = ?;
         "#,
         (id,)
     ).await?;
@@ -42,48 +180,1158 @@ pub async fn get_playlist_by_id(sess: &Session, id: Uuid) -> anyhow::Result<Opti
 }
 
 
-pub async fn upvote_playlist(sess: &Session, user_id: i64, playlist_id: Uuid) -> anyhow::Result<()> {
-    sess.query_prepared(
-        "INSERT INTO playlist_votes (user_id, playlist_id) VALUES (?, ?);",
-        (user_id, playlist_id.clone()),
-    ).await?;
+/// Fetches the playlists with the given ids, returning them in the same order
+/// as the ids.
+///
+/// Ids are queried in chunks of `MAX_IDS_PER_QUERY` and ids which no longer
+/// exist are skipped.
+pub async fn get_playlists_with_ids(sess: &Session, ids: &[Uuid]) -> ApiResult<Vec<Playlist>> {
+    let mut found = HashMap::with_capacity(ids.len());
+    for chunk in ids.chunks(MAX_IDS_PER_QUERY) {
+        let result = sess.query_prepared(
+            r#"
+            SELECT id, owner_id, banner, description, is_public, ordered_items, nsfw, title, votes, tags, created_at, updated_at, version, deleted_at
+            FROM playlists WHERE id IN ?;
+            "#,
+            (chunk.to_vec(),)
+        ).await?;
 
-    sess.query_prepared(
-        "UPDATE playlists SET votes = votes + 1 WHERE id = ?;",
-        (playlist_id,)
+        let rows = result.rows
+            .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+        found.extend(
+            rows.into_typed::<Playlist>()
+                .filter_map(|v| v.ok())
+                .map(|v| (v.id, v))
+        );
+    }
+
+    let playlists = ids.iter()
+        .filter_map(|id| found.remove(id))
+        .filter(|v| v.deleted_at.is_none())
+        .collect();
+
+    Ok(playlists)
+}
+
+
+/// Saves the playlist's editable fields, returning false without saving if the
+/// playlist has been edited since it was read.
+///
+/// This is a lightweight transaction on `version`, which is incremented on
+/// success so the next edit must be based on this one.
+pub async fn save_playlist(sess: &Session, playlist: &mut Playlist) -> ApiResult<bool> {
+    let updated_at = utils::now();
+    let version = playlist.version + 1;
+
+    // Playlists created before versions were tracked have no version, binding
+    // null makes the condition match those instead.
+    let expected = Some(playlist.version).filter(|v| *v != 0);
+
+    let result = sess.query_prepared(
+        r#"
+        UPDATE playlists SET
+            banner = ?,
+            description = ?,
+            is_public = ?,
+            ordered_items = ?,
+            nsfw = ?,
+            title = ?,
+            tags = ?,
+            updated_at = ?,
+            version = ?
+        WHERE id = ? AND owner_id = ?
+        IF version = ?;
+        "#,
+        (
+            &playlist.banner,
+            &playlist.description,
+            playlist.is_public,
+            &playlist.items,
+            playlist.nsfw,
+            &playlist.title,
+            &playlist.tags,
+            utils::to_cql_timestamp(updated_at),
+            version,
+            playlist.id,
+            *playlist.owner_id,
+            expected,
+        )
     ).await?;
 
+    if !db::was_applied(&result) {
+        return Ok(false)
+    }
+
+    playlist.item_count = playlist.items.len();
+    playlist.updated_at = Some(updated_at);
+    playlist.version = version;
+
+    Ok(true)
+}
+
+
+/// The `featured_playlists` partition every featured playlist is kept in.
+///
+/// Playlists are featured by hand, so there are only ever a few of them and
+/// they can all be read from one partition.
+const FEATURED_SCOPE: &str = "featured";
+
+/// Adds the playlist to or removes it from the featured playlists.
+pub async fn set_playlist_featured(
+    sess: &Session,
+    playlist_id: Uuid,
+    featured: bool,
+) -> ApiResult<()> {
+    let query = if featured {
+        "INSERT INTO featured_playlists (scope, playlist_id) VALUES (?, ?);"
+    } else {
+        "DELETE FROM featured_playlists WHERE scope = ? AND playlist_id = ?;"
+    };
+
+    sess.query_prepared(query, (FEATURED_SCOPE, playlist_id)).await?;
+
     Ok(())
 }
 
-pub async fn has_user_voted(sess: &Session, user_id: i64, playlist_id: Uuid) -> anyhow::Result<bool> {
+
+/// Gets the featured playlists which are public and haven't been removed.
+///
+/// Playlists which are made private after being featured stay featured but
+/// are left out until they are public again.
+pub async fn get_featured_playlists(sess: &Session) -> ApiResult<Vec<Playlist>> {
     let result = sess.query_prepared(
-        "SELECT true FROM playlist_votes WHERE user_id = ? AND playlist_id = ?;",
+        "SELECT playlist_id FROM featured_playlists WHERE scope = ?;",
+        (FEATURED_SCOPE,)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let ids: Vec<Uuid> = rows.into_typed::<(Uuid,)>()
+        .filter_map(|v| v.ok())
+        .map(|v| v.0)
+        .collect();
+
+    let playlists = get_playlists_with_ids(sess, &ids).await?
+        .into_iter()
+        .filter(|v| v.is_public)
+        .collect();
+
+    Ok(playlists)
+}
+
+
+/// The number of times a vote count update is retried if the count is
+/// changed concurrently.
+pub(super) const MAX_VOTE_UPDATE_ATTEMPTS: usize = 10;
+
+/// Records the user's vote and increments the vote count, returning the new
+/// count or None if the user has already voted on the playlist.
+///
+/// The vote is only recorded if the user hasn't voted already, so concurrent
+/// votes by the same user only count once. If the count can't be updated the
+/// vote is removed again so the user can retry.
+pub async fn upvote_playlist(
+    sess: &Session,
+    user_id: i64,
+    playlist: &Playlist,
+    cooldown_secs: i32,
+) -> ApiResult<Option<i32>> {
+    let result = sess.query_prepared(
+        "INSERT INTO playlist_votes (user_id, playlist_id, voted_on) VALUES (?, ?, toTimestamp(now())) IF NOT EXISTS USING TTL ?;",
+        (user_id, playlist.id, cooldown_secs)
+    ).await?;
+
+    if !db::was_applied(&result) {
+        return Ok(None)
+    }
+
+    match adjust_playlist_votes(sess, playlist, 1).await {
+        Ok(votes) => Ok(Some(votes)),
+        Err(e) => {
+            sess.query_prepared(
+                "DELETE FROM playlist_votes WHERE user_id = ? AND playlist_id = ?;",
+                (user_id, playlist.id),
+            ).await?;
+
+            Err(e)
+        },
+    }
+}
+
+/// Removes the user's vote and decrements the vote count, returning the new
+/// count or None if the user hasn't voted on the playlist, e.g. because a
+/// concurrent request removed it first.
+pub async fn remove_playlist_vote(sess: &Session, user_id: i64, playlist: &Playlist) -> ApiResult<Option<i32>> {
+    let result = sess.query_prepared(
+        "DELETE FROM playlist_votes WHERE user_id = ? AND playlist_id = ? IF EXISTS;",
+        (user_id, playlist.id),
+    ).await?;
+
+    if !db::was_applied(&result) {
+        return Ok(None)
+    }
+
+    adjust_playlist_votes(sess, playlist, -1).await.map(Some)
+}
+
+/// Adds `delta` to the playlist's vote count, returning the new count.
+///
+/// Like `spend_user_credits` the count is only changed if it hasn't been
+/// modified since it was read, so concurrent votes can't overwrite each other.
+async fn adjust_playlist_votes(sess: &Session, playlist: &Playlist, delta: i32) -> ApiResult<i32> {
+    for _ in 0..MAX_VOTE_UPDATE_ATTEMPTS {
+        let result = sess.query_prepared(
+            "SELECT votes FROM playlists WHERE id = ? AND owner_id = ?;",
+            (playlist.id, *playlist.owner_id)
+        ).await?;
+
+        let rows = result.rows
+            .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+        let current = match rows.into_typed::<(Option<i32>,)>().next() {
+            None => return Err(anyhow!("playlist {} no longer exists", playlist.id).into()),
+            Some(v) => v?.0,
+        };
+
+        let votes = current.unwrap_or(0) + delta;
+        let result = sess.query_prepared(
+            "UPDATE playlists SET votes = ? WHERE id = ? AND owner_id = ? IF votes = ?;",
+            (votes, playlist.id, *playlist.owner_id, current)
+        ).await?;
+
+        if db::was_applied(&result) {
+            return Ok(votes)
+        }
+    }
+
+    Err(anyhow!("failed to update playlist votes due to concurrent updates").into())
+}
+
+/// Gets when the user last up-voted the playlist, or None if they have not
+/// voted on it recently.
+pub async fn get_vote_time(sess: &Session, user_id: i64, playlist_id: Uuid) -> ApiResult<Option<DateTime<Utc>>> {
+    let result = sess.query_prepared(
+        "SELECT voted_on FROM playlist_votes WHERE user_id = ? AND playlist_id = ?;",
+        (user_id, playlist_id)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let voted_on = rows.into_typed::<(Option<chrono::Duration>,)>()
+        .next()
+        .transpose()?
+        .and_then(|v| v.0)
+        .map(utils::from_cql_timestamp);
+
+    Ok(voted_on)
+}
+
+pub async fn has_user_voted(sess: &Session, user_id: i64, playlist_id: Uuid) -> ApiResult<bool> {
+    let result = sess.query_prepared(
+        "SELECT user_id FROM playlist_votes WHERE user_id = ? AND playlist_id = ?;",
         (user_id, playlist_id)
     ).await?;
 
     let rows = result.rows
         .ok_or_else(|| anyhow!("expected returned rows"))?;
 
-    let has_votes = Option::flatten(
-        rows.into_typed::<(bool,)>()
-            .next()
-            .map(|v| v.ok().map(|v| v.0))
-    ).unwrap_or(false);
+    Ok(!rows.is_empty())
+}
+
+/// Gets the number of seconds until the user can vote on the playlist again,
+/// or None if the user has not voted on it recently.
+pub async fn get_vote_cooldown(sess: &Session, user_id: i64, playlist_id: Uuid) -> ApiResult<Option<u64>> {
+    let result = sess.query_prepared(
+        "SELECT TTL(voted_on) FROM playlist_votes WHERE user_id = ? AND playlist_id = ?;",
+        (user_id, playlist_id)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let cooldown = match rows.into_typed::<(Option<i32>,)>().next() {
+        None => return Ok(None),
+        Some(v) => v?.0.unwrap_or(0),
+    };
+
+    Ok(Some(cooldown.max(0) as u64))
+}
+
+/// Counts the number of playlists owned by the given user, not including
+/// removed playlists.
+pub async fn count_playlists_for_owner(sess: &Session, owner_id: i64) -> ApiResult<i64> {
+    let result = sess.query_prepared(
+        "SELECT deleted_at FROM playlists_by_owner WHERE owner_id = ?;",
+        (owner_id,)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let count = rows.into_typed::<(Option<chrono::Duration>,)>()
+        .filter_map(|v| v.ok())
+        .filter(|v| v.0.is_none())
+        .count();
+
+    Ok(count as i64)
+}
+
+/// Gets the id of a playlist owned by the given user with the same title,
+/// ignoring case and surrounding whitespace, not including removed playlists.
+pub async fn find_playlist_with_title(sess: &Session, owner_id: i64, title: &str) -> ApiResult<Option<Uuid>> {
+    let result = sess.query_prepared(
+        "SELECT id, title, deleted_at FROM playlists_by_owner WHERE owner_id = ?;",
+        (owner_id,)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let title = normalize_title(title);
+    let found = rows.into_typed::<(Uuid, String, Option<chrono::Duration>)>()
+        .filter_map(|v| v.ok())
+        .find(|(_, existing, deleted_at)| deleted_at.is_none() & (normalize_title(existing) == title))
+        .map(|(id, _, _)| id);
+
+    Ok(found)
+}
 
-    Ok(has_votes)
+fn normalize_title(title: &str) -> String {
+    title.trim().to_lowercase()
 }
 
-pub async fn remove_playlist(sess: &Session, playlist_id: Uuid) -> anyhow::Result<()> {
+/// Gets the ids of every playlist owned by the given user, including removed
+/// playlists.
+pub async fn get_playlist_ids_for_owner(sess: &Session, owner_id: i64) -> ApiResult<Vec<Uuid>> {
+    let result = sess.query_prepared(
+        "SELECT id FROM playlists_by_owner WHERE owner_id = ?;",
+        (owner_id,)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let ids = rows.into_typed::<(Uuid,)>()
+        .filter_map(|v| v.ok())
+        .map(|v| v.0)
+        .collect();
+
+    Ok(ids)
+}
+
+/// Records which playlists reference which entries after a playlist's items
+/// change from `previous` to `current`.
+pub async fn update_entry_refs(
+    sess: &Session,
+    playlist_id: Uuid,
+    previous: &[Uuid],
+    current: &[Uuid],
+) -> ApiResult<()> {
+    let previous: HashSet<Uuid> = previous.iter().copied().collect();
+    let current: HashSet<Uuid> = current.iter().copied().collect();
+
+    let mut queries = vec![];
+    let mut values = vec![];
+    for entry_id in current.difference(&previous) {
+        queries.push("INSERT INTO entry_playlist_refs (entry_id, playlist_id) VALUES (?, ?);");
+        values.push((*entry_id, playlist_id));
+    }
+
+    for entry_id in previous.difference(&current) {
+        queries.push("DELETE FROM entry_playlist_refs WHERE entry_id = ? AND playlist_id = ?;");
+        values.push((*entry_id, playlist_id));
+    }
+
+    if queries.is_empty() {
+        return Ok(())
+    }
+
+    sess.batch(&queries, values).await?;
+
+    Ok(())
+}
+
+/// Gets the ids of all playlists which contain the given entry.
+pub async fn get_playlists_referencing(sess: &Session, entry_id: Uuid) -> ApiResult<Vec<Uuid>> {
+    let result = sess.query_prepared(
+        "SELECT playlist_id FROM entry_playlist_refs WHERE entry_id = ?;",
+        (entry_id,)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let ids = rows.into_typed::<(Uuid,)>()
+        .filter_map(|v| v.ok())
+        .map(|v| v.0)
+        .collect();
+
+    Ok(ids)
+}
+
+/// Removes the entry from the playlist, returning false if the playlist
+/// no longer exists or doesn't contain it.
+///
+/// The playlist is saved with `save_playlist` and its nsfw flag recomputed
+/// from the remaining items, the entry's reference to it is dropped either way.
+pub async fn remove_entry_from_playlist(sess: &Session, playlist_id: Uuid, entry_id: Uuid) -> ApiResult<bool> {
+    for _ in 0..MAX_REMOVE_ATTEMPTS {
+        let mut playlist = match get_playlist_by_id(sess, playlist_id).await? {
+            None => return Ok(false),
+            Some(v) => v,
+        };
+
+        let previous = playlist.items.clone();
+        playlist.items.retain(|v| *v != entry_id);
+        if playlist.items.len() == previous.len() {
+            update_entry_refs(sess, playlist_id, &[entry_id], &[]).await?;
+            return Ok(false)
+        }
+
+        let entries = super::get_entries_with_ids(sess, &playlist.items).await?;
+        playlist.nsfw = entries.iter().any(|v| v.nsfw);
+
+        if save_playlist(sess, &mut playlist).await? {
+            update_entry_refs(sess, playlist_id, &previous, &playlist.items).await?;
+            return Ok(true)
+        }
+    }
+
+    Err(anyhow!("failed to remove entry {} from playlist {} due to concurrent edits", entry_id, playlist_id).into())
+}
+
+/// Removes the playlist by setting its `deleted_at` so it can be restored
+/// later, the references to its entries are dropped so they can be deleted.
+///
+/// This is a lightweight transaction on `deleted_at` so it returns false if
+/// the playlist does not exist or was already removed, e.g. by a concurrent
+/// request.
+pub async fn remove_playlist(sess: &Session, playlist_id: Uuid) -> ApiResult<bool> {
+    let playlist = match get_playlist_by_id(sess, playlist_id).await? {
+        None => return Ok(false),
+        Some(v) => v,
+    };
+
+    let result = sess.query_prepared(
+        "UPDATE playlists SET deleted_at = ? WHERE id = ? AND owner_id = ? IF deleted_at = null;",
+        (utils::to_cql_timestamp(utils::now()), playlist_id, *playlist.owner_id)
+    ).await?;
+
+    if !db::was_applied(&result) {
+        return Ok(false)
+    }
+
+    update_entry_refs(sess, playlist_id, &playlist.items, &[]).await?;
+    super::stats::listing_changed(sess, playlist_id, super::stats::listed_tags(&playlist), None).await?;
+
+    Ok(true)
+}
+
+/// Restores a removed playlist, adding back the references to its items.
+pub async fn restore_playlist(sess: &Session, playlist: &Playlist) -> ApiResult<()> {
     sess.query_prepared(
-        "DELETE FROM playlists WHERE playlist_id = ?;",
-        (playlist_id,)
+        "UPDATE playlists SET deleted_at = null WHERE id = ? AND owner_id = ?;",
+        (playlist.id, *playlist.owner_id)
     ).await?;
 
+    update_entry_refs(sess, playlist.id, &[], &playlist.items).await?;
+    let restored = playlist.is_public.then_some(playlist.tags.as_slice());
+    super::stats::listing_changed(sess, playlist.id, None, restored).await?;
+
+    Ok(())
+}
+
+/// Permanently deletes a playlist, whether or not it has been removed.
+///
+/// Votes are keyed by user so can't be removed by playlist, they are left
+/// to expire with their TTL instead.
+pub async fn purge_playlist(sess: &Session, playlist_id: Uuid) -> ApiResult<()> {
+    if let Some(playlist) = get_playlist_including_deleted(sess, playlist_id).await? {
+        update_entry_refs(sess, playlist_id, &playlist.items, &[]).await?;
+        super::stats::listing_changed(sess, playlist_id, super::stats::listed_tags(&playlist), None).await?;
+    }
+
+    super::collaborators::remove_collaborators(sess, playlist_id).await?;
+    set_playlist_featured(sess, playlist_id, false).await?;
+
     sess.query_prepared(
-        "DELETE FROM playlist_votes WHERE playlist_id = ?;",
+        "DELETE FROM playlists WHERE id = ?;",
         (playlist_id,)
     ).await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// These need a Scylla node to run against, start one with
+/// `docker-compose up scylla` and run `cargo test -- --ignored`.
+#[cfg(test)]
+mod tests {
+    use futures_util::future::join_all;
+    use poem::{Endpoint, EndpointExt, IntoResponse, Request, Response};
+    use poem::http::{header, Method, StatusCode};
+    use poem_openapi::OpenApiService;
+    use serde_json::Value;
+    use tokio::sync::watch;
+
+    use crate::config::Config;
+    use crate::users::user_info;
+    use crate::webhooks;
+    use super::super::{leaderboard::Leaderboard, live::VoteUpdates, PlaylistsApi};
+    use super::*;
+
+    const SCYLLA_NODE: &str = "127.0.0.1:9042";
+
+    /// A user id which won't belong to a real Discord user.
+    fn test_user_id() -> i64 {
+        -(rand::random::<u32>() as i64) - 1
+    }
+
+    /// Gives a new test user an access token, returning their id and token.
+    async fn insert_test_user(sess: &Session) -> (i64, String) {
+        let user_id = test_user_id();
+        let token = format!("test-{}", Uuid::new_v4());
+        sess.query_prepared(
+            "INSERT INTO access_tokens (user_id, access_token) VALUES (?, ?);",
+            (user_id, token.clone())
+        ).await.unwrap();
+
+        (user_id, token)
+    }
+
+    async fn insert_test_playlist(sess: &Session) -> Playlist {
+        insert_owned_playlist(sess, test_user_id(), true).await
+    }
+
+    async fn insert_owned_playlist(sess: &Session, owner_id: i64, is_public: bool) -> Playlist {
+        let id = Uuid::new_v4();
+        sess.query_prepared(
+            "INSERT INTO playlists (id, owner_id, title, is_public, nsfw, votes, version) VALUES (?, ?, 'Votes', ?, false, 0, 0);",
+            (id, owner_id, is_public)
+        ).await.unwrap();
+
+        get_playlist_by_id(sess, id).await.unwrap().unwrap()
+    }
+
+    /// Serves `PlaylistsApi` with the data `main` gives it, without the
+    /// middleware.
+    fn test_app(sess: &Session) -> impl Endpoint<Output = Response> {
+        let config = Config::from_env().unwrap();
+        let client = reqwest::Client::new();
+        let (_, shutdown) = watch::channel(false);
+        let (webhooks, _) = webhooks::spawn(client.clone(), &config, shutdown);
+
+        OpenApiService::new(PlaylistsApi, "Spooderfy API", "1.0.0")
+            .data(sess.clone())
+            .data(VoteUpdates::new(config.max_vote_subscribers))
+            .data(Leaderboard::default())
+            .data(webhooks)
+            .data(client)
+            .data(config)
+            .map_to_response()
+    }
+
+    /// Sends a request to the app, as the user with the token if one is given.
+    async fn send(
+        app: &impl Endpoint<Output = Response>,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> Response {
+        let mut req = Request::builder().method(method).uri_str(uri);
+        if let Some(token) = token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+
+        let req = match body {
+            None => req.finish(),
+            Some(body) => req
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(body.to_string()),
+        };
+
+        match app.call(req).await {
+            Ok(resp) => resp.into_response(),
+            Err(e) => e.as_response(),
+        }
+    }
+
+    async fn json_body(resp: Response) -> Value {
+        resp.into_body().into_json().await.unwrap()
+    }
+
+    async fn delete_test_playlist(sess: &Session, playlist: &Playlist) {
+        sess.query_prepared("DELETE FROM playlists WHERE id = ?;", (playlist.id,)).await.unwrap();
+    }
+
+    async fn current_votes(sess: &Session, playlist: &Playlist) -> i32 {
+        get_playlist_by_id(sess, playlist.id).await.unwrap().unwrap().votes
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn concurrent_votes_are_all_counted() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let playlist = insert_test_playlist(&sess).await;
+        let users: Vec<i64> = (0..5).map(|_| test_user_id()).collect();
+
+        let results = join_all(users.iter().map(|user_id| upvote_playlist(&sess, *user_id, &playlist, 60))).await;
+        let mut counts: Vec<i32> = results.into_iter().map(|v| v.unwrap().unwrap()).collect();
+        counts.sort_unstable();
+
+        assert_eq!(counts, vec![1, 2, 3, 4, 5]);
+        assert_eq!(current_votes(&sess, &playlist).await, 5);
+
+        let results = join_all(users.iter().map(|user_id| remove_playlist_vote(&sess, *user_id, &playlist))).await;
+        assert!(results.into_iter().all(|v| v.unwrap().is_some()));
+        assert_eq!(current_votes(&sess, &playlist).await, 0);
+
+        delete_test_playlist(&sess, &playlist).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn concurrent_votes_by_one_user_count_once() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let playlist = insert_test_playlist(&sess).await;
+        let user_id = test_user_id();
+
+        let (first, second) = tokio::join!(
+            upvote_playlist(&sess, user_id, &playlist, 60),
+            upvote_playlist(&sess, user_id, &playlist, 60),
+        );
+        let counted: Vec<i32> = [first.unwrap(), second.unwrap()].into_iter().flatten().collect();
+
+        assert_eq!(counted, vec![1]);
+        assert_eq!(current_votes(&sess, &playlist).await, 1);
+
+        let (first, second) = tokio::join!(
+            remove_playlist_vote(&sess, user_id, &playlist),
+            remove_playlist_vote(&sess, user_id, &playlist),
+        );
+        let removed: Vec<i32> = [first.unwrap(), second.unwrap()].into_iter().flatten().collect();
+
+        assert_eq!(removed, vec![0]);
+        assert_eq!(current_votes(&sess, &playlist).await, 0);
+
+        delete_test_playlist(&sess, &playlist).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn concurrent_votes_with_one_credit_only_spend_it_once() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let playlists = [insert_test_playlist(&sess).await, insert_test_playlist(&sess).await];
+        let user_id = test_user_id();
+        user_info::adjust_user_credits(&sess, user_id, 1).await.unwrap();
+
+        // This follows the upvote endpoint, only voting if the credit was spent.
+        let results = join_all(playlists.iter().map(|playlist| {
+            let sess = &sess;
+            async move {
+                if !user_info::spend_user_credits(sess, user_id, 1).await.unwrap() {
+                    return false
+                }
+
+                upvote_playlist(sess, user_id, playlist, 60).await.unwrap().is_some()
+            }
+        })).await;
+
+        assert_eq!(results.iter().filter(|v| **v).count(), 1);
+        assert_eq!(user_info::get_user_vote_credits(&sess, user_id).await.unwrap(), 0);
+
+        let mut votes = 0;
+        for playlist in &playlists {
+            votes += current_votes(&sess, playlist).await;
+            delete_test_playlist(&sess, playlist).await;
+        }
+        assert_eq!(votes, 1);
+
+        sess.query_prepared("DELETE FROM user_vote_credits WHERE user_id = ?;", (user_id,)).await.unwrap();
+    }
+
+    async fn insert_owned_entry(sess: &Session, owner_id: i64, is_public: bool) -> PlaylistEntry {
+        let id = Uuid::new_v4();
+        sess.query_prepared(
+            "INSERT INTO playlist_entries (id, owner_id, title, is_public, nsfw, votes) VALUES (?, ?, 'Entry', ?, false, 0);",
+            (id, owner_id, is_public)
+        ).await.unwrap();
+
+        super::super::get_entry_by_id(sess, id).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn private_playlists_are_only_returned_to_the_owner_and_collaborators() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let app = test_app(&sess);
+        let (owner_id, owner) = insert_test_user(&sess).await;
+        let (collaborator_id, collaborator) = insert_test_user(&sess).await;
+        let (_, other) = insert_test_user(&sess).await;
+        let playlist = insert_owned_playlist(&sess, owner_id, false).await;
+        super::super::collaborators::add_collaborator(&sess, playlist.id, collaborator_id).await.unwrap();
+
+        let uri = format!("/playlists?id={}", playlist.id);
+        let missing = send(&app, Method::GET, &format!("/playlists?id={}", Uuid::new_v4()), None, None).await;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        let missing = json_body(missing).await;
+
+        for token in [None, Some(other.as_str())] {
+            let resp = send(&app, Method::GET, &uri, token, None).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            assert_eq!(json_body(resp).await, missing);
+        }
+
+        for token in [owner.as_str(), collaborator.as_str()] {
+            let resp = send(&app, Method::GET, &uri, Some(token), None).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        let entry = insert_owned_entry(&sess, owner_id, false).await;
+        let uri = format!("/entries?id={}", entry.id);
+        assert_eq!(send(&app, Method::GET, &uri, Some(&other), None).await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(send(&app, Method::GET, &uri, Some(&owner), None).await.status(), StatusCode::OK);
+
+        sess.query_prepared("DELETE FROM playlist_entries WHERE id = ?;", (entry.id,)).await.unwrap();
+        super::super::collaborators::remove_collaborators(&sess, playlist.id).await.unwrap();
+        delete_test_playlist(&sess, &playlist).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn private_playlists_not_owned_return_not_found() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let app = test_app(&sess);
+        let (owner_id, _) = insert_test_user(&sess).await;
+        let (_, other) = insert_test_user(&sess).await;
+        let playlist = insert_owned_playlist(&sess, owner_id, false).await;
+
+        let requests = [
+            (Method::DELETE, "/playlists"),
+            (Method::POST, "/playlists/vote"),
+            (Method::POST, "/favorites"),
+            (Method::POST, "/playlists/clone"),
+        ];
+
+        for (method, path) in requests {
+            let missing = send(&app, method.clone(), &format!("{}?id={}", path, Uuid::new_v4()), Some(&other), None).await;
+            let resp = send(&app, method, &format!("{}?id={}", path, playlist.id), Some(&other), None).await;
+
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{} {}", path, playlist.id);
+            assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+            assert_eq!(json_body(resp).await, json_body(missing).await, "{} differs from a missing playlist", path);
+        }
+
+        assert!(get_playlist_by_id(&sess, playlist.id).await.unwrap().is_some());
+        delete_test_playlist(&sess, &playlist).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn stats_of_hidden_playlists_are_not_found() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let app = test_app(&sess);
+        let (owner_id, owner) = insert_test_user(&sess).await;
+        let (_, other) = insert_test_user(&sess).await;
+        let private = insert_owned_playlist(&sess, owner_id, false).await;
+        let nsfw = insert_owned_playlist(&sess, owner_id, true).await;
+        sess.query_prepared(
+            "UPDATE playlists SET nsfw = true WHERE id = ? AND owner_id = ?;",
+            (nsfw.id, owner_id)
+        ).await.unwrap();
+
+        for (playlist, token) in [(&private, None), (&private, Some(other.as_str())), (&nsfw, Some(other.as_str()))] {
+            let resp = send(&app, Method::GET, &format!("/playlists/stats?id={}", playlist.id), token, None).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        }
+
+        let resp = send(&app, Method::GET, &format!("/playlists/stats?id={}", private.id), Some(&owner), None).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        delete_test_playlist(&sess, &private).await;
+        delete_test_playlist(&sess, &nsfw).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn refreshing_nsfw_bumps_the_playlist_version() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let owner_id = test_user_id();
+        let entry = insert_owned_entry(&sess, owner_id, true).await;
+        let mut playlist = insert_owned_playlist(&sess, owner_id, true).await;
+        playlist.items = vec![entry.id];
+        assert!(save_playlist(&sess, &mut playlist).await.unwrap());
+        update_entry_refs(&sess, playlist.id, &[], &playlist.items).await.unwrap();
+
+        sess.query_prepared(
+            "UPDATE playlist_entries SET nsfw = true WHERE id = ? AND owner_id = ?;",
+            (entry.id, owner_id)
+        ).await.unwrap();
+        super::super::refresh_playlists_nsfw(&sess, entry.id).await.unwrap();
+
+        let refreshed = get_playlist_by_id(&sess, playlist.id).await.unwrap().unwrap();
+        assert!(refreshed.nsfw);
+        assert_eq!(refreshed.version, playlist.version + 1);
+        assert!(refreshed.updated_at > playlist.updated_at);
+
+        // A save based on the playlist from before the refresh must be rejected
+        // rather than writing back the old flag.
+        assert!(!save_playlist(&sess, &mut playlist).await.unwrap());
+
+        update_entry_refs(&sess, playlist.id, &playlist.items, &[]).await.unwrap();
+        sess.query_prepared("DELETE FROM playlist_entries WHERE id = ?;", (entry.id,)).await.unwrap();
+        delete_test_playlist(&sess, &playlist).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn updating_a_playlist_with_new_entries_is_rejected() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let app = test_app(&sess);
+        let (owner_id, owner) = insert_test_user(&sess).await;
+        let entry = insert_owned_entry(&sess, owner_id, true).await;
+        let playlist = insert_owned_playlist(&sess, owner_id, true).await;
+
+        let payload = serde_json::json!({
+            "title": "Updated",
+            "is_public": true,
+            "items": [entry.id],
+            "new_entries": [{"title": "New entry", "is_public": true}],
+        });
+        let uri = format!("/playlists?id={}", playlist.id);
+        let resp = send(&app, Method::PUT, &uri, Some(&owner), Some(payload)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let unchanged = get_playlist_by_id(&sess, playlist.id).await.unwrap().unwrap();
+        assert_eq!(unchanged.version, playlist.version);
+        assert_eq!(unchanged.title, playlist.title);
+
+        sess.query_prepared("DELETE FROM playlist_entries WHERE id = ?;", (entry.id,)).await.unwrap();
+        delete_test_playlist(&sess, &playlist).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn purging_an_entry_removes_it_from_playlists() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let owner_id = test_user_id();
+        let purged = insert_owned_entry(&sess, owner_id, true).await;
+        let kept = insert_owned_entry(&sess, owner_id, true).await;
+        let mut playlist = insert_owned_playlist(&sess, test_user_id(), true).await;
+        playlist.items = vec![purged.id, kept.id];
+        assert!(save_playlist(&sess, &mut playlist).await.unwrap());
+        update_entry_refs(&sess, playlist.id, &[], &playlist.items).await.unwrap();
+
+        assert_eq!(super::super::purge_entry(&sess, purged.id).await.unwrap(), 1);
+
+        let updated = get_playlist_by_id(&sess, playlist.id).await.unwrap().unwrap();
+        assert_eq!(updated.items, vec![kept.id]);
+        assert_eq!(updated.version, playlist.version + 1);
+        assert!(get_playlists_referencing(&sess, purged.id).await.unwrap().is_empty());
+        assert!(super::super::get_entry_including_deleted(&sess, purged.id).await.unwrap().is_none());
+
+        update_entry_refs(&sess, playlist.id, &updated.items, &[]).await.unwrap();
+        sess.query_prepared("DELETE FROM playlist_entries WHERE id = ?;", (kept.id,)).await.unwrap();
+        delete_test_playlist(&sess, &playlist).await;
+    }
+
+    /// The key the superuser tests authenticate with, `SUPERUSER_KEY` must be
+    /// set to one of the accepted keys.
+    fn superuser_key() -> String {
+        std::env::var("SUPERUSER_KEY").expect("SUPERUSER_KEY must be set for the superuser tests")
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn deleting_twice_returns_not_found() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let app = test_app(&sess);
+        let (owner_id, owner) = insert_test_user(&sess).await;
+        let (moderator_id, moderator) = insert_test_user(&sess).await;
+        sess.query_prepared("INSERT INTO moderators (user_id) VALUES (?);", (moderator_id,)).await.unwrap();
+        let superuser = superuser_key();
+
+        let owned = insert_owned_playlist(&sess, owner_id, true).await;
+        let moderated = insert_owned_playlist(&sess, owner_id, true).await;
+        let removed = insert_owned_playlist(&sess, owner_id, true).await;
+        let purged = insert_owned_playlist(&sess, owner_id, true).await;
+        let owned_entry = insert_owned_entry(&sess, owner_id, true).await;
+        let removed_entry = insert_owned_entry(&sess, owner_id, true).await;
+        let purged_entry = insert_owned_entry(&sess, owner_id, true).await;
+
+        let requests = [
+            (format!("/playlists?id={}", owned.id), owner.as_str()),
+            (format!("/playlists/moderate?id={}", moderated.id), moderator.as_str()),
+            (format!("/playlists/override?id={}", removed.id), superuser.as_str()),
+            (format!("/playlists/override?id={}&hard=true", purged.id), superuser.as_str()),
+            (format!("/entries?id={}", owned_entry.id), owner.as_str()),
+            (format!("/entries/override?id={}", removed_entry.id), superuser.as_str()),
+            (format!("/entries/override?id={}&hard=true", purged_entry.id), superuser.as_str()),
+        ];
+
+        for (uri, token) in requests.iter() {
+            let first = send(&app, Method::DELETE, uri, Some(token), None).await;
+            assert_eq!(first.status(), StatusCode::OK, "{}", uri);
+
+            let second = send(&app, Method::DELETE, uri, Some(token), None).await;
+            assert_eq!(second.status(), StatusCode::NOT_FOUND, "{}", uri);
+        }
+
+        // Only the deletes which removed something are in the audit log.
+        let audited = crate::audit::get_recent_entries(&sess, 1000).await.unwrap();
+        for target_id in [removed.id, purged.id, removed_entry.id, purged_entry.id] {
+            assert_eq!(audited.iter().filter(|v| v.target_id == target_id).count(), 1);
+        }
+
+        for playlist in [&owned, &moderated, &removed] {
+            delete_test_playlist(&sess, playlist).await;
+        }
+        for entry in [&owned_entry, &removed_entry] {
+            sess.query_prepared("DELETE FROM playlist_entries WHERE id = ?;", (entry.id,)).await.unwrap();
+        }
+        sess.query_prepared("DELETE FROM moderators WHERE user_id = ?;", (moderator_id,)).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn created_entries_are_returned_as_inserted() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let app = test_app(&sess);
+        let (owner_id, owner) = insert_test_user(&sess).await;
+
+        let payload = serde_json::json!({
+            "title": "Created entry",
+            "description": "Created by a test",
+            "is_public": true,
+        });
+        let resp = send(&app, Method::POST, "/entries", Some(&owner), Some(payload)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let created = json_body(resp).await;
+        let id: Uuid = created["id"].as_str().unwrap().parse().unwrap();
+        let inserted = super::super::get_entry_by_id(&sess, id).await.unwrap().unwrap();
+        assert_eq!(created["id"], serde_json::json!(inserted.id));
+        assert_eq!(*inserted.owner_id, owner_id);
+        assert_eq!(inserted.title, "Created entry");
+        assert_eq!(inserted.description.as_deref(), Some("Created by a test"));
+        assert_eq!(inserted.votes, 0);
+
+        sess.query_prepared("DELETE FROM playlist_entries WHERE id = ?;", (id,)).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn repeated_idempotency_keys_create_one_playlist() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let app = test_app(&sess);
+        let (owner_id, owner) = insert_test_user(&sess).await;
+        let entry = insert_owned_entry(&sess, owner_id, true).await;
+
+        let payload = serde_json::json!({
+            "title": "Idempotent",
+            "is_public": true,
+            "items": [entry.id],
+        });
+        let key = Uuid::new_v4().to_string();
+
+        let mut responses = vec![];
+        for _ in 0..2 {
+            let req = Request::builder()
+                .method(Method::POST)
+                .uri_str("/playlists")
+                .header(header::AUTHORIZATION, format!("Bearer {}", owner))
+                .header("Idempotency-Key", key.as_str())
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(payload.to_string());
+
+            let resp = app.call(req).await.unwrap().into_response();
+            assert_eq!(resp.status(), StatusCode::OK);
+            responses.push(json_body(resp).await);
+        }
+
+        assert_eq!(responses[0], responses[1]);
+        let ids = get_playlist_ids_for_owner(&sess, owner_id).await.unwrap();
+        assert_eq!(ids.len(), 1);
+        assert_eq!(responses[0]["id"], serde_json::json!(ids[0]));
+
+        purge_playlist(&sess, ids[0]).await.unwrap();
+        sess.query_prepared("DELETE FROM playlist_entries WHERE id = ?;", (entry.id,)).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn only_collaborators_can_edit_other_users_playlists() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let app = test_app(&sess);
+        let (owner_id, _) = insert_test_user(&sess).await;
+        let (collaborator_id, collaborator) = insert_test_user(&sess).await;
+        let (_, other) = insert_test_user(&sess).await;
+        let entry = insert_owned_entry(&sess, owner_id, true).await;
+        let playlist = insert_owned_playlist(&sess, owner_id, true).await;
+        super::super::collaborators::add_collaborator(&sess, playlist.id, collaborator_id).await.unwrap();
+
+        let patch = |version: i32| Some(serde_json::json!({"title": "Edited", "version": version}));
+        let items_uri = format!("/playlists/items?id={}", playlist.id);
+        let item_uri = format!("/playlists/items?id={}&item_id={}", playlist.id, entry.id);
+        let patch_uri = format!("/playlists?id={}", playlist.id);
+
+        // The playlist is public so someone else editing it gets a 403 rather
+        // than the 404 a private playlist would give.
+        let resp = send(&app, Method::PATCH, &patch_uri, Some(&other), patch(playlist.version)).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = send(&app, Method::POST, &items_uri, Some(&other), Some(serde_json::json!([entry.id]))).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = send(&app, Method::DELETE, &item_uri, Some(&other), None).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(get_playlist_by_id(&sess, playlist.id).await.unwrap().unwrap().version, playlist.version);
+
+        let resp = send(&app, Method::PATCH, &patch_uri, Some(&collaborator), patch(playlist.version)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(json_body(resp).await["title"], "Edited");
+
+        let resp = send(&app, Method::POST, &items_uri, Some(&collaborator), Some(serde_json::json!([entry.id]))).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(json_body(resp).await["items"], serde_json::json!([entry.id]));
+
+        let resp = send(&app, Method::DELETE, &item_uri, Some(&collaborator), None).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(json_body(resp).await["items"], serde_json::json!([]));
+
+        sess.query_prepared("DELETE FROM playlist_entries WHERE id = ?;", (entry.id,)).await.unwrap();
+        super::super::collaborators::remove_collaborators(&sess, playlist.id).await.unwrap();
+        delete_test_playlist(&sess, &playlist).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn removing_a_middle_item_keeps_the_order_of_the_rest() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let app = test_app(&sess);
+        let (owner_id, owner) = insert_test_user(&sess).await;
+        let mut entries = vec![];
+        for _ in 0..3 {
+            entries.push(insert_owned_entry(&sess, owner_id, true).await);
+        }
+
+        let mut playlist = insert_owned_playlist(&sess, owner_id, true).await;
+        playlist.items = entries.iter().map(|v| v.id).collect();
+        assert!(save_playlist(&sess, &mut playlist).await.unwrap());
+        update_entry_refs(&sess, playlist.id, &[], &playlist.items).await.unwrap();
+
+        let uri = format!("/playlists/items?id={}&item_id={}", playlist.id, entries[1].id);
+        let resp = send(&app, Method::DELETE, &uri, Some(&owner), None).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let remaining = vec![entries[0].id, entries[2].id];
+        assert_eq!(json_body(resp).await["items"], serde_json::json!(remaining));
+        assert_eq!(get_playlist_by_id(&sess, playlist.id).await.unwrap().unwrap().items, remaining);
+
+        update_entry_refs(&sess, playlist.id, &remaining, &[]).await.unwrap();
+        for entry in entries {
+            sess.query_prepared("DELETE FROM playlist_entries WHERE id = ?;", (entry.id,)).await.unwrap();
+        }
+        delete_test_playlist(&sess, &playlist).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn votes_can_be_cast_again_once_they_expire() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let playlist = insert_test_playlist(&sess).await;
+        let entry = insert_owned_entry(&sess, test_user_id(), true).await;
+        let user_id = test_user_id();
+
+        assert_eq!(upvote_playlist(&sess, user_id, &playlist, 1).await.unwrap(), Some(1));
+        assert_eq!(upvote_playlist(&sess, user_id, &playlist, 1).await.unwrap(), None);
+        assert_eq!(super::super::entries::upvote_playlist(&sess, user_id, &entry, 1).await.unwrap(), Some(1));
+        assert_eq!(super::super::entries::upvote_playlist(&sess, user_id, &entry, 1).await.unwrap(), None);
+
+        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+
+        assert!(!has_user_voted(&sess, user_id, playlist.id).await.unwrap());
+        assert_eq!(get_vote_cooldown(&sess, user_id, playlist.id).await.unwrap(), None);
+        assert_eq!(upvote_playlist(&sess, user_id, &playlist, 1).await.unwrap(), Some(2));
+        assert_eq!(super::super::entries::upvote_playlist(&sess, user_id, &entry, 1).await.unwrap(), Some(2));
+
+        sess.query_prepared("DELETE FROM playlist_entries WHERE id = ?;", (entry.id,)).await.unwrap();
+        delete_test_playlist(&sess, &playlist).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn only_other_users_can_vote_on_content() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let app = test_app(&sess);
+        let cost = Config::from_env().unwrap().vote_credit_cost;
+        let (owner_id, owner) = insert_test_user(&sess).await;
+        let (voter_id, voter) = insert_test_user(&sess).await;
+        user_info::adjust_user_credits(&sess, owner_id, cost * 2).await.unwrap();
+        user_info::adjust_user_credits(&sess, voter_id, cost * 2).await.unwrap();
+
+        let playlist = insert_owned_playlist(&sess, owner_id, true).await;
+        let entry = insert_owned_entry(&sess, owner_id, true).await;
+        let uris = [
+            format!("/playlists/vote?id={}", playlist.id),
+            format!("/entries/vote?id={}", entry.id),
+        ];
+
+        for uri in uris.iter() {
+            let resp = send(&app, Method::POST, uri, Some(&owner), None).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", uri);
+            assert_eq!(json_body(resp).await["detail"], "You cannot vote on your own content.");
+
+            let resp = send(&app, Method::POST, uri, Some(&voter), None).await;
+            assert_eq!(resp.status(), StatusCode::OK, "{}", uri);
+            assert_eq!(json_body(resp).await["votes"], 1);
+        }
+
+        // Only the voter spent credits.
+        assert_eq!(user_info::get_user_vote_credits(&sess, owner_id).await.unwrap(), cost * 2);
+        assert_eq!(user_info::get_user_vote_credits(&sess, voter_id).await.unwrap(), 0);
+        assert_eq!(current_votes(&sess, &playlist).await, 1);
+
+        remove_playlist_vote(&sess, voter_id, &playlist).await.unwrap();
+        super::super::remove_entry_vote(&sess, voter_id, &entry).await.unwrap();
+        for user_id in [owner_id, voter_id] {
+            sess.query_prepared("DELETE FROM user_vote_credits WHERE user_id = ?;", (user_id,)).await.unwrap();
+        }
+        sess.query_prepared("DELETE FROM playlist_entries WHERE id = ?;", (entry.id,)).await.unwrap();
+        delete_test_playlist(&sess, &playlist).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn matching_etags_get_not_modified() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let app = test_app(&sess);
+        let playlist = insert_test_playlist(&sess).await;
+        let uri = format!("/playlists?id={}", playlist.id);
+
+        let resp = send(&app, Method::GET, &uri, None, None).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let etag = resp.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri_str(&uri)
+            .header(header::IF_NONE_MATCH, etag.as_str())
+            .finish();
+        let resp = app.call(req).await.unwrap().into_response();
+
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers().get(header::ETAG).unwrap().to_str().unwrap(), etag);
+        assert!(resp.into_body().into_vec().await.unwrap().is_empty());
+
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri_str(&uri)
+            .header(header::IF_NONE_MATCH, "\"stale\"")
+            .finish();
+        assert_eq!(app.call(req).await.unwrap().into_response().status(), StatusCode::OK);
+
+        delete_test_playlist(&sess, &playlist).await;
+    }
+}
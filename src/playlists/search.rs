@@ -0,0 +1,178 @@
+use std::cmp::{Ordering, Reverse};
+
+use crate::db::Session;
+use crate::errors::ApiResult;
+use super::{discover, playlist, Playlist};
+
+
+/// The maximum number of public playlists searched.
+///
+/// Scylla can't search titles, so like discovery a bounded candidate set is
+/// read from `listed_playlists`, an equal share from each bucket, and
+/// matched in process.
+const MAX_CANDIDATES: i32 = 1000;
+
+/// The `min_score` used for fuzzy searches if none is given.
+pub const DEFAULT_MIN_SCORE: f64 = 0.6;
+
+
+/// Gets up to `limit` public playlists whose titles match the query.
+///
+/// Without `fuzzy` titles must contain the query, ignoring case, and the
+/// most voted are returned first. With `fuzzy` every candidate is scored by
+/// `title_score`, those scoring below `min_score` are dropped and the best
+/// matches are returned first with `score` set.
+pub async fn search_playlists(
+    sess: &Session,
+    query: &str,
+    fuzzy: bool,
+    min_score: f64,
+    limit: usize,
+    include_nsfw: bool,
+) -> ApiResult<Vec<Playlist>> {
+    let ids = discover::get_listed_ids(sess, MAX_CANDIDATES / discover::LISTED_BUCKETS).await?;
+    let candidates = playlist::get_playlists_with_ids(sess, &ids).await?
+        .into_iter()
+        .filter(|playlist| playlist.is_public & (include_nsfw | !playlist.nsfw));
+
+    let mut playlists: Vec<Playlist> = if fuzzy {
+        let query = tokenize(query);
+        let mut playlists: Vec<Playlist> = candidates
+            .filter_map(|mut playlist| {
+                let score = title_score(&query, &tokenize(&playlist.title));
+                playlist.score = Some(score);
+                (score >= min_score).then_some(playlist)
+            })
+            .collect();
+
+        playlists.sort_by(|a, b| {
+            b.score.partial_cmp(&a.score)
+                .unwrap_or(Ordering::Equal)
+                .then(b.votes.cmp(&a.votes))
+        });
+        playlists
+    } else {
+        let query = query.trim().to_lowercase();
+        let mut playlists: Vec<Playlist> = candidates
+            .filter(|playlist| playlist.title.to_lowercase().contains(&query))
+            .collect();
+
+        playlists.sort_by_key(|v| Reverse(v.votes));
+        playlists
+    };
+
+    playlists.truncate(limit);
+
+    Ok(playlists)
+}
+
+
+/// Splits the text into lowercase words, anything which isn't a letter or
+/// digit separates words.
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_lowercase())
+        .collect()
+}
+
+/// Scores how closely a title matches the query from `0` to `1`, both given
+/// as words from `tokenize`.
+///
+/// This is the better of two scores so either way of mistyping is tolerated:
+///  - The edit distance between the query and title with the spaces removed,
+///    so `"chil hop"` still closely matches `"Chillhop"`.
+///  - The average of each query word's best match against the title's words,
+///    so queries naming some words of a longer title still match.
+fn title_score(query: &[String], title: &[String]) -> f64 {
+    if query.is_empty() | title.is_empty() {
+        return 0.0
+    }
+
+    let joined = word_similarity(&query.concat(), &title.concat());
+    let overlap = query.iter()
+        .map(|word| {
+            title.iter()
+                .map(|v| word_similarity(word, v))
+                .fold(0.0, f64::max)
+        })
+        .sum::<f64>() / query.len() as f64;
+
+    joined.max(overlap)
+}
+
+/// Scores how similar two words are from `0` to `1`, a word scores `1`
+/// against any word it is the start of so partially typed words match.
+fn word_similarity(typed: &str, word: &str) -> f64 {
+    if word.starts_with(typed) {
+        return 1.0
+    }
+
+    let typed: Vec<char> = typed.chars().collect();
+    let word: Vec<char> = word.chars().collect();
+    let longest = typed.len().max(word.len());
+
+    1.0 - edit_distance(&typed, &word) as f64 / longest as f64
+}
+
+/// The Levenshtein distance between the two strings, the number of single
+/// character insertions, deletions or substitutions turning one into the
+/// other.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + (ca != cb) as usize;
+            current[j + 1] = substitution
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(query: &str, title: &str) -> f64 {
+        title_score(&tokenize(query), &tokenize(title))
+    }
+
+    #[test]
+    fn edit_distance_counts_single_character_edits() {
+        let chars = |v: &str| v.chars().collect::<Vec<_>>();
+
+        assert_eq!(edit_distance(&chars("chillhop"), &chars("chillhop")), 0);
+        assert_eq!(edit_distance(&chars("chilhop"), &chars("chillhop")), 1);
+        assert_eq!(edit_distance(&chars("kitten"), &chars("sitting")), 3);
+        assert_eq!(edit_distance(&chars(""), &chars("lofi")), 4);
+    }
+
+    #[test]
+    fn mistyped_queries_match_the_title() {
+        assert!(score("chil hop", "Chillhop") >= DEFAULT_MIN_SCORE);
+        assert!(score("chillhopp", "Chillhop Essentials") >= DEFAULT_MIN_SCORE);
+        assert!(score("lofi beats", "Late night lo-fi beats") >= DEFAULT_MIN_SCORE);
+    }
+
+    #[test]
+    fn exact_and_partial_words_score_highest() {
+        assert_eq!(score("Chillhop", "chillhop"), 1.0);
+        assert_eq!(score("chill", "Chillhop Essentials"), 1.0);
+        assert!(score("chil hop", "Chillhop") > score("chil hop", "Chill Rock"));
+    }
+
+    #[test]
+    fn unrelated_titles_score_below_the_default_threshold() {
+        assert!(score("chillhop", "Heavy Metal Classics") < DEFAULT_MIN_SCORE);
+        assert_eq!(score("", "Chillhop"), 0.0);
+        assert_eq!(score("!!", "Chillhop"), 0.0);
+    }
+}
@@ -0,0 +1,198 @@
+use anyhow::anyhow;
+use chrono::{DateTime, Duration, Utc};
+use poem_openapi::Object;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use scylla::IntoTypedRows;
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use crate::db::{self, Session};
+use crate::errors::ApiResult;
+use crate::utils;
+use super::Playlist;
+
+
+/// The length of the random part of a share token.
+const SHARE_SECRET_LENGTH: usize = 32;
+
+
+#[derive(Object)]
+pub struct ShareToken {
+    /// Pass this to `GET /playlists/shared` to fetch the playlist.
+    pub token: String,
+
+    /// When the token stops working, if it expires.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+
+/// The result of checking a share token against a playlist.
+pub enum ShareStatus {
+    Valid,
+    Expired,
+    Invalid,
+}
+
+
+/// Share tokens are the playlist id followed by a random secret, so the
+/// playlist can be looked up by id and only the secret needs comparing.
+fn format_token(playlist_id: Uuid, secret: &str) -> String {
+    format!("{}.{}", playlist_id, secret)
+}
+
+/// Splits a share token back into the playlist id and secret.
+pub fn parse_token(token: &str) -> Option<(Uuid, &str)> {
+    let (id, secret) = token.split_once('.')?;
+    let id = Uuid::parse_str(id).ok()?;
+
+    Some((id, secret))
+}
+
+fn generate_secret() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(SHARE_SECRET_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+fn is_expired(expires_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+    expires_at.map(|v| v <= now).unwrap_or(false)
+}
+
+
+/// Gets the playlist's share secret and when it expires, if there is one.
+async fn get_share_secret(
+    sess: &Session,
+    playlist_id: Uuid,
+) -> ApiResult<Option<(String, Option<DateTime<Utc>>)>> {
+    let result = sess.query_prepared(
+        "SELECT share_token, share_expires_at FROM playlists WHERE id = ?;",
+        (playlist_id,)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let secret = rows.into_typed::<(Option<String>, Option<chrono::Duration>)>()
+        .next()
+        .transpose()?
+        .and_then(|(secret, expires_at)| {
+            secret.map(|v| (v, expires_at.map(utils::from_cql_timestamp)))
+        });
+
+    Ok(secret)
+}
+
+/// Gets a share token for the playlist.
+///
+/// Without `expires_in` the playlist's current token is returned if it hasn't
+/// expired, otherwise a token which never expires is generated. With
+/// `expires_in` a new token expiring after that long always replaces the
+/// current one.
+///
+/// The secret is only replaced if it hasn't changed since it was read, so
+/// concurrent requests all get back the same token.
+pub async fn get_or_create_share_token(
+    sess: &Session,
+    playlist: &Playlist,
+    expires_in: Option<Duration>,
+) -> ApiResult<ShareToken> {
+    let now = utils::now();
+    let current = get_share_secret(sess, playlist.id).await?;
+
+    if let (Some((secret, expires_at)), None) = (&current, expires_in) {
+        if !is_expired(*expires_at, now) {
+            return Ok(ShareToken {
+                token: format_token(playlist.id, secret),
+                expires_at: *expires_at,
+            })
+        }
+    }
+
+    let secret = generate_secret();
+    let expires_at = expires_in.map(|v| now + v);
+    let result = sess.query_prepared(
+        r#"
+        UPDATE playlists SET share_token = ?, share_expires_at = ?
+        WHERE id = ? AND owner_id = ?
+        IF share_token = ?;
+        "#,
+        (
+            &secret,
+            expires_at.map(utils::to_cql_timestamp),
+            playlist.id,
+            *playlist.owner_id,
+            current.map(|v| v.0),
+        )
+    ).await?;
+
+    if db::was_applied(&result) {
+        return Ok(ShareToken {
+            token: format_token(playlist.id, &secret),
+            expires_at,
+        })
+    }
+
+    let (secret, expires_at) = get_share_secret(sess, playlist.id).await?
+        .ok_or_else(|| anyhow!("share token was revoked while being created"))?;
+
+    Ok(ShareToken {
+        token: format_token(playlist.id, &secret),
+        expires_at,
+    })
+}
+
+/// Revokes the playlist's share token, links using it stop working.
+pub async fn revoke_share_token(sess: &Session, playlist: &Playlist) -> ApiResult<()> {
+    sess.query_prepared(
+        "UPDATE playlists SET share_token = null, share_expires_at = null WHERE id = ? AND owner_id = ?;",
+        (playlist.id, *playlist.owner_id)
+    ).await?;
+
+    Ok(())
+}
+
+/// Checks the secret against the playlist's current share secret.
+///
+/// The secrets are compared in constant time so the time taken doesn't
+/// reveal how much of the secret matched.
+pub async fn check_share_secret(sess: &Session, playlist_id: Uuid, secret: &str) -> ApiResult<ShareStatus> {
+    let (expected, expires_at) = match get_share_secret(sess, playlist_id).await? {
+        None => return Ok(ShareStatus::Invalid),
+        Some(v) => v,
+    };
+
+    if !bool::from(secret.as_bytes().ct_eq(expected.as_bytes())) {
+        return Ok(ShareStatus::Invalid)
+    }
+
+    if is_expired(expires_at, utils::now()) {
+        return Ok(ShareStatus::Expired)
+    }
+
+    Ok(ShareStatus::Valid)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_valid_until_they_expire() {
+        let created_at = utils::now();
+        let expires_at = Some(created_at + Duration::minutes(5));
+
+        assert!(!is_expired(expires_at, created_at));
+        assert!(!is_expired(expires_at, created_at + Duration::minutes(4)));
+        assert!(is_expired(expires_at, created_at + Duration::minutes(5)));
+        assert!(is_expired(expires_at, created_at + Duration::days(1)));
+    }
+
+    #[test]
+    fn tokens_without_an_expiry_never_expire() {
+        assert!(!is_expired(None, utils::now() + Duration::weeks(520)));
+    }
+}
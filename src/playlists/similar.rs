@@ -0,0 +1,77 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{HashMap, HashSet};
+use anyhow::anyhow;
+use scylla::IntoTypedRows;
+use uuid::Uuid;
+
+use crate::db::Session;
+use crate::errors::ApiResult;
+use super::{get_playlists_with_ids, Playlist};
+
+
+/// The maximum number of entry ids passed to a single `IN` query.
+const MAX_IDS_PER_QUERY: usize = 100;
+
+/// The maximum number of candidate playlists fetched and scored, those
+/// sharing the most entries with the target are kept.
+///
+/// Sharing more entries doesn't always mean a higher similarity, as large
+/// playlists are penalised, but it is a close enough bound to avoid fetching
+/// every playlist which shares a single popular entry.
+const MAX_CANDIDATES: usize = 200;
+
+
+/// Gets up to `limit` public playlists which share the most entries with
+/// the target, most similar first, with `similarity` set to the Jaccard
+/// similarity of their items.
+///
+/// Candidates are gathered from the `entry_playlist_refs` index of the
+/// target's items rather than scanning every playlist.
+pub async fn similar_playlists(
+    sess: &Session,
+    target: &Playlist,
+    limit: usize,
+    include_nsfw: bool,
+) -> ApiResult<Vec<Playlist>> {
+    let items: HashSet<Uuid> = target.items.iter().copied().collect();
+    let items: Vec<Uuid> = items.into_iter().collect();
+
+    let mut shared: HashMap<Uuid, usize> = HashMap::new();
+    for chunk in items.chunks(MAX_IDS_PER_QUERY) {
+        let result = sess.query_prepared(
+            "SELECT playlist_id FROM entry_playlist_refs WHERE entry_id IN ?;",
+            (chunk.to_vec(),)
+        ).await?;
+
+        let rows = result.rows
+            .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+        for (playlist_id,) in rows.into_typed::<(Uuid,)>().filter_map(|v| v.ok()) {
+            if playlist_id != target.id {
+                *shared.entry(playlist_id).or_default() += 1;
+            }
+        }
+    }
+
+    let mut ids: Vec<Uuid> = shared.keys().copied().collect();
+    ids.sort_by_key(|id| Reverse(shared[id]));
+    ids.truncate(MAX_CANDIDATES);
+
+    let mut playlists: Vec<Playlist> = get_playlists_with_ids(sess, &ids).await?
+        .into_iter()
+        .filter(|playlist| playlist.is_public & (include_nsfw | !playlist.nsfw))
+        .map(|mut playlist| {
+            let overlap = shared[&playlist.id];
+            let size = playlist.items.iter().collect::<HashSet<_>>().len();
+            let union = items.len() + size - overlap;
+
+            playlist.similarity = Some(overlap as f64 / union as f64);
+            playlist
+        })
+        .collect();
+
+    playlists.sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap_or(Ordering::Equal));
+    playlists.truncate(limit);
+
+    Ok(playlists)
+}
@@ -0,0 +1,238 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use anyhow::anyhow;
+use poem_openapi::Object;
+use scylla::IntoTypedRows;
+use scylla::frame::value::Counter;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::db::Session;
+use crate::errors::ApiResult;
+use super::discover::{self, LISTED_BUCKETS};
+use super::Playlist;
+
+
+/// How long the count of public playlists is reused before it is counted
+/// again.
+const PUBLIC_COUNT_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// The `tag_counts` partition every tag is counted in, so they can all be
+/// read at once.
+const TAG_SCOPE: &str = "public";
+
+
+lazy_static! {
+    static ref PUBLIC_COUNT: Mutex<Option<(Instant, i64)>> = Mutex::new(None);
+}
+
+
+#[derive(Object)]
+pub struct PlaylistStats {
+    pub id: Uuid,
+
+    /// The number of times the playlist has been fetched by anyone other
+    /// than its owner.
+    pub views: i64,
+    pub votes: i32,
+}
+
+
+#[derive(Object)]
+pub struct PlaylistCount {
+    pub count: i64,
+}
+
+
+#[derive(Object)]
+pub struct TagCount {
+    pub tag: String,
+
+    /// The number of public playlists with the tag.
+    pub playlists: i64,
+}
+
+
+/// Increments the view count of the playlist.
+pub async fn record_view(sess: &Session, playlist_id: Uuid) -> ApiResult<()> {
+    sess.query_prepared(
+        "UPDATE playlist_views SET views = views + 1 WHERE playlist_id = ?;",
+        (playlist_id,)
+    ).await?;
+
+    Ok(())
+}
+
+/// Records a view of the playlist in the background so the request viewing
+/// it doesn't wait on the write, failures are only logged.
+pub fn spawn_record_view(sess: &Session, playlist_id: Uuid) {
+    let sess = sess.clone();
+    tokio::spawn(async move {
+        if let Err(e) = record_view(&sess, playlist_id).await {
+            warn!("failed to record view of playlist {}: {}", playlist_id, e);
+        }
+    });
+}
+
+/// Gets the number of views of the playlist, this is 0 if it has never
+/// been viewed.
+pub async fn get_view_count(sess: &Session, playlist_id: Uuid) -> ApiResult<i64> {
+    let result = sess.query_prepared(
+        "SELECT views FROM playlist_views WHERE playlist_id = ?;",
+        (playlist_id,)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let views = rows.into_typed::<(Counter,)>()
+        .next()
+        .transpose()?
+        .map(|v| v.0.0)
+        .unwrap_or_default();
+
+    Ok(views)
+}
+
+/// Counts the public playlists, not including removed playlists.
+///
+/// This counts the rows of every `listed_playlists` bucket, which grows with
+/// the number of public playlists, so the count is cached for
+/// `PUBLIC_COUNT_TTL` and concurrent requests wait on a single count
+/// rather than each starting one.
+pub async fn count_public_playlists(sess: &Session) -> ApiResult<i64> {
+    let mut cached = PUBLIC_COUNT.lock().await;
+    if let Some((counted_at, count)) = *cached {
+        if counted_at.elapsed() < PUBLIC_COUNT_TTL {
+            return Ok(count)
+        }
+    }
+
+    let mut count = 0;
+    for bucket in 0..LISTED_BUCKETS {
+        let result = sess.query_prepared(
+            "SELECT COUNT(*) FROM listed_playlists WHERE bucket = ?;",
+            (bucket,)
+        ).await?;
+
+        let rows = result.rows
+            .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+        count += rows.into_typed::<(i64,)>()
+            .next()
+            .transpose()?
+            .map(|v| v.0)
+            .unwrap_or_default();
+    }
+
+    *cached = Some((Instant::now(), count));
+
+    Ok(count)
+}
+
+
+/// The `playlist_totals` row counting public playlists which haven't been
+/// removed.
+const PUBLIC_TOTAL: &str = "public";
+
+/// Gets the maintained count of public playlists which haven't been removed.
+///
+/// This is kept up to date by `listing_changed` rather than counted, so it is
+/// a single read. Counter updates can't be retried safely, so a write which
+/// times out can leave the total slightly off, `count_public_playlists` is
+/// the exact but expensive count.
+pub async fn get_public_total(sess: &Session) -> ApiResult<i64> {
+    let result = sess.query_prepared(
+        "SELECT playlists FROM playlist_totals WHERE name = ?;",
+        (PUBLIC_TOTAL,)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let total = rows.into_typed::<(Counter,)>()
+        .next()
+        .transpose()?
+        .map(|v| v.0.0)
+        .unwrap_or(0);
+
+    Ok(total.max(0))
+}
+
+/// The tags of the playlist if it is listed publicly, i.e. it is public and
+/// hasn't been removed, for passing to `listing_changed`.
+pub fn listed_tags(playlist: &Playlist) -> Option<&[String]> {
+    (playlist.is_public & playlist.deleted_at.is_none()).then_some(playlist.tags.as_slice())
+}
+
+/// Adjusts `listed_playlists`, the public playlist total and tag counts after
+/// a playlist goes from the `previous` to the `current` listing, where a
+/// listing is the playlist's tags if it is public and not removed or `None`
+/// if it isn't.
+///
+/// Every write which can change whether a playlist is listed, or its tags
+/// while it is, must call this once it has been applied:
+/// - creating, importing or cloning a public playlist.
+/// - editing a playlist's visibility or tags.
+/// - removing or restoring a public playlist.
+/// - permanently deleting a public playlist which wasn't already removed.
+pub async fn listing_changed(
+    sess: &Session,
+    playlist_id: Uuid,
+    previous: Option<&[String]>,
+    current: Option<&[String]>,
+) -> ApiResult<()> {
+    let delta = match (previous.is_some(), current.is_some()) {
+        (false, true) => 1,
+        (true, false) => -1,
+        _ => 0,
+    };
+
+    if delta != 0 {
+        discover::set_listed(sess, playlist_id, delta > 0).await?;
+        sess.query_prepared(
+            "UPDATE playlist_totals SET playlists = playlists + ? WHERE name = ?;",
+            (Counter(delta), PUBLIC_TOTAL)
+        ).await?;
+    }
+
+    let previous: HashSet<&String> = previous.unwrap_or_default().iter().collect();
+    let current: HashSet<&String> = current.unwrap_or_default().iter().collect();
+
+    let added = current.difference(&previous).map(|v| (*v, 1));
+    let removed = previous.difference(&current).map(|v| (*v, -1));
+    for (tag, delta) in added.chain(removed) {
+        sess.query_prepared(
+            "UPDATE tag_counts SET playlists = playlists + ? WHERE scope = ? AND tag = ?;",
+            (Counter(delta), TAG_SCOPE, tag)
+        ).await?;
+    }
+
+    Ok(())
+}
+
+/// Gets up to `limit` of the tags used by public playlists, most used first.
+///
+/// Tags are counted by `listing_changed` as playlists are edited, tags which
+/// are no longer used by any public playlist are left out.
+pub async fn get_tag_counts(sess: &Session, limit: usize) -> ApiResult<Vec<TagCount>> {
+    let result = sess.query_prepared(
+        "SELECT tag, playlists FROM tag_counts WHERE scope = ?;",
+        (TAG_SCOPE,)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let mut tags: Vec<TagCount> = rows.into_typed::<(String, Counter)>()
+        .filter_map(|v| v.ok())
+        .filter(|v| v.1.0 > 0)
+        .map(|(tag, playlists)| TagCount { tag, playlists: playlists.0 })
+        .collect();
+
+    tags.sort_by_key(|v| Reverse(v.playlists));
+    tags.truncate(limit);
+
+    Ok(tags)
+}
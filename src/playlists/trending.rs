@@ -0,0 +1,145 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use anyhow::anyhow;
+use chrono::{DateTime, Duration, Utc};
+use scylla::IntoTypedRows;
+use scylla::frame::value::Counter;
+use uuid::Uuid;
+
+use crate::db::Session;
+use crate::errors::ApiResult;
+use crate::utils;
+use super::{get_playlists_with_ids, Playlist};
+
+
+/// The number of hours of votes counted towards a playlist trending.
+const TRENDING_WINDOW_HOURS: i64 = 24;
+
+/// The maximum number of the highest ranked playlists which are fetched
+/// before filtering out private and NSFW playlists.
+const MAX_CANDIDATES: usize = 200;
+
+
+/// Votes are counted in hourly buckets so the trending window can be summed
+/// over a fixed number of small partitions rather than scanning every vote.
+///
+/// Counters cannot have a TTL, old buckets are never read again once they
+/// fall out of the window.
+fn bucket_for(dt: DateTime<Utc>) -> String {
+    dt.format("%Y-%m-%dT%H").to_string()
+}
+
+/// Votes are also counted in daily buckets of the same table so the week
+/// and month windows read tens of partitions rather than hundreds of hourly
+/// ones.
+///
+/// Daily buckets were added after hourly ones, so votes from before then
+/// are missing from these windows.
+fn daily_bucket_for(dt: DateTime<Utc>) -> String {
+    dt.format("%Y-%m-%d").to_string()
+}
+
+/// Adds `delta` votes to the playlist's count for the hour and the day `at`
+/// falls in.
+pub async fn record_vote(
+    sess: &Session,
+    playlist_id: Uuid,
+    at: DateTime<Utc>,
+    delta: i64,
+) -> ApiResult<()> {
+    for bucket in [bucket_for(at), daily_bucket_for(at)] {
+        sess.query_prepared(
+            "UPDATE playlist_trending_votes SET votes = votes + ? WHERE bucket = ? AND playlist_id = ?;",
+            (Counter(delta), bucket, playlist_id)
+        ).await?;
+    }
+
+    Ok(())
+}
+
+/// Gets up to `limit` public playlists which received the most votes in the
+/// last `TRENDING_WINDOW_HOURS`, most votes first.
+pub async fn trending_playlists(
+    sess: &Session,
+    limit: usize,
+    include_nsfw: bool,
+) -> ApiResult<Vec<Playlist>> {
+    let now = utils::now();
+    let buckets: Vec<String> = (0..TRENDING_WINDOW_HOURS)
+        .map(|hours| bucket_for(now - Duration::hours(hours)))
+        .collect();
+
+    let ranked = rank_by_votes(sess, buckets).await?;
+
+    let ids: Vec<Uuid> = ranked.iter().map(|(id, _)| *id).collect();
+    let playlists = get_playlists_with_ids(sess, &ids).await?
+        .into_iter()
+        .filter(|playlist| playlist.is_public & (include_nsfw | !playlist.nsfw))
+        .take(limit)
+        .collect();
+
+    Ok(playlists)
+}
+
+/// Gets up to `limit` public playlists which received the most votes in the
+/// last `days` days including today, with `window_votes` set to the votes
+/// received in that time.
+///
+/// Only playlists with the given `tag` are included if one is set.
+pub async fn top_playlists_in_window(
+    sess: &Session,
+    days: i64,
+    limit: usize,
+    include_nsfw: bool,
+    tag: Option<&str>,
+) -> ApiResult<Vec<Playlist>> {
+    let now = utils::now();
+    let buckets: Vec<String> = (0..days)
+        .map(|days| daily_bucket_for(now - Duration::days(days)))
+        .collect();
+
+    let mut ranked: HashMap<Uuid, i64> = rank_by_votes(sess, buckets).await?
+        .into_iter()
+        .collect();
+
+    let mut ids: Vec<Uuid> = ranked.keys().copied().collect();
+    ids.sort_by_key(|id| Reverse(ranked[id]));
+
+    let playlists = get_playlists_with_ids(sess, &ids).await?
+        .into_iter()
+        .filter(|playlist| playlist.is_public & (include_nsfw | !playlist.nsfw))
+        .filter(|playlist| tag.map(|tag| playlist.tags.iter().any(|v| v == tag)).unwrap_or(true))
+        .take(limit)
+        .map(|mut playlist| {
+            playlist.window_votes = ranked.remove(&playlist.id);
+            playlist
+        })
+        .collect();
+
+    Ok(playlists)
+}
+
+/// Sums the votes of each playlist across the given buckets, returning up
+/// to `MAX_CANDIDATES` playlists with a positive total, most votes first.
+async fn rank_by_votes(sess: &Session, buckets: Vec<String>) -> ApiResult<Vec<(Uuid, i64)>> {
+    let result = sess.query_prepared(
+        "SELECT playlist_id, votes FROM playlist_trending_votes WHERE bucket IN ?;",
+        (buckets,)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let mut scores: HashMap<Uuid, i64> = HashMap::new();
+    for (playlist_id, votes) in rows.into_typed::<(Uuid, Counter)>().filter_map(|v| v.ok()) {
+        *scores.entry(playlist_id).or_default() += votes.0;
+    }
+
+    let mut ranked: Vec<(Uuid, i64)> = scores.into_iter()
+        .filter(|(_, votes)| *votes > 0)
+        .collect();
+    ranked.sort_by_key(|(_, votes)| Reverse(*votes));
+    ranked.truncate(MAX_CANDIDATES);
+
+    Ok(ranked)
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use poem::http::{header, Method};
+use poem::{Endpoint, Request, Result};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+use crate::db::Session;
+use crate::errors::ApiError;
+use crate::users::user_info;
+use crate::utils;
+
+
+/// How often buckets which have refilled are dropped.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The most buckets kept at once, new clients are limited straight away
+/// while there are this many buckets which haven't refilled.
+const MAX_BUCKETS: usize = 100_000;
+
+
+/// Who a bucket belongs to.
+///
+/// Requests are only keyed by user once their token has been resolved, so
+/// sending made up tokens doesn't get around the limit for the client's IP.
+/// Resolving a token which isn't cached is charged to the client's IP too,
+/// so made up tokens can't be used to query the database without limit.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum BucketKey {
+    User(i64),
+    Ip(IpAddr),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+
+/// Limits each bearer token, or client IP for anonymous requests, to
+/// `RATE_LIMIT_PER_MIN` requests a minute using a token bucket.
+///
+/// Buckets hold up to a minute's worth of requests, so short bursts are
+/// allowed as long as the average stays under the limit.
+#[derive(Clone)]
+pub struct RateLimiter(Arc<RateLimiterInner>);
+
+struct RateLimiterInner {
+    buckets: Mutex<HashMap<BucketKey, Bucket>>,
+    capacity: f64,
+    refill_per_sec: f64,
+    max_buckets: usize,
+}
+
+impl RateLimiter {
+    pub fn new(per_min: u32) -> Self {
+        Self::with_max_buckets(per_min, MAX_BUCKETS)
+    }
+
+    fn with_max_buckets(per_min: u32, max_buckets: usize) -> Self {
+        Self(Arc::new(RateLimiterInner {
+            buckets: Mutex::new(HashMap::new()),
+            capacity: per_min as f64,
+            refill_per_sec: per_min as f64 / 60.0,
+            max_buckets,
+        }))
+    }
+
+    /// Takes a request from the key's bucket, returning the number of
+    /// seconds until one is available if it is empty.
+    ///
+    /// If there are already `max_buckets` buckets the refilled ones are
+    /// dropped to make room, if none have refilled the request is limited
+    /// until the next cleanup.
+    fn acquire(&self, key: BucketKey) -> Result<(), u64> {
+        let now = Instant::now();
+        let mut buckets = self.0.buckets.lock().unwrap();
+
+        if !buckets.contains_key(&key) && (buckets.len() >= self.0.max_buckets) {
+            self.retain_refilling(&mut buckets, now);
+
+            if buckets.len() >= self.0.max_buckets {
+                warn!("rate limit buckets are full, limiting new clients");
+                return Err(CLEANUP_INTERVAL.as_secs())
+            }
+        }
+
+        let bucket = buckets.entry(key)
+            .or_insert(Bucket { tokens: self.0.capacity, updated_at: now });
+
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.0.refill_per_sec).min(self.0.capacity);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(())
+        }
+
+        let wait = (1.0 - bucket.tokens) / self.0.refill_per_sec;
+        Err(wait.ceil().max(1.0) as u64)
+    }
+
+    /// Drops the buckets which would have refilled by now, these behave
+    /// the same as a new bucket so nothing is lost.
+    fn remove_full(&self) -> usize {
+        let mut buckets = self.0.buckets.lock().unwrap();
+        let before = buckets.len();
+        self.retain_refilling(&mut buckets, Instant::now());
+
+        before - buckets.len()
+    }
+
+    fn retain_refilling(&self, buckets: &mut HashMap<BucketKey, Bucket>, now: Instant) {
+        let refill_time = self.0.capacity / self.0.refill_per_sec;
+        buckets.retain(|_, v| now.duration_since(v.updated_at).as_secs_f64() < refill_time);
+    }
+}
+
+
+/// Spawns the task which periodically drops refilled buckets so clients
+/// which have gone away don't keep using memory.
+///
+/// The task stops once `shutdown` is set to true.
+pub fn spawn_cleanup(limiter: RateLimiter, mut shutdown: watch::Receiver<bool>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {},
+                _ = shutdown.changed() => break,
+            }
+
+            let removed = limiter.remove_full();
+            if removed > 0 {
+                debug!("dropped {} idle rate limit buckets", removed);
+            }
+        }
+
+        info!("rate limit cleanup stopped");
+    })
+}
+
+
+/// Rejects requests once the caller's bucket is empty.
+///
+/// Requests with a superuser key are never limited, and neither are CORS
+/// preflights as browsers send those on their own. Requests are keyed by
+/// the user their token belongs to, anonymous requests and those with an
+/// invalid token are keyed by the address of the connecting client instead,
+/// so clients behind the same proxy share a bucket.
+///
+/// Tokens which aren't cached are only looked up once a request has been
+/// taken from the client's bucket, an invalid token isn't charged again.
+pub async fn limit<E: Endpoint>(next: E, req: Request, limiter: RateLimiter) -> Result<E::Output> {
+    if req.method() == Method::OPTIONS {
+        return next.call(req).await
+    }
+
+    let token = req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    let ip_key = req.remote_addr()
+        .as_socket_addr()
+        .map(|v| BucketKey::Ip(v.ip()));
+
+    let mut charged_ip = false;
+    let user_id = match (token, req.data::<Session>()) {
+        (Some(token), _) if utils::is_superuser_key(token) => return next.call(req).await,
+        (Some(token), Some(sess)) => match user_info::get_cached_user_id(token) {
+            Some(user_id) => Some(user_id),
+            None => {
+                if let Some(key) = ip_key {
+                    acquire(&limiter, key)?;
+                    charged_ip = true;
+                }
+
+                user_info::get_user_id_from_token(sess, token)
+                    .await
+                    .map_err(ApiError::from)?
+            },
+        },
+        _ => None,
+    };
+
+    match user_id {
+        Some(user_id) => acquire(&limiter, BucketKey::User(user_id))?,
+        None if !charged_ip => {
+            if let Some(key) = ip_key {
+                acquire(&limiter, key)?;
+            }
+        },
+        None => {},
+    }
+
+    next.call(req).await
+}
+
+fn acquire(limiter: &RateLimiter, key: BucketKey) -> Result<()> {
+    limiter.acquire(key)
+        .map_err(|retry_after| ApiError::TooManyRequests(retry_after).into())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn ip(last: u8) -> BucketKey {
+        BucketKey::Ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, last)))
+    }
+
+    /// Moves the key's bucket back in time as if it hadn't been used for
+    /// the given duration.
+    fn age(limiter: &RateLimiter, key: BucketKey, by: Duration) {
+        let mut buckets = limiter.0.buckets.lock().unwrap();
+        let bucket = buckets.get_mut(&key).unwrap();
+        bucket.updated_at = bucket.updated_at.checked_sub(by).unwrap();
+    }
+
+    #[test]
+    fn acquire_allows_bursts_up_to_the_limit() {
+        let limiter = RateLimiter::new(3);
+
+        for _ in 0..3 {
+            assert_eq!(limiter.acquire(ip(1)), Ok(()));
+        }
+
+        // One request is refilled every 20 seconds.
+        assert_eq!(limiter.acquire(ip(1)), Err(20));
+    }
+
+    #[test]
+    fn acquire_limits_keys_separately() {
+        let limiter = RateLimiter::new(1);
+
+        assert_eq!(limiter.acquire(ip(1)), Ok(()));
+        assert!(limiter.acquire(ip(1)).is_err());
+        assert_eq!(limiter.acquire(ip(2)), Ok(()));
+        assert_eq!(limiter.acquire(BucketKey::User(1)), Ok(()));
+    }
+
+    #[test]
+    fn acquire_refills_over_time() {
+        let limiter = RateLimiter::new(60);
+
+        for _ in 0..60 {
+            assert_eq!(limiter.acquire(ip(1)), Ok(()));
+        }
+        assert_eq!(limiter.acquire(ip(1)), Err(1));
+
+        age(&limiter, ip(1), Duration::from_secs(2));
+        assert_eq!(limiter.acquire(ip(1)), Ok(()));
+        assert_eq!(limiter.acquire(ip(1)), Ok(()));
+        assert!(limiter.acquire(ip(1)).is_err());
+    }
+
+    #[test]
+    fn acquire_limits_new_keys_once_full() {
+        let limiter = RateLimiter::with_max_buckets(1, 2);
+
+        assert_eq!(limiter.acquire(ip(1)), Ok(()));
+        assert_eq!(limiter.acquire(ip(2)), Ok(()));
+        assert_eq!(limiter.acquire(ip(3)), Err(CLEANUP_INTERVAL.as_secs()));
+
+        // Existing keys keep their buckets.
+        age(&limiter, ip(1), Duration::from_secs(30));
+        assert!(limiter.acquire(ip(1)).is_err());
+
+        // Refilled buckets are dropped to make room.
+        age(&limiter, ip(2), Duration::from_secs(60));
+        assert_eq!(limiter.acquire(ip(3)), Ok(()));
+        assert_eq!(limiter.0.buckets.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn remove_full_only_drops_refilled_buckets() {
+        let limiter = RateLimiter::new(1);
+
+        assert_eq!(limiter.acquire(ip(1)), Ok(()));
+        assert_eq!(limiter.acquire(ip(2)), Ok(()));
+        age(&limiter, ip(1), Duration::from_secs(60));
+
+        assert_eq!(limiter.remove_full(), 1);
+        assert!(limiter.acquire(ip(2)).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+mod report;
+
+use poem::Result;
+use poem::web::Data;
+use poem_openapi::{Object, OpenApi};
+use poem_openapi::param::Query;
+use poem_openapi::payload::Json;
+use uuid::Uuid;
+
+pub use report::*;
+use crate::ApiTags;
+use crate::db::Session;
+use crate::playlists;
+use crate::users::user_info;
+use crate::utils::{
+    decode_cursor,
+    JsonResponse,
+    ModeratorBearer,
+    TokenBearer,
+    DEFAULT_PAGE_SIZE,
+};
+use crate::webhooks::Webhooks;
+
+
+#[derive(Object, Debug)]
+pub struct ReportCreationPayload {
+    /// The id of the playlist or entry being reported.
+    id: Uuid,
+
+    target_type: ReportTargetType,
+
+    reason: ReportReason,
+}
+
+
+pub struct ReportsApi;
+
+#[OpenApi]
+impl ReportsApi {
+    /// Report Content
+    ///
+    /// Flags a playlist or entry for review by a moderator.
+    #[oai(path = "/reports", method = "post", tag = "ApiTags::Reports")]
+    pub async fn create_report(
+        &self,
+        payload: Json<ReportCreationPayload>,
+        session: Data<&Session>,
+        webhooks: Data<&Webhooks>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<Report>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let exists = match payload.0.target_type {
+            ReportTargetType::Playlist => playlists::get_playlist_by_id(&session, payload.0.id).await?.is_some(),
+            ReportTargetType::Entry => playlists::get_entry_by_id(&session, payload.0.id).await?.is_some(),
+        };
+
+        if !exists {
+            return Ok(JsonResponse::bad_request("The reported content does not exist."))
+        }
+
+        let report = report::create_report(
+            &session,
+            user_id,
+            payload.0.id,
+            payload.0.target_type,
+            payload.0.reason,
+        ).await?;
+
+        webhooks.report_filed(report.id, report.target_id);
+
+        Ok(JsonResponse::ok(report))
+    }
+
+    /// List Pending Reports
+    ///
+    /// Get a page of pending reports, newest first. The returned `next_cursor`
+    /// can be passed back as the `cursor` to get the following page.
+    #[oai(path = "/reports", method = "get", tag = "ApiTags::Reports")]
+    pub async fn get_reports(
+        &self,
+        #[oai(validator(minimum(value = "1"), maximum(value = "100")))]
+        limit: Query<Option<i32>>,
+        cursor: Query<Option<String>>,
+        session: Data<&Session>,
+        _token: ModeratorBearer,
+    ) -> Result<JsonResponse<ReportPage>> {
+        let cursor = match cursor.0 {
+            None => None,
+            Some(cursor) => match decode_cursor(&cursor) {
+                None => return Ok(JsonResponse::bad_request("Invalid cursor.")),
+                Some(cursor) => Some(cursor),
+            },
+        };
+
+        let limit = limit.0.unwrap_or(DEFAULT_PAGE_SIZE);
+        let page = report::get_pending_reports(&session, limit, cursor).await?;
+
+        Ok(JsonResponse::ok(page))
+    }
+}
@@ -0,0 +1,132 @@
+use std::str::FromStr;
+use anyhow::anyhow;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use poem_openapi::{Enum, Object};
+use scylla::IntoTypedRows;
+use strum::{Display, EnumString};
+use uuid::Uuid;
+
+use crate::db::Session;
+use crate::utils::{self, encode_cursor, JsSafeBigInt};
+
+
+/// The status of reports which have not yet been handled.
+const PENDING: &str = "pending";
+
+
+#[derive(Enum, Display, EnumString, Copy, Clone, Debug)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+#[oai(rename_all = "lowercase")]
+pub enum ReportTargetType {
+    Playlist,
+    Entry,
+}
+
+#[derive(Enum, Display, EnumString, Copy, Clone, Debug)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+#[oai(rename_all = "lowercase")]
+pub enum ReportReason {
+    Nsfw,
+    Abuse,
+    Spam,
+    Other,
+}
+
+
+#[derive(Object)]
+pub struct Report {
+    pub id: Uuid,
+    pub target_id: Uuid,
+    pub target_type: ReportTargetType,
+    pub reason: ReportReason,
+    pub reporter_id: JsSafeBigInt,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Object)]
+pub struct ReportPage {
+    pub reports: Vec<Report>,
+
+    /// The cursor to fetch the next page with if there are more reports.
+    pub next_cursor: Option<String>,
+}
+
+
+pub async fn create_report(
+    sess: &Session,
+    reporter_id: i64,
+    target_id: Uuid,
+    target_type: ReportTargetType,
+    reason: ReportReason,
+) -> anyhow::Result<Report> {
+    let report = Report {
+        id: Uuid::new_v4(),
+        target_id,
+        target_type,
+        reason,
+        reporter_id: JsSafeBigInt(reporter_id),
+        created_at: utils::now(),
+    };
+
+    sess.query_prepared(
+        r#"
+        INSERT INTO reports (
+            status,
+            created_at,
+            id,
+            target_id,
+            target_type,
+            reason,
+            reporter_id
+        ) VALUES (?, ?, ?, ?, ?, ?, ?);
+        "#,
+        (
+            PENDING,
+            utils::to_cql_timestamp(report.created_at),
+            report.id,
+            report.target_id,
+            report.target_type.to_string(),
+            report.reason.to_string(),
+            reporter_id,
+        )
+    ).await?;
+
+    Ok(report)
+}
+
+/// Gets a page of pending reports, newest first.
+pub async fn get_pending_reports(
+    sess: &Session,
+    limit: i32,
+    cursor: Option<Bytes>,
+) -> anyhow::Result<ReportPage> {
+    let result = sess.query_prepared_paged(
+        r#"
+        SELECT id, target_id, target_type, reason, reporter_id, created_at
+        FROM reports WHERE status = ?;
+        "#,
+        (PENDING,),
+        Some(limit),
+        cursor,
+    ).await?;
+
+    let next_cursor = encode_cursor(result.paging_state);
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    type ReportInfo = (Uuid, Uuid, String, String, JsSafeBigInt, chrono::Duration);
+    let reports = rows.into_typed::<ReportInfo>()
+        .filter_map(|v| v.ok())
+        .filter_map(|v| Some(Report {
+            id: v.0,
+            target_id: v.1,
+            target_type: ReportTargetType::from_str(&v.2).ok()?,
+            reason: ReportReason::from_str(&v.3).ok()?,
+            reporter_id: v.4,
+            created_at: utils::from_cql_timestamp(v.5),
+        }))
+        .collect();
+
+    Ok(ReportPage { reports, next_cursor })
+}
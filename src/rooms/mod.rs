@@ -173,7 +173,7 @@ async fn create_room_from_payload(
         None
     };
 
-    sess.query(
+    sess.query_prepared(
         r#"
         INSERT INTO rooms (
             id,
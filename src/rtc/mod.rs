@@ -42,7 +42,7 @@ impl RtcApi {
             Some(u) => u,
         };
 
-        let room = match rooms::get_room_by_id(&session, payload.room_id.clone()).await? {
+        let room = match rooms::get_room_by_id(&session, payload.room_id).await? {
             None => return Ok(JsonResponse::bad_request("No active room exists with this id.")),
             Some(room) => room,
         };
@@ -75,13 +75,13 @@ impl RtcApi {
             Some(u) => u,
         };
 
-        let room = match rooms::get_room_by_id(&session, payload.room_id.clone()).await? {
+        let room = match rooms::get_room_by_id(&session, payload.room_id).await? {
             None => return Ok(JsonResponse::bad_request("No active room exists with this id.")),
             Some(room) => room,
         };
 
         let has_guild_access = if let Some(guild_id) = room.guild_id.as_ref() {
-          user.access_servers.contains_key(&*guild_id)
+          user.access_servers.contains_key(guild_id)
         } else {
             false
         };
@@ -0,0 +1,91 @@
+use std::time::Duration;
+use anyhow::anyhow;
+use scylla::IntoTypedRows;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+use crate::config::Config;
+use crate::db::Session;
+use super::user_info;
+
+
+/// The number of credits given to each active user per interval.
+const CREDITS_PER_INTERVAL: i32 = 1;
+
+/// The number of users fetched per page while scanning for active users.
+const USERS_PAGE_SIZE: i32 = 500;
+
+
+/// Spawns the task which periodically tops up the credits of every active
+/// user towards `credit_regen_cap`.
+///
+/// The task stops once `shutdown` is set to true, finishing the user it is
+/// currently updating first.
+pub fn spawn(sess: Session, config: &Config, mut shutdown: watch::Receiver<bool>) -> JoinHandle<()> {
+    let period = Duration::from_secs(config.credit_regen_interval_secs);
+    let cap = config.credit_regen_cap;
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        // The first tick completes immediately, skip it so a restart doesn't
+        // hand out credits early.
+        interval.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {},
+                _ = shutdown.changed() => break,
+            }
+
+            match regenerate_credits(&sess, cap, &shutdown).await {
+                Ok(users) => info!("regenerated credits for {} users", users),
+                Err(e) => error!("failed to regenerate credits: {}", e),
+            }
+
+            if *shutdown.borrow() {
+                break
+            }
+        }
+
+        info!("credit regeneration stopped");
+    })
+}
+
+/// Tops up the credits of every user with a live access token, returning
+/// the number of users who were given credits.
+async fn regenerate_credits(sess: &Session, cap: i32, shutdown: &watch::Receiver<bool>) -> anyhow::Result<usize> {
+    let mut updated = 0;
+    let mut paging_state = None;
+
+    loop {
+        let result = sess.query_prepared_paged(
+            "SELECT DISTINCT user_id FROM access_tokens_by_user;",
+            &[],
+            Some(USERS_PAGE_SIZE),
+            paging_state,
+        ).await?;
+
+        paging_state = result.paging_state;
+        let rows = result.rows
+            .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+        for (user_id,) in rows.into_typed::<(i64,)>().filter_map(|v| v.ok()) {
+            if *shutdown.borrow() {
+                return Ok(updated)
+            }
+
+            match user_info::top_up_user_credits(sess, user_id, CREDITS_PER_INTERVAL, cap).await {
+                Ok(0) => {},
+                Ok(_) => updated += 1,
+                Err(e) => warn!("failed to regenerate credits for user {}: {}", user_id, e),
+            }
+        }
+
+        if paging_state.is_none() {
+            return Ok(updated)
+        }
+    }
+}
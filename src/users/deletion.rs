@@ -0,0 +1,151 @@
+use anyhow::{anyhow, Result};
+use poem_openapi::Object;
+use scylla::IntoTypedRows;
+use uuid::Uuid;
+
+use crate::db::Session;
+use crate::playlists;
+use super::user_info;
+
+
+/// What was removed while deleting a user's data.
+#[derive(Object, Default, Debug)]
+pub struct DeletionSummary {
+    /// If every step completed, when this is false the request can be
+    /// retried and will carry on from where it stopped.
+    pub complete: bool,
+    pub playlists_deleted: usize,
+    pub entries_deleted: usize,
+
+    /// The number of other users' playlists the user's entries were
+    /// removed from.
+    pub playlists_updated: usize,
+    pub votes_deleted: usize,
+    pub tokens_revoked: usize,
+}
+
+
+/// Deletes the user's playlists, entries, votes, favorites, credits, tokens
+/// and user record, recording what was removed in `summary` as it goes.
+///
+/// Playlists and entries are permanently deleted, including any which were
+/// already removed. Entries which are in other users' playlists are removed
+/// from those playlists first rather than refusing, so the user can always
+/// leave.
+///
+/// Each step reads what is left, so if this fails part way through calling
+/// it again picks up where it stopped.
+pub async fn delete_user_data(
+    sess: &Session,
+    user_id: i64,
+    summary: &mut DeletionSummary,
+) -> Result<()> {
+    for playlist_id in playlists::get_playlist_ids_for_owner(sess, user_id).await? {
+        playlists::purge_playlist(sess, playlist_id).await?;
+        summary.playlists_deleted += 1;
+    }
+
+    for entry_id in playlists::get_entry_ids_for_owner(sess, user_id).await? {
+        summary.playlists_updated += playlists::purge_entry(sess, entry_id).await?;
+        summary.entries_deleted += 1;
+    }
+
+    summary.votes_deleted += delete_votes(
+        sess,
+        "SELECT playlist_id FROM playlist_votes_by_user WHERE user_id = ?;",
+        "DELETE FROM playlist_votes WHERE user_id = ? AND playlist_id = ?;",
+        user_id,
+    ).await?;
+    summary.votes_deleted += delete_votes(
+        sess,
+        "SELECT entry_id FROM playlist_entries_votes_by_user WHERE user_id = ?;",
+        "DELETE FROM playlist_entries_votes WHERE user_id = ? AND entry_id = ?;",
+        user_id,
+    ).await?;
+
+    playlists::remove_favorites_for_user(sess, user_id).await?;
+    sess.query_prepared("DELETE FROM user_vote_credits WHERE user_id = ?;", (user_id,)).await?;
+    sess.query_prepared("DELETE FROM moderators WHERE user_id = ?;", (user_id,)).await?;
+    sess.query_prepared("DELETE FROM users WHERE id = ?;", (user_id,)).await?;
+
+    // The tokens go last so a failed deletion can still be retried.
+    summary.tokens_revoked += revoke_tokens(sess, user_id).await?;
+
+    summary.complete = true;
+
+    Ok(())
+}
+
+async fn delete_votes(sess: &Session, select: &str, delete: &str, user_id: i64) -> Result<usize> {
+    let result = sess.query_prepared(select, (user_id,)).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let ids: Vec<Uuid> = rows.into_typed::<(Uuid,)>()
+        .filter_map(|v| v.ok())
+        .map(|v| v.0)
+        .collect();
+
+    for id in ids.iter() {
+        sess.query_prepared(delete, (user_id, *id)).await?;
+    }
+
+    Ok(ids.len())
+}
+
+async fn revoke_tokens(sess: &Session, user_id: i64) -> Result<usize> {
+    let result = sess.query_prepared(
+        "SELECT access_token FROM access_tokens_by_user WHERE user_id = ?;",
+        (user_id,)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let tokens: Vec<String> = rows.into_typed::<(String,)>()
+        .filter_map(|v| v.ok())
+        .map(|v| v.0)
+        .collect();
+
+    for token in tokens.iter() {
+        sess.query_prepared(
+            "DELETE FROM access_tokens WHERE access_token = ?;",
+            (token,)
+        ).await?;
+        user_info::invalidate_cached_token(token);
+    }
+
+    Ok(tokens.len())
+}
+
+/// These need a Scylla node to run against, start one with
+/// `docker-compose up scylla` and run `cargo test -- --ignored`.
+#[cfg(test)]
+mod tests {
+    use crate::db;
+    use super::*;
+
+    const SCYLLA_NODE: &str = "127.0.0.1:9042";
+
+    #[tokio::test]
+    #[ignore = "needs a Scylla node"]
+    async fn revoked_tokens_are_evicted_from_the_cache() {
+        let sess = db::connect(SCYLLA_NODE).await.unwrap();
+        let user_id = -(rand::random::<u32>() as i64) - 1;
+        let token = format!("test-{}", Uuid::new_v4());
+
+        sess.query_prepared(
+            "INSERT INTO access_tokens (user_id, access_token) VALUES (?, ?);",
+            (user_id, token.clone())
+        ).await.unwrap();
+
+        let resolved = user_info::get_user_id_from_token(&sess, &token).await.unwrap();
+        assert_eq!(resolved, Some(user_id));
+        assert_eq!(user_info::get_cached_user_id(&token), Some(user_id));
+
+        assert_eq!(revoke_tokens(&sess, user_id).await.unwrap(), 1);
+        assert_eq!(user_info::get_cached_user_id(&token), None);
+        assert_eq!(user_info::get_user_id_from_token(&sess, &token).await.unwrap(), None);
+    }
+}
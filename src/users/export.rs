@@ -0,0 +1,101 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use scylla::IntoTypedRows;
+use uuid::Uuid;
+
+use crate::db::Session;
+use crate::playlists::{Playlist, PlaylistEntry};
+use crate::rooms::models::{ArchivedRoom, Room};
+use crate::utils;
+use super::notifications::{self, Notification};
+use super::user_info::{self, User};
+use super::{playlist_info, room_info};
+
+
+/// A vote cast by the user which is still within its cooldown, older votes
+/// are not kept.
+#[derive(Object)]
+pub struct VoteRecord {
+    /// The id of the playlist or entry which was voted on.
+    pub target_id: Uuid,
+    pub voted_on: Option<DateTime<Utc>>,
+}
+
+
+/// Everything held about a single user.
+#[derive(Object)]
+pub struct UserExport {
+    pub exported_at: DateTime<Utc>,
+    pub user: Option<User>,
+    pub credits: i32,
+    pub playlists: Vec<Playlist>,
+    pub entries: Vec<PlaylistEntry>,
+    pub playlist_votes: Vec<VoteRecord>,
+    pub entry_votes: Vec<VoteRecord>,
+    pub notifications: Vec<Notification>,
+    pub active_room: Option<Room>,
+    pub archived_rooms: Vec<ArchivedRoom>,
+}
+
+
+/// Gathers all the data held about the user of the given token, returning
+/// None if the token is invalid.
+pub async fn export_user_data(sess: &Session, token: &str) -> Result<Option<UserExport>> {
+    let user_id = match user_info::get_user_id_from_token(sess, token).await? {
+        None => return Ok(None),
+        Some(user_id) => user_id,
+    };
+
+    let playlists = playlist_info::get_playlists_for_token(sess, token, None, None)
+        .await?
+        .map(|v| v.playlists)
+        .unwrap_or_default();
+
+    let entries = playlist_info::get_playlist_entries_for_token(sess, token, None, None)
+        .await?
+        .map(|v| v.entries)
+        .unwrap_or_default();
+
+    let archived_rooms = room_info::get_archived_rooms(sess, token)
+        .await?
+        .unwrap_or_default();
+
+    Ok(Some(UserExport {
+        exported_at: utils::now(),
+        user: user_info::get_user_from_id(sess, user_id).await?,
+        credits: user_info::get_user_vote_credits(sess, user_id).await?,
+        playlists,
+        entries,
+        playlist_votes: get_votes(
+            sess,
+            "SELECT playlist_id, voted_on FROM playlist_votes_by_user WHERE user_id = ?;",
+            user_id,
+        ).await?,
+        entry_votes: get_votes(
+            sess,
+            "SELECT entry_id, voted_on FROM playlist_entries_votes_by_user WHERE user_id = ?;",
+            user_id,
+        ).await?,
+        notifications: notifications::get_user_notifications(sess, user_id).await?,
+        active_room: room_info::get_active_room_for_user_id(sess, user_id).await?,
+        archived_rooms,
+    }))
+}
+
+async fn get_votes(sess: &Session, query: &str, user_id: i64) -> Result<Vec<VoteRecord>> {
+    let result = sess.query_prepared(query, (user_id,)).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let votes = rows.into_typed::<(Uuid, Option<chrono::Duration>)>()
+        .filter_map(|v| v.ok())
+        .map(|v| VoteRecord {
+            target_id: v.0,
+            voted_on: v.1.map(utils::from_cql_timestamp),
+        })
+        .collect();
+
+    Ok(votes)
+}
@@ -1,29 +1,77 @@
 pub mod user_info;
+pub mod credit_regen;
+pub mod export;
+pub mod deletion;
 pub mod notifications;
 pub mod room_info;
 pub mod playlist_info;
+pub mod profile;
+pub mod votes;
 
+use chrono::{DateTime, Duration, Utc};
 use poem::web::Data;
-use poem::Result;
+use poem::{Request, Result};
 use poem_openapi::payload::Json;
-use poem_openapi::{Object, OpenApi};
+use poem_openapi::{ApiResponse, Object, OpenApi};
 use poem_openapi::param::Query;
 use serde_json::Value;
 use uuid::Uuid;
 
+use deletion::DeletionSummary;
+use export::UserExport;
+use profile::UserProfile;
 use user_info::{User, Guild};
+use votes::VotePage;
 
 use crate::ApiTags;
-use crate::utils::{JsonResponse, SuperUserBearer, TokenBearer};
+use crate::config::Config;
+use crate::utils::{self, parse_cursor, JsSafeBigInt, JsonResponse, PageResponse, SuperUserBearer, TokenBearer, DEFAULT_PAGE_SIZE};
 use crate::db::Session;
 use crate::playlists::{get_playlist_by_id, Playlist, PlaylistEntry};
 use crate::rooms::models::{ArchivedRoom, Room};
 use crate::users::notifications::Notification;
+use crate::users::playlist_info::PlaylistPage;
+
+/// How long a user must wait between daily credit claims.
+const DAILY_CLAIM_COOLDOWN_SECS: i64 = 24 * 60 * 60;
 
 
 #[derive(Object)]
 pub struct CreditResponse {
     credits: i32,
+
+    /// When the daily credits can next be claimed, or `null` if they can be
+    /// claimed now.
+    next_claim_at: Option<DateTime<Utc>>,
+}
+
+
+#[derive(ApiResponse)]
+pub enum ExportResponse {
+    /// The user's data, sent as a file download.
+    #[oai(status = 200)]
+    Ok(Json<Box<UserExport>>, #[oai(header = "Content-Disposition")] String),
+
+    /// The provided access token has expired.
+    #[oai(status = 401)]
+    Unauthorized,
+}
+
+
+#[derive(ApiResponse)]
+pub enum DeletionResponse {
+    /// All of the user's data was deleted.
+    #[oai(status = 200)]
+    Ok(Json<DeletionSummary>),
+
+    /// The provided access token has expired.
+    #[oai(status = 401)]
+    Unauthorized,
+
+    /// Deleting failed part way through, the summary lists what was removed
+    /// before it failed. Retrying carries on from where it stopped.
+    #[oai(status = 500)]
+    Incomplete(Json<DeletionSummary>),
 }
 
 pub struct UsersApi;
@@ -46,19 +94,146 @@ impl UsersApi {
         }
     }
 
+    /// Get User Profile
+    ///
+    /// Get the public profile of any user, including their public playlists and
+    /// the total votes their public playlists and entries have received.
+    #[oai(path = "/users/profile", method = "get", tag = "ApiTags::User")]
+    pub async fn get_user_profile(
+        &self,
+        id: Query<JsSafeBigInt>,
+        session: Data<&Session>,
+    ) -> Result<JsonResponse<UserProfile>> {
+        match profile::get_user_profile(&session, *id.0).await? {
+            None => Ok(JsonResponse::not_found("This user does not exist.")),
+            Some(profile) => Ok(JsonResponse::ok(profile)),
+        }
+    }
+
     /// Get User Credits
     ///
-    /// Get the user voting credits associated with a given token.
+    /// Get the user voting credits associated with a given token, along with
+    /// when the daily credits can next be claimed.
     #[oai(path = "/users/@me/credits", method = "get", tag = "ApiTags::User")]
     pub async fn get_user_credits(
         &self,
         session: Data<&Session>,
         token: TokenBearer,
     ) -> Result<JsonResponse<CreditResponse>> {
-        if let Some(credits) = user_info::get_vote_credits_for_token(&session, &token.0.token).await? {
-            Ok(JsonResponse::ok(CreditResponse { credits }))
-        } else {
-            Ok(JsonResponse::unauthorized())
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let credits = user_info::get_user_vote_credits(&session, user_id).await?;
+        let next_claim_at = user_info::get_last_credit_claim(&session, user_id).await?
+            .map(|v| v + Duration::seconds(DAILY_CLAIM_COOLDOWN_SECS))
+            .filter(|v| *v > utils::now());
+
+        Ok(JsonResponse::ok(CreditResponse { credits, next_claim_at }))
+    }
+
+    /// Claim Daily Credits
+    ///
+    /// Grant the user their daily voting credits, this can be claimed once every
+    /// 24 hours. Claiming too soon returns a 429 with the remaining cooldown.
+    ///
+    /// Returns the user's new credit balance.
+    #[oai(path = "/users/credits/claim", method = "post", tag = "ApiTags::User")]
+    pub async fn claim_daily_credits(
+        &self,
+        session: Data<&Session>,
+        config: Data<&Config>,
+        token: TokenBearer,
+    ) -> Result<JsonResponse<CreditResponse>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(JsonResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let now = utils::now();
+        let last_claim = user_info::get_last_credit_claim(&session, user_id).await?;
+        if let Some(last_claim) = last_claim {
+            let remaining = DAILY_CLAIM_COOLDOWN_SECS - (now - last_claim).num_seconds();
+            if remaining > 0 {
+                return Ok(JsonResponse::too_many_requests(
+                    "You have already claimed your daily credits.",
+                    remaining as u64,
+                ))
+            }
+        }
+
+        // Another request claimed the credits since they were checked.
+        if !user_info::set_last_credit_claim_if(&session, user_id, last_claim, Some(now)).await? {
+            return Ok(JsonResponse::too_many_requests(
+                "You have already claimed your daily credits.",
+                DAILY_CLAIM_COOLDOWN_SECS as u64,
+            ))
+        }
+
+        if let Err(e) = user_info::adjust_user_credits(&session, user_id, config.daily_credit_claim).await {
+            user_info::set_last_credit_claim_if(&session, user_id, Some(now), last_claim).await?;
+            return Err(e.into())
+        }
+
+        let credits = user_info::get_user_vote_credits(&session, user_id).await?;
+        let next_claim_at = Some(now + Duration::seconds(DAILY_CLAIM_COOLDOWN_SECS));
+        Ok(JsonResponse::ok(CreditResponse { credits, next_claim_at }))
+    }
+
+    /// Delete User
+    ///
+    /// Deletes all of the playlists, entries, votes and credits of the user
+    /// associated with a given token, along with the user itself and all of
+    /// their access tokens.
+    ///
+    /// Entries which are in other users' playlists are removed from those
+    /// playlists. A summary of what was deleted is returned.
+    #[oai(path = "/users/@me", method = "delete", tag = "ApiTags::User")]
+    pub async fn delete_user(
+        &self,
+        session: Data<&Session>,
+        token: TokenBearer,
+    ) -> Result<DeletionResponse> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(DeletionResponse::Unauthorized),
+            Some(v) => v,
+        };
+
+        let mut summary = DeletionSummary::default();
+        match deletion::delete_user_data(&session, user_id, &mut summary).await {
+            Ok(()) => {
+                info!("deleted user {}: {:?}", user_id, summary);
+                Ok(DeletionResponse::Ok(Json(summary)))
+            },
+            Err(e) => {
+                error!("failed to delete user {}: {} after {:?}", user_id, e, summary);
+                Ok(DeletionResponse::Incomplete(Json(summary)))
+            },
+        }
+    }
+
+    /// Export User Data
+    ///
+    /// Get everything held about the user associated with a given token as a
+    /// single JSON file, this includes their profile, playlists, entries, recent
+    /// votes, credits, notifications and rooms.
+    #[oai(path = "/users/export", method = "get", tag = "ApiTags::User")]
+    pub async fn export_user_data(
+        &self,
+        session: Data<&Session>,
+        token: TokenBearer,
+    ) -> Result<ExportResponse> {
+        match export::export_user_data(&session, &token.0.token).await? {
+            None => Ok(ExportResponse::Unauthorized),
+            Some(export) => {
+                let disposition = format!(
+                    "attachment; filename=\"spooderfy-export-{}.json\"",
+                    export.exported_at.format("%Y-%m-%d"),
+                );
+
+                Ok(ExportResponse::Ok(Json(Box::new(export)), disposition))
+            },
         }
     }
 
@@ -178,7 +353,7 @@ impl UsersApi {
             Some(room) => room,
         };
 
-        crate::rooms::set_room_playlist(&session, room.id.clone(), playlist.id).await?;
+        crate::rooms::set_room_playlist(&session, room.id, playlist.id).await?;
 
         room.active_playlist = Some(playlist.id);
 
@@ -205,7 +380,7 @@ impl UsersApi {
             Some(room) => room,
         };
 
-        let active_id = match room.active_playlist.clone() {
+        let active_id = match room.active_playlist {
             None => return Ok(JsonResponse::bad_request("No playlist selected.")),
             Some(active_id) => active_id,
         };
@@ -221,8 +396,8 @@ impl UsersApi {
 
         crate::rooms::set_room_currently_playing(
             &session,
-            room.id.clone(),
-            entry_id.0.clone(),
+            room.id,
+            entry_id.0,
         ).await?;
 
         room.playing_now = Some(entry_id.0);
@@ -254,9 +429,35 @@ impl UsersApi {
         session: Data<&Session>,
         token: TokenBearer,
     ) -> Result<JsonResponse<Vec<Playlist>>> {
-        match playlist_info::get_playlists_for_token(&session, &token.0.token).await? {
+        match playlist_info::get_playlists_for_token(&session, &token.0.token, None, None).await? {
             None => Ok(JsonResponse::unauthorized()),
-            Some(playlists) =>  Ok(JsonResponse::ok(playlists)),
+            Some(page) =>  Ok(JsonResponse::ok(page.playlists)),
+        }
+    }
+
+    /// Get User Playlists Page
+    ///
+    /// Get a page of the user's playlists, the returned `next_cursor` can be
+    /// passed back as the `cursor` to get the following page. The url of the
+    /// next page is also given in the `Link` header.
+    #[oai(path = "/users/playlists", method = "get", tag = "ApiTags::User")]
+    pub async fn get_user_playlists_page(
+        &self,
+        req: &Request,
+        #[oai(validator(minimum(value = "1"), maximum(value = "100")))]
+        limit: Query<Option<i32>>,
+        cursor: Query<Option<String>>,
+        session: Data<&Session>,
+        token: TokenBearer,
+    ) -> Result<PageResponse<PlaylistPage>> {
+        let cursor = parse_cursor(cursor.0.as_deref())?;
+        let limit = limit.0.unwrap_or(DEFAULT_PAGE_SIZE);
+        match playlist_info::get_playlists_for_token(&session, &token.0.token, Some(limit), cursor).await? {
+            None => Ok(PageResponse::unauthorized()),
+            Some(page) => {
+                let next_cursor = page.next_cursor.clone();
+                Ok(PageResponse::ok(req, page, next_cursor.as_deref()))
+            },
         }
     }
 
@@ -269,12 +470,43 @@ impl UsersApi {
         session: Data<&Session>,
         token: TokenBearer,
     ) -> Result<JsonResponse<Vec<PlaylistEntry>>> {
-        match playlist_info::get_playlist_entries_for_token(&session, &token.0.token).await? {
+        match playlist_info::get_playlist_entries_for_token(&session, &token.0.token, None, None).await? {
             None => Ok(JsonResponse::unauthorized()),
-            Some(entries) =>  Ok(JsonResponse::ok(entries)),
+            Some(page) =>  Ok(JsonResponse::ok(page.entries)),
         }
     }
 
+    /// Get User Votes
+    ///
+    /// Get a page of the playlists and entries the user has active votes on,
+    /// the returned `next_cursor` can be passed back as the `cursor` to get
+    /// the following page. The url of the next page is also given in the
+    /// `Link` header.
+    ///
+    /// Playlist votes are listed before entry votes.
+    #[oai(path = "/users/votes", method = "get", tag = "ApiTags::User")]
+    pub async fn get_user_votes(
+        &self,
+        req: &Request,
+        #[oai(validator(minimum(value = "1"), maximum(value = "100")))]
+        limit: Query<Option<i32>>,
+        cursor: Query<Option<String>>,
+        session: Data<&Session>,
+        token: TokenBearer,
+    ) -> Result<PageResponse<VotePage>> {
+        let user_id = match user_info::get_user_id_from_token(&session, &token.0.token).await? {
+            None => return Ok(PageResponse::unauthorized()),
+            Some(v) => v,
+        };
+
+        let cursor = parse_cursor(cursor.0.as_deref())?;
+        let limit = limit.0.unwrap_or(DEFAULT_PAGE_SIZE);
+        let page = votes::get_votes_for_user(&session, user_id, limit, cursor).await?;
+        let next_cursor = page.next_cursor.clone();
+
+        Ok(PageResponse::ok(req, page, next_cursor.as_deref()))
+    }
+
     /// Add User Credits
     ///
     /// Add the user credits associated with a given token.
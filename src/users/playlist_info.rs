@@ -1,56 +1,176 @@
+use std::collections::HashSet;
 use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use poem_openapi::Object;
 use scylla::IntoTypedRows;
 
 use crate::db::Session;
 use super::user_info;
 use crate::playlists::{PlaylistEntry, Playlist};
+use uuid::Uuid;
 
+use crate::utils::encode_cursor;
 
+
+#[derive(Object)]
+pub struct PlaylistPage {
+    pub playlists: Vec<Playlist>,
+
+    /// The cursor to fetch the next page with if there are more playlists.
+    pub next_cursor: Option<String>,
+}
+
+
+#[derive(Object)]
+pub struct EntryPage {
+    pub entries: Vec<PlaylistEntry>,
+
+    /// The cursor to fetch the next page with if there are more entries.
+    pub next_cursor: Option<String>,
+}
+
+
+/// Gets a page of the playlists owned by the user of the given token.
+///
+/// If no limit is given all playlists are returned in a single page.
 pub async fn get_playlists_for_token(
     sess: &Session,
     token: &str,
-) -> Result<Option<Vec<Playlist>>> {
+    limit: Option<i32>,
+    cursor: Option<Bytes>,
+) -> Result<Option<PlaylistPage>> {
     let user_id = match user_info::get_user_id_from_token(sess, token).await? {
         None => return Ok(None),
         Some(user_id) => user_id,
     };
 
-    let result = sess.query_prepared(
-        "SELECT * FROM playlists WHERE owner_id = ?",
-        (user_id,)
+    let result = sess.query_prepared_paged(
+        r#"
+        SELECT id, owner_id, banner, description, is_public, ordered_items, nsfw, title, votes, tags, created_at, updated_at, version, deleted_at
+        FROM playlists_by_owner WHERE owner_id = ?;
+        "#,
+        (user_id,),
+        limit,
+        cursor,
     ).await?;
 
+    let next_cursor = encode_cursor(result.paging_state);
     let rows = result.rows
         .ok_or_else(|| anyhow!("expected returned rows"))?;
 
     let playlists = rows.into_typed::<Playlist>()
         .filter_map(|v| v.ok())
+        .filter(|v| v.deleted_at.is_none())
         .collect();
 
-    Ok(Some(playlists))
+    Ok(Some(PlaylistPage { playlists, next_cursor }))
 }
 
 
+/// Gets a page of the playlist entries owned by the user of the given token.
+///
+/// If no limit is given all entries are returned in a single page.
 pub async fn get_playlist_entries_for_token(
     sess: &Session,
     token: &str,
-) -> Result<Option<Vec<PlaylistEntry>>> {
+    limit: Option<i32>,
+    cursor: Option<Bytes>,
+) -> Result<Option<EntryPage>> {
     let user_id = match user_info::get_user_id_from_token(sess, token).await? {
         None => return Ok(None),
         Some(user_id) => user_id,
     };
 
+    let result = sess.query_prepared_paged(
+        r#"
+        SELECT id, owner_id, description, is_public, nsfw, ref_link, title, votes, created_at, updated_at, deleted_at
+        FROM playlist_entries_by_owner WHERE owner_id = ?;
+        "#,
+        (user_id,),
+        limit,
+        cursor,
+    ).await?;
+
+    let next_cursor = encode_cursor(result.paging_state);
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let entries = rows.into_typed::<PlaylistEntry>()
+        .filter_map(|v| v.ok())
+        .filter(|v| v.deleted_at.is_none())
+        .collect();
+
+    Ok(Some(EntryPage { entries, next_cursor }))
+}
+
+/// Gets a page of the playlist entries owned by the user of the given token
+/// which are not in the items of any of their playlists.
+///
+/// Every item of the user's playlists is read to check each page against,
+/// so pages can have fewer than `limit` entries even when there are more.
+pub async fn get_orphan_entries_for_token(
+    sess: &Session,
+    token: &str,
+    limit: Option<i32>,
+    cursor: Option<Bytes>,
+) -> Result<Option<EntryPage>> {
+    let playlists = match get_playlists_for_token(sess, token, None, None).await? {
+        None => return Ok(None),
+        Some(page) => page.playlists,
+    };
+
+    let used: HashSet<Uuid> = playlists.into_iter()
+        .flat_map(|v| v.items)
+        .collect();
+
+    let mut page = match get_playlist_entries_for_token(sess, token, limit, cursor).await? {
+        None => return Ok(None),
+        Some(page) => page,
+    };
+    page.entries.retain(|v| !used.contains(&v.id));
+
+    Ok(Some(page))
+}
+
+/// Gets the public playlists owned by the given user.
+pub async fn get_public_playlists_for_user(sess: &Session, user_id: i64) -> Result<Vec<Playlist>> {
+    let result = sess.query_prepared(
+        r#"
+        SELECT id, owner_id, banner, description, is_public, ordered_items, nsfw, title, votes, tags, created_at, updated_at, version, deleted_at
+        FROM playlists_by_owner WHERE owner_id = ?;
+        "#,
+        (user_id,)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let playlists = rows.into_typed::<Playlist>()
+        .filter_map(|v| v.ok())
+        .filter(|v| v.is_public & v.deleted_at.is_none())
+        .collect();
+
+    Ok(playlists)
+}
+
+
+/// Gets the public playlist entries owned by the given user.
+pub async fn get_public_entries_for_user(sess: &Session, user_id: i64) -> Result<Vec<PlaylistEntry>> {
     let result = sess.query_prepared(
-        "SELECT * FROM playlist_entries WHERE owner_id = ?",
+        r#"
+        SELECT id, owner_id, description, is_public, nsfw, ref_link, title, votes, created_at, updated_at, deleted_at
+        FROM playlist_entries_by_owner WHERE owner_id = ?;
+        "#,
         (user_id,)
     ).await?;
 
     let rows = result.rows
         .ok_or_else(|| anyhow!("expected returned rows"))?;
 
-    let playlists = rows.into_typed::<PlaylistEntry>()
+    let entries = rows.into_typed::<PlaylistEntry>()
         .filter_map(|v| v.ok())
+        .filter(|v| v.is_public & v.deleted_at.is_none())
         .collect();
 
-    Ok(Some(playlists))
-}
\ No newline at end of file
+    Ok(entries)
+}
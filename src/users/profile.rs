@@ -0,0 +1,53 @@
+use anyhow::Result;
+use poem_openapi::Object;
+
+use crate::db::Session;
+use crate::playlists::Playlist;
+use crate::utils::JsSafeBigInt;
+use super::{playlist_info, user_info};
+
+
+/// The publicly visible information about a user.
+#[derive(Object)]
+pub struct UserProfile {
+    pub id: JsSafeBigInt,
+    pub username: String,
+    pub avatar: Option<String>,
+
+    /// The user's public playlists.
+    pub playlists: Vec<Playlist>,
+
+    /// The number of public entries the user owns.
+    pub entry_count: usize,
+
+    /// The total votes received across the user's public playlists and
+    /// entries.
+    pub total_votes: i64,
+}
+
+
+/// Gets the public profile of the given user, returning None if the user
+/// does not exist.
+///
+/// Only public playlists and entries are included or counted.
+pub async fn get_user_profile(sess: &Session, user_id: i64) -> Result<Option<UserProfile>> {
+    let user = match user_info::get_user_from_id(sess, user_id).await? {
+        None => return Ok(None),
+        Some(user) => user,
+    };
+
+    let playlists = playlist_info::get_public_playlists_for_user(sess, user_id).await?;
+    let entries = playlist_info::get_public_entries_for_user(sess, user_id).await?;
+
+    let total_votes = playlists.iter().map(|v| v.votes as i64).sum::<i64>()
+        + entries.iter().map(|v| v.votes as i64).sum::<i64>();
+
+    Ok(Some(UserProfile {
+        id: user.id,
+        username: user.username,
+        avatar: user.avatar,
+        playlists,
+        entry_count: entries.len(),
+        total_votes,
+    }))
+}
@@ -15,7 +15,7 @@ pub async fn get_active_room_for_token(
         Some(user_id) => user_id,
     };
 
-    get_active_room_for_user_id(sess, user_id).await.map(|v| Some(v))
+    get_active_room_for_user_id(sess, user_id).await.map(Some)
 }
 
 
@@ -1,10 +1,31 @@
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use concread::arcache::{ARCache, ARCacheBuilder};
 use scylla::IntoTypedRows;
 use poem_openapi::Object;
+use tracing::Span;
 
-use crate::db::Session;
-use crate::utils::JsSafeBigInt;
+use crate::db::{self, Session};
+use crate::metrics;
+use crate::utils::{self, JsSafeBigInt};
+
+/// How long a resolved token is trusted before it is looked up again.
+const TOKEN_CACHE_TTL: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    static ref TOKEN_CACHE: ARCache<String, CachedUserId> = ARCacheBuilder::new()
+        .set_size(1024, num_cpus::get())
+        .build()
+        .unwrap();
+}
+
+#[derive(Debug, Clone)]
+struct CachedUserId {
+    user_id: i64,
+    cached_at: Instant,
+}
 
 #[derive(Object)]
 pub struct User {
@@ -14,6 +35,9 @@ pub struct User {
     pub avatar: Option<String>,
     pub updated_on: i64,
     pub username: String,
+
+    /// If the user has verified their age and can see NSFW content.
+    pub age_verified: bool,
 }
 
 #[derive(Object)]
@@ -27,7 +51,18 @@ pub struct Guild {
 
 
 /// Gets a user_id from the given access token if it's valid otherwise return None.
+///
+/// Valid tokens are cached for `TOKEN_CACHE_TTL`, invalid tokens are always
+/// looked up so newly issued tokens work straight away.
 pub async fn get_user_id_from_token(sess: &Session, token: &str) -> anyhow::Result<Option<i64>> {
+    let cached = get_cached_user_id(token);
+    metrics::record_token_cache_lookup(cached.is_some());
+
+    if let Some(user_id) = cached {
+        Span::current().record("user_id", &user_id);
+        return Ok(Some(user_id))
+    }
+
     let result = sess.query_prepared(
         "SELECT user_id FROM access_tokens WHERE access_token = ?;",
         (token.to_string(),)
@@ -44,9 +79,89 @@ pub async fn get_user_id_from_token(sess: &Session, token: &str) -> anyhow::Resu
         }
     };
 
+    if let Some(user_id) = user_id {
+        Span::current().record("user_id", &user_id);
+        cache_user_id(token, user_id);
+    }
+
     Ok(user_id)
 }
 
+fn cache_user_id(token: &str, user_id: i64) {
+    let mut writer = TOKEN_CACHE.write();
+    writer.insert(token.to_string(), CachedUserId { user_id, cached_at: Instant::now() });
+    writer.commit();
+}
+
+/// Gets the user the token was resolved to if it was resolved within the
+/// last `TOKEN_CACHE_TTL`, this never queries the database.
+pub fn get_cached_user_id(token: &str) -> Option<i64> {
+    let mut reader = TOKEN_CACHE.read();
+    reader.get(token)
+        .filter(|v| v.cached_at.elapsed() < TOKEN_CACHE_TTL)
+        .map(|v| v.user_id)
+}
+
+/// Removes the token from the cache so it stops being accepted immediately
+/// rather than after `TOKEN_CACHE_TTL`.
+pub fn invalidate_cached_token(token: &str) {
+    let mut writer = TOKEN_CACHE.write();
+    writer.remove(token.to_string());
+    writer.commit();
+}
+
+
+/// Checks if the given user has been granted the moderator role.
+pub async fn is_moderator(sess: &Session, user_id: i64) -> anyhow::Result<bool> {
+    let result = sess.query_prepared(
+        "SELECT user_id FROM moderators WHERE user_id = ?;",
+        (user_id,)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    Ok(!rows.is_empty())
+}
+
+
+/// Checks if the given user has verified their age, users who have never
+/// been verified are not.
+pub async fn is_age_verified(sess: &Session, user_id: i64) -> anyhow::Result<bool> {
+    let result = sess.query_prepared(
+        "SELECT age_verified FROM users WHERE id = ?;",
+        (user_id,)
+    ).await?;
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let verified = match rows.into_typed::<(Option<bool>,)>().next() {
+        None => false,
+        Some(row) => row?.0.unwrap_or(false),
+    };
+
+    Ok(verified)
+}
+
+/// Checks if NSFW content can be shown to the user, anonymous requests are
+/// never shown it.
+pub async fn can_view_nsfw(sess: &Session, user_id: Option<i64>) -> anyhow::Result<bool> {
+    match user_id {
+        None => Ok(false),
+        Some(user_id) => is_age_verified(sess, user_id).await,
+    }
+}
+
+pub async fn can_view_nsfw_for_token(sess: &Session, token: Option<&str>) -> anyhow::Result<bool> {
+    let user_id = match token {
+        None => None,
+        Some(token) => get_user_id_from_token(sess, token).await?,
+    };
+
+    can_view_nsfw(sess, user_id).await
+}
+
 
 /// Gets a full user object from the given access token.
 pub async fn get_user_from_token(sess: &Session, token: &str) -> anyhow::Result<Option<User>> {
@@ -60,14 +175,14 @@ pub async fn get_user_from_token(sess: &Session, token: &str) -> anyhow::Result<
 
 pub async fn get_user_from_id(sess: &Session, user_id: i64) -> anyhow::Result<Option<User>> {
     let result = sess.query_prepared(
-        "SELECT id, access_servers, avatar, updated_on, username FROM users WHERE id = ?;",
+        "SELECT id, access_servers, avatar, updated_on, username, age_verified FROM users WHERE id = ?;",
         (user_id,)
     ).await?;
 
     let rows = result.rows
         .ok_or_else(|| anyhow!("expected returned rows"))?;
 
-    type UserInfo = (JsSafeBigInt, HashMap<i64, bool>, Option<String>, chrono::Duration, String);
+    type UserInfo = (JsSafeBigInt, HashMap<i64, bool>, Option<String>, chrono::Duration, String, Option<bool>);
 
     let res = match rows.into_typed::<UserInfo>().next() {
         None => None,
@@ -78,7 +193,8 @@ pub async fn get_user_from_id(sess: &Session, user_id: i64) -> anyhow::Result<Op
                 access_servers: v.1,
                 avatar: v.2,
                 updated_on: v.3.num_milliseconds(),
-                username: v.4
+                username: v.4,
+                age_verified: v.5.unwrap_or(false),
             })
         },
     };
@@ -86,48 +202,160 @@ pub async fn get_user_from_id(sess: &Session, user_id: i64) -> anyhow::Result<Op
     Ok(res)
 }
 
-/// Gets the amount of credits the user currently has by a given token.
-pub async fn get_vote_credits_for_token(sess: &Session, token: &str) -> anyhow::Result<Option<i32>> {
-    let user_id = match get_user_id_from_token(sess, token).await? {
-        None => return Ok(None),
-        Some(user_id) => user_id,
-    };
-
-    get_user_vote_credits(sess, user_id).await.map(|v| Some(v))
-}
-
 /// Gets the amount of credits the user currently has.
 ///
 /// This assumes the user currently exists.
 pub async fn get_user_vote_credits(sess: &Session, user_id: i64) -> anyhow::Result<i32> {
+    get_credits_row(sess, user_id).await.map(|v| v.unwrap_or(0))
+}
+
+
+/// Increments a user's vote credits by a given amount.
+///
+/// This assumes the user currently exists.
+pub async fn adjust_user_credits(sess: &Session, user_id: i64, value: i32) -> anyhow::Result<()> {
+    for _ in 0..MAX_CREDIT_UPDATE_ATTEMPTS {
+        let current = get_credits_row(sess, user_id).await?;
+        let new = current.unwrap_or(0) + value;
+
+        if set_credits_if(sess, user_id, current, new).await? {
+            return Ok(())
+        }
+    }
+
+    Err(anyhow!("failed to adjust user credits due to concurrent updates"))
+}
+
+
+/// Increments a user's vote credits by a given amount without taking the
+/// balance above `cap`, returning the number of credits actually added.
+///
+/// Like `spend_user_credits` the balance is only changed if it hasn't been
+/// modified since it was read, so a concurrent claim or vote can't push the
+/// balance over the cap.
+pub async fn top_up_user_credits(sess: &Session, user_id: i64, value: i32, cap: i32) -> anyhow::Result<i32> {
+    for _ in 0..MAX_CREDIT_UPDATE_ATTEMPTS {
+        let current = get_credits_row(sess, user_id).await?;
+        let credits = current.unwrap_or(0);
+        let added = value.min(cap - credits);
+
+        if added <= 0 {
+            return Ok(0)
+        }
+
+        if set_credits_if(sess, user_id, current, credits + added).await? {
+            return Ok(added)
+        }
+    }
+
+    Err(anyhow!("failed to top up user credits due to concurrent updates"))
+}
+
+
+/// Removes the given amount of credits from a user, returning false if the
+/// user does not have enough credits.
+///
+/// The balance is only ever changed if it hasn't been modified since it was
+/// read, so concurrent spends can never drive the balance negative.
+pub async fn spend_user_credits(sess: &Session, user_id: i64, cost: i32) -> anyhow::Result<bool> {
+    for _ in 0..MAX_CREDIT_UPDATE_ATTEMPTS {
+        let current = get_credits_row(sess, user_id).await?;
+        let credits = current.unwrap_or(0);
+
+        if credits < cost {
+            return Ok(false)
+        }
+
+        if set_credits_if(sess, user_id, current, credits - cost).await? {
+            metrics::record_credits_spent(cost);
+            return Ok(true)
+        }
+    }
+
+    Err(anyhow!("failed to spend user credits due to concurrent updates"))
+}
+
+
+/// Gets when the user last claimed their daily credits, if ever.
+pub async fn get_last_credit_claim(sess: &Session, user_id: i64) -> anyhow::Result<Option<DateTime<Utc>>> {
     let result = sess.query_prepared(
-        "SELECT credits FROM user_vote_credits WHERE id = ?;",
+        "SELECT last_claim FROM users WHERE id = ?;",
         (user_id,)
     ).await?;
 
     let rows = result.rows
         .ok_or_else(|| anyhow!("expected returned rows"))?;
 
+    let last_claim = rows.into_typed::<(Option<chrono::Duration>,)>()
+        .next()
+        .transpose()?
+        .and_then(|v| v.0)
+        .map(utils::from_cql_timestamp);
 
-    let (credits,) = match rows.into_typed::<(i32,)>().next() {
-        None => return Ok(0),
-        Some(v) => v?,
-    };
+    Ok(last_claim)
+}
+
+/// Sets when the user last claimed their daily credits providing it is still
+/// the `current` value, returning if the change was applied.
+///
+/// This is a lightweight transaction so only one of several concurrent claims
+/// can succeed.
+pub async fn set_last_credit_claim_if(
+    sess: &Session,
+    user_id: i64,
+    current: Option<DateTime<Utc>>,
+    new: Option<DateTime<Utc>>,
+) -> anyhow::Result<bool> {
+    let result = sess.query_prepared(
+        "UPDATE users SET last_claim = ? WHERE id = ? IF last_claim = ?;",
+        (new.map(utils::to_cql_timestamp), user_id, current.map(utils::to_cql_timestamp))
+    ).await?;
 
-    Ok(credits)
+    Ok(db::was_applied(&result))
 }
 
 
-/// Increments a user's vote credits by a given amount.
-///
-/// This assumes the user currently exists.
-pub async fn adjust_user_credits(sess: &Session, user_id: i64, value: i32) -> anyhow::Result<()> {
-    sess.query_prepared(
-        "UPDATE user_vote_credits SET credit = credit + ? WHERE user_id = ?;",
-        (value, user_id)
+/// The number of times a credit update is retried if the balance is
+/// changed concurrently.
+const MAX_CREDIT_UPDATE_ATTEMPTS: usize = 5;
+
+async fn get_credits_row(sess: &Session, user_id: i64) -> anyhow::Result<Option<i32>> {
+    let result = sess.query_prepared(
+        "SELECT credits FROM user_vote_credits WHERE user_id = ?;",
+        (user_id,)
     ).await?;
 
-    Ok(())
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let (credits,) = match rows.into_typed::<(Option<i32>,)>().next() {
+        None => return Ok(None),
+        Some(v) => v?,
+    };
+
+    Ok(Some(credits.unwrap_or(0)))
+}
+
+/// Sets the user's credits providing they are still the `current` value
+/// returning if the change was applied.
+async fn set_credits_if(
+    sess: &Session,
+    user_id: i64,
+    current: Option<i32>,
+    new: i32,
+) -> anyhow::Result<bool> {
+    let result = match current {
+        None => sess.query_prepared(
+            "INSERT INTO user_vote_credits (user_id, credits) VALUES (?, ?) IF NOT EXISTS;",
+            (user_id, new)
+        ).await?,
+        Some(current) => sess.query_prepared(
+            "UPDATE user_vote_credits SET credits = ? WHERE user_id = ? IF credits = ?;",
+            (new, user_id, current)
+        ).await?,
+    };
+
+    Ok(db::was_applied(&result))
 }
 
 
@@ -175,4 +403,42 @@ async fn get_guilds(sess: &Session, guild_ids: Vec<i64>) -> anyhow::Result<Vec<G
         .collect();
 
     Ok(guilds)
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A token which won't collide with any other test using the cache.
+    fn test_token() -> String {
+        format!("test-{}", uuid::Uuid::new_v4())
+    }
+
+    #[test]
+    fn cached_tokens_are_returned() {
+        let token = test_token();
+        cache_user_id(&token, 1);
+
+        assert_eq!(get_cached_user_id(&token), Some(1));
+    }
+
+    #[test]
+    fn invalidated_tokens_are_evicted() {
+        let token = test_token();
+        cache_user_id(&token, 1);
+        invalidate_cached_token(&token);
+
+        assert_eq!(get_cached_user_id(&token), None);
+    }
+
+    #[test]
+    fn invalidating_a_token_leaves_others_cached() {
+        let (token, other) = (test_token(), test_token());
+        cache_user_id(&token, 1);
+        cache_user_id(&other, 2);
+        invalidate_cached_token(&token);
+
+        assert_eq!(get_cached_user_id(&other), Some(2));
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::anyhow;
+use bytes::{BufMut, Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use poem_openapi::{Enum, Object};
+use scylla::IntoTypedRows;
+use uuid::Uuid;
+
+use crate::db::Session;
+use crate::errors::{ApiError, ApiResult};
+use crate::utils::{self, encode_cursor};
+
+
+#[derive(Enum, Copy, Clone, PartialEq, Eq)]
+#[oai(rename_all = "lowercase")]
+pub enum VoteKind {
+    Playlist,
+    Entry,
+}
+
+impl VoteKind {
+    fn as_byte(self) -> u8 {
+        match self {
+            Self::Playlist => 0,
+            Self::Entry => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Playlist),
+            1 => Some(Self::Entry),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(Object)]
+pub struct Vote {
+    pub kind: VoteKind,
+
+    /// The id of the playlist or entry which was voted on.
+    pub id: Uuid,
+
+    /// When the vote was made.
+    pub voted_on: Option<DateTime<Utc>>,
+}
+
+
+#[derive(Object)]
+pub struct VotePage {
+    pub votes: Vec<Vote>,
+
+    /// The cursor to fetch the next page with if there are more votes.
+    pub next_cursor: Option<String>,
+}
+
+
+/// Gets a page of the user's active votes, playlist votes are listed first
+/// followed by entry votes.
+///
+/// The cursor starts with the kind of vote being paged through followed by
+/// the paging state for that kind, so a page can end part way through
+/// either kind.
+pub async fn get_votes_for_user(
+    sess: &Session,
+    user_id: i64,
+    limit: i32,
+    cursor: Option<Bytes>,
+) -> ApiResult<VotePage> {
+    let (kind, paging_state) = match cursor {
+        None => (VoteKind::Playlist, None),
+        Some(cursor) => {
+            let kind = cursor.first()
+                .copied()
+                .and_then(VoteKind::from_byte)
+                .ok_or_else(|| ApiError::Validation("Invalid cursor.".to_string()))?;
+
+            let paging_state = Some(cursor.slice(1..))
+                .filter(|v| !v.is_empty());
+
+            (kind, paging_state)
+        },
+    };
+
+    let query = match kind {
+        VoteKind::Playlist => "SELECT playlist_id, voted_on FROM playlist_votes_by_user WHERE user_id = ?;",
+        VoteKind::Entry => "SELECT entry_id, voted_on FROM playlist_entries_votes_by_user WHERE user_id = ?;",
+    };
+
+    let result = sess.query_prepared_paged(
+        query,
+        (user_id,),
+        Some(limit),
+        paging_state,
+    ).await?;
+
+    let next = match (kind, result.paging_state) {
+        (kind, Some(state)) => Some((kind, state)),
+        (VoteKind::Playlist, None) => Some((VoteKind::Entry, Bytes::new())),
+        (VoteKind::Entry, None) => None,
+    };
+
+    let next_cursor = encode_cursor(next.map(|(kind, state)| {
+        let mut cursor = BytesMut::with_capacity(state.len() + 1);
+        cursor.put_u8(kind.as_byte());
+        cursor.put(state);
+        cursor.freeze()
+    }));
+
+    let rows = result.rows
+        .ok_or_else(|| anyhow!("expected returned rows"))?;
+
+    let votes = rows.into_typed::<(Uuid, Option<chrono::Duration>)>()
+        .filter_map(|v| v.ok())
+        .map(|(id, voted_on)| Vote {
+            kind,
+            id,
+            voted_on: voted_on.map(utils::from_cql_timestamp),
+        })
+        .collect();
+
+    Ok(VotePage { votes, next_cursor })
+}
@@ -1,19 +1,32 @@
 use std::borrow::Cow;
+use std::collections::HashSet;
 use std::fmt::{Display, Formatter};
 use std::ops::Deref;
 use std::str::FromStr;
-use poem::Request;
+use bytes::Bytes;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use poem::{FromRequest, Request, RequestBody};
+use poem::http::header;
 use poem_openapi::payload::Json;
-use poem_openapi::types::{ParseError, ParseFromJSON, ParseResult, ToJSON, Type};
-use poem_openapi::{Object, ApiResponse, SecurityScheme};
+use poem_openapi::types::{ParseError, ParseFromJSON, ParseFromParameter, ParseResult, ToJSON, Type};
+use poem_openapi::{ApiResponse, SecurityScheme};
 use poem_openapi::auth::Bearer;
-use poem_openapi::registry::MetaSchemaRef;
+use poem_openapi::error::{ParseJsonError, ParseParamError};
+use poem_openapi::registry::{MetaSchemaRef, Registry};
 use scylla::cql_to_rust::{FromCqlVal, FromCqlValError};
 use scylla::frame::response::result::CqlValue;
+use scylla::frame::value::Timestamp;
 use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use subtle::{Choice, ConstantTimeEq};
+use uuid::Uuid;
 
+use crate::db::Session;
+use crate::errors::{ApiError, ApiResult};
+use crate::users::user_info;
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct JsSafeBigInt(pub i64);
 
 impl Display for JsSafeBigInt {
@@ -58,11 +71,30 @@ impl ToJSON for JsSafeBigInt {
     }
 }
 
+/// The largest integer magnitude a JavaScript number can represent exactly.
+const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
 impl ParseFromJSON for JsSafeBigInt {
+    /// Parses the id from either a JSON number or a numeric string.
+    ///
+    /// Numbers outside of the JS safe integer range are rejected as they
+    /// will already have lost precision on the client, ids larger than
+    /// this must be sent as strings.
     fn parse_from_json(value: Value) -> ParseResult<Self> {
-        value.as_i64()
-            .map(|v| Self(v))
-            .ok_or_else(|| ParseError::custom("cannot convert value into integer"))
+        if let Some(id) = value.as_str().and_then(|s| s.parse::<i64>().ok()) {
+            return Ok(Self(id))
+        }
+
+        let id = value.as_i64()
+            .ok_or_else(|| ParseError::custom("cannot convert value into integer"))?;
+
+        if !(-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(&id) {
+            return Err(ParseError::custom(
+                "integer is outside of the JS safe integer range, send it as a string instead"
+            ))
+        }
+
+        Ok(Self(id))
     }
 }
 
@@ -75,51 +107,391 @@ impl FromStr for JsSafeBigInt {
     }
 }
 
+impl ParseFromParameter for JsSafeBigInt {
+    fn parse_from_parameter(value: &str) -> ParseResult<Self> {
+        value.parse::<i64>()
+            .map(Self)
+            .map_err(ParseError::custom)
+    }
+}
+
 impl FromCqlVal<CqlValue> for JsSafeBigInt {
     fn from_cql(cql_val: CqlValue) -> Result<Self, FromCqlValError> {
         cql_val.as_bigint()
-            .map(|v| Self(v))
-            .ok_or_else(|| FromCqlValError::BadCqlType)
+            .map(Self)
+            .ok_or(FromCqlValError::BadCqlType)
+    }
+}
+
+
+/// A string which is trimmed and has runs of whitespace collapsed into a
+/// single space when parsed.
+///
+/// This happens before any validators run, so `min_length` applies to the
+/// normalized value and whitespace only strings are rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NormalizedString(pub String);
+
+impl NormalizedString {
+    pub fn into_inner(self) -> String {
+        self.0
     }
 }
 
+impl Deref for NormalizedString {
+    type Target = String;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<NormalizedString> for String {
+    fn from(v: NormalizedString) -> Self {
+        v.0
+    }
+}
+
+impl Type for NormalizedString {
+    const IS_REQUIRED: bool = <String as Type>::IS_REQUIRED;
+    type RawValueType = <String as Type>::RawValueType;
+    type RawElementValueType = <String as Type>::RawElementValueType;
+
+    fn name() -> Cow<'static, str> {
+        String::name()
+    }
+
+    fn schema_ref() -> MetaSchemaRef {
+        String::schema_ref()
+    }
+
+    fn as_raw_value(&self) -> Option<&Self::RawValueType> {
+        Some(&self.0)
+    }
+
+    fn raw_element_iter<'a>(&'a self) -> Box<dyn Iterator<Item=&'a Self::RawElementValueType> + 'a> {
+        self.0.raw_element_iter()
+    }
+}
+
+impl ToJSON for NormalizedString {
+    fn to_json(&self) -> Value {
+        self.0.to_json()
+    }
+}
+
+impl ParseFromJSON for NormalizedString {
+    fn parse_from_json(value: Value) -> ParseResult<Self> {
+        let value = String::parse_from_json(value).map_err(ParseError::propagate)?;
+        let normalized = value.split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ");
+
+        Ok(Self(normalized))
+    }
+}
+
+
+/// The current time, truncated to the millisecond precision Scylla
+/// stores timestamps with.
+pub fn now() -> DateTime<Utc> {
+    Utc.timestamp_millis(Utc::now().timestamp_millis())
+}
+
+/// Converts a timestamp read from Scylla into a UTC datetime.
+pub fn from_cql_timestamp(ts: Duration) -> DateTime<Utc> {
+    Utc.timestamp_millis(ts.num_milliseconds())
+}
+
+/// Converts a UTC datetime into a timestamp which can be bound to a query.
+pub fn to_cql_timestamp(dt: DateTime<Utc>) -> Timestamp {
+    Timestamp(Duration::milliseconds(dt.timestamp_millis()))
+}
+
+
+/// A partial update payload which additionally remembers which fields
+/// were explicitly sent as `null`.
+///
+/// Poem treats a missing field and a `null` field the same way, which
+/// is fine for creation but means a PATCH cannot tell "leave this alone"
+/// apart from "clear this".
+pub struct PatchPayload<T> {
+    pub inner: T,
+    nulled: HashSet<String>,
+}
+
+impl<T> PatchPayload<T> {
+    /// Returns true if the given field was explicitly set to `null`.
+    pub fn is_cleared(&self, field: &str) -> bool {
+        self.nulled.contains(field)
+    }
+}
+
+impl<T> Deref for PatchPayload<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T: Type> Type for PatchPayload<T> {
+    const IS_REQUIRED: bool = T::IS_REQUIRED;
+    type RawValueType = T::RawValueType;
+    type RawElementValueType = T::RawElementValueType;
+
+    fn name() -> Cow<'static, str> {
+        T::name()
+    }
+
+    fn schema_ref() -> MetaSchemaRef {
+        T::schema_ref()
+    }
+
+    fn register(registry: &mut Registry) {
+        T::register(registry)
+    }
+
+    fn as_raw_value(&self) -> Option<&Self::RawValueType> {
+        self.inner.as_raw_value()
+    }
+
+    fn raw_element_iter<'a>(&'a self) -> Box<dyn Iterator<Item=&'a Self::RawElementValueType> + 'a> {
+        self.inner.raw_element_iter()
+    }
+}
+
+impl<T: ParseFromJSON> ParseFromJSON for PatchPayload<T> {
+    fn parse_from_json(value: Value) -> ParseResult<Self> {
+        let nulled = value.as_object()
+            .map(|obj| {
+                obj.iter()
+                    .filter(|(_, v)| v.is_null())
+                    .map(|(k, _)| k.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let inner = T::parse_from_json(value).map_err(ParseError::propagate)?;
+
+        Ok(Self { inner, nulled })
+    }
+}
+
+
+/// The number of rows returned by a paginated listing if no limit is given.
+pub const DEFAULT_PAGE_SIZE: i32 = 50;
+
+/// Encodes a Scylla paging state into an opaque cursor which can be handed
+/// to clients.
+pub fn encode_cursor(paging_state: Option<Bytes>) -> Option<String> {
+    paging_state.map(|v| base64::encode_config(v, base64::URL_SAFE_NO_PAD))
+}
+
+/// Decodes a cursor produced by `encode_cursor` back into a paging state.
+pub fn decode_cursor(cursor: &str) -> Option<Bytes> {
+    base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .map(Bytes::from)
+}
+
+/// Decodes the optional `cursor` query parameter of a paginated listing,
+/// failing with a validation error if it is not a valid cursor.
+pub fn parse_cursor(cursor: Option<&str>) -> ApiResult<Option<Bytes>> {
+    match cursor {
+        None => Ok(None),
+        Some(cursor) => decode_cursor(cursor)
+            .map(Some)
+            .ok_or_else(|| ApiError::Validation("Invalid cursor.".to_string())),
+    }
+}
+
+/// A request field which failed validation and the rule it broke.
+#[derive(Debug)]
+pub struct FieldError {
+    pub field: String,
+
+    /// The validator's name as written in `#[oai(validator(...))]`, e.g.
+    /// `min_length`, or `unknown` / `read_only` for fields which can't be
+    /// given at all.
+    pub rule: String,
+}
+
+/// Extracts the failing field and rule from a poem-openapi parameter or
+/// JSON body parse error.
+///
+/// poem-openapi only reports failures as messages, and stops at the first
+/// failing field, so at most one field is returned. Errors which don't name
+/// a field, such as a missing field or the wrong type, return `None`.
+///
+/// The messages are pinned by a test for each validator the API uses, so an
+/// upgrade which changes them fails the tests rather than dropping the errors.
+pub fn field_errors(err: &poem::Error) -> Option<Vec<FieldError>> {
+    let error = if let Some(err) = err.downcast_ref::<ParseParamError>() {
+        FieldError {
+            field: err.name.to_string(),
+            rule: failed_rule(&err.reason)?,
+        }
+    } else if let Some(err) = err.downcast_ref::<ParseJsonError>() {
+        parse_field_error(&err.reason)?
+    } else {
+        return None
+    };
+
+    Some(vec![error])
+}
+
+/// Parses a field error out of an object parse message such as
+/// ``failed to parse "Payload": field `title` verification failed. minLength(1)``.
+fn parse_field_error(reason: &str) -> Option<FieldError> {
+    let (field, rule) = if let Some((_, rest)) = reason.split_once("unknown field `") {
+        (rest.split_once('`')?.0, "unknown".to_string())
+    } else if let Some((_, rest)) = reason.split_once("properties `") {
+        let (field, rest) = rest.split_once('`')?;
+        if !rest.starts_with(" is read only") {
+            return None
+        }
+
+        (field, "read_only".to_string())
+    } else {
+        let (_, rest) = reason.split_once("field `")?;
+        let (field, rest) = rest.split_once('`')?;
+        (field, failed_rule(rest)?)
+    };
+
+    Some(FieldError { field: field.to_string(), rule })
+}
+
+/// Gets the snake case name of the validator from a
+/// `verification failed. maxLength(32)` message.
+fn failed_rule(reason: &str) -> Option<String> {
+    let (_, validator) = reason.split_once("verification failed. ")?;
+    let name = validator.split('(').next()?.trim();
+    if name.is_empty() {
+        return None
+    }
+
+    let mut rule = String::with_capacity(name.len() + 2);
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            rule.push('_');
+            rule.push(c.to_ascii_lowercase());
+        } else {
+            rule.push(c);
+        }
+    }
+
+    Some(rule)
+}
+
 
 lazy_static!{
-    static ref SUPERUSER_KEY: Option<String> = {
-      std::env::var("SUPERUSER_KEY").ok()
+    /// The SHA-256 hashes of the keys accepted by `SuperUserBearer`.
+    ///
+    /// These are read from the comma separated `SUPERUSER_KEYS` variable,
+    /// falling back to the single `SUPERUSER_KEY` if it is not set.
+    static ref SUPERUSER_KEY_HASHES: Vec<[u8; 32]> = {
+        std::env::var("SUPERUSER_KEYS")
+            .or_else(|_| std::env::var("SUPERUSER_KEY"))
+            .map(|keys| parse_key_hashes(&keys))
+            .unwrap_or_default()
     };
 }
 
+/// Hashes each key in the comma separated list, ignoring blank keys.
+fn parse_key_hashes(keys: &str) -> Vec<[u8; 32]> {
+    keys.split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(hash_key)
+        .collect()
+}
+
+fn hash_key(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
 #[derive(SecurityScheme)]
 #[oai(type = "bearer")]
 pub struct TokenBearer(pub Bearer);
 
+/// The bearer token of the request if one was given.
+///
+/// This is used by endpoints which are public but return extra information
+/// to authenticated users.
+pub struct OptionalTokenBearer(pub Option<String>);
+
+#[poem::async_trait]
+impl<'a> FromRequest<'a> for OptionalTokenBearer {
+    async fn from_request(req: &'a Request, _body: &mut RequestBody) -> poem::Result<Self> {
+        let token = req.headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(|v| v.to_string());
+
+        Ok(Self(token))
+    }
+}
+
+/// A superuser.
+///
+/// This contains a short fingerprint of the key used so actions can be
+/// attributed without storing the key itself.
 #[derive(SecurityScheme)]
 #[oai(type = "bearer", checker = "token_checker")]
-pub struct SuperUserBearer(());
+pub struct SuperUserBearer(pub String);
 
-async fn token_checker(_: &Request, bearer: Bearer) -> Option<()> {
-    if let Some(key) = SUPERUSER_KEY.as_ref() {
-        if &bearer.token == key {
-            return Some(())
-        }
+async fn token_checker(_: &Request, bearer: Bearer) -> Option<String> {
+    if is_superuser_key(&bearer.token) {
+        Some(key_fingerprint(&bearer.token))
+    } else {
+        None
     }
+}
+
+/// The first 8 bytes of the key's hash, hex encoded.
+fn key_fingerprint(key: &str) -> String {
+    hash_key(key)[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
 
-    None
+pub fn is_superuser_key(token: &str) -> bool {
+    matches_any_key(token, &SUPERUSER_KEY_HASHES)
 }
 
+fn matches_any_key(token: &str, key_hashes: &[[u8; 32]]) -> bool {
+    // The hashes are compared rather than the keys themselves so every
+    // comparison is over the same length, and every key is checked in
+    // constant time so the time taken doesn't reveal which key, or how
+    // much of it, matched.
+    let token = hash_key(token);
+    let matched = key_hashes.iter()
+        .fold(Choice::from(0), |acc, key| acc | token.ct_eq(key));
 
-#[derive(Object)]
-pub struct Detail {
-    /// More information for the given error.
-    detail: String,
+    matched.into()
 }
 
-impl From<String> for Detail {
-    fn from(msg: String) -> Self {
-        Self {
-            detail: msg
-        }
+/// A moderator, or a superuser who implicitly has all moderator permissions.
+///
+/// This contains the moderator's user id, or None if a superuser key was used.
+#[derive(SecurityScheme)]
+#[oai(type = "bearer", checker = "moderator_checker")]
+pub struct ModeratorBearer(pub Option<i64>);
+
+async fn moderator_checker(req: &Request, bearer: Bearer) -> Option<Option<i64>> {
+    if is_superuser_key(&bearer.token) {
+        return Some(None)
+    }
+
+    let sess = req.extensions().get::<Session>()?;
+    let user_id = user_info::get_user_id_from_token(sess, &bearer.token).await.ok()??;
+
+    match user_info::is_moderator(sess, user_id).await {
+        Ok(true) => Some(Some(user_id)),
+        _ => None,
     }
 }
 
@@ -132,7 +504,7 @@ pub enum JsonResponse<T: Send + Sync + ToJSON> {
 
     /// Some part of the request was invalid.
     #[oai(status = 400)]
-    BadRequest(Json<Detail>),
+    BadRequest(Json<Value>),
 
     /// The provided access token has expired.
     #[oai(status = 401)]
@@ -141,6 +513,23 @@ pub enum JsonResponse<T: Send + Sync + ToJSON> {
     /// You lack the permissions required to perform this action.
     #[oai(status = 403)]
     Forbidden,
+
+    /// The requested resource does not exist.
+    #[oai(status = 404)]
+    NotFound(Json<Value>),
+
+    /// The action conflicts with the current state of the resource.
+    #[oai(status = 409)]
+    Conflict(Json<Value>),
+
+    /// The requested resource existed but is no longer available.
+    #[oai(status = 410)]
+    Gone(Json<Value>),
+
+    /// This action has been performed too recently, try again after the
+    /// given number of seconds.
+    #[oai(status = 429)]
+    TooManyRequests(Json<Value>, #[oai(header = "Retry-After")] u64),
 }
 
 impl<T: Send + Sync + ToJSON> JsonResponse<T> {
@@ -149,7 +538,16 @@ impl<T: Send + Sync + ToJSON> JsonResponse<T> {
     }
 
     pub fn bad_request(msg: impl Display) -> Self {
-        Self::BadRequest(Json(Detail::from(msg.to_string())))
+        Self::BadRequest(Json(json!({
+            "detail": msg.to_string(),
+        })))
+    }
+
+    pub fn invalid_items(msg: impl Display, items: &[Uuid]) -> Self {
+        Self::BadRequest(Json(json!({
+            "detail": msg.to_string(),
+            "invalid_items": items,
+        })))
     }
 
     pub fn forbidden() -> Self {
@@ -159,4 +557,353 @@ impl<T: Send + Sync + ToJSON> JsonResponse<T> {
     pub fn unauthorized() -> Self {
         Self::Unauthorized
     }
-}
\ No newline at end of file
+
+    pub fn not_found(msg: impl Display) -> Self {
+        Self::NotFound(Json(json!({
+            "detail": msg.to_string(),
+        })))
+    }
+
+    pub fn conflict(msg: impl Display) -> Self {
+        Self::Conflict(Json(json!({
+            "detail": msg.to_string(),
+        })))
+    }
+
+    pub fn already_exists(msg: impl Display, id: Uuid) -> Self {
+        Self::Conflict(Json(json!({
+            "detail": msg.to_string(),
+            "id": id,
+        })))
+    }
+
+    pub fn gone(msg: impl Display) -> Self {
+        Self::Gone(Json(json!({
+            "detail": msg.to_string(),
+        })))
+    }
+
+    pub fn still_referenced(msg: impl Display, playlists: &[Uuid]) -> Self {
+        Self::Conflict(Json(json!({
+            "detail": msg.to_string(),
+            "playlists": playlists,
+        })))
+    }
+
+    /// The user can't afford an action costing `cost` credits, `code` lets
+    /// clients tell this apart from other bad requests, e.g. to point the
+    /// user at the daily claim.
+    pub fn insufficient_credits(balance: i32, cost: i32) -> Self {
+        Self::BadRequest(Json(json!({
+            "code": "INSUFFICIENT_CREDITS",
+            "detail": "You do not have enough credits.",
+            "balance": balance,
+            "cost": cost,
+        })))
+    }
+
+    pub fn too_many_requests(msg: impl Display, retry_after_secs: u64) -> Self {
+        Self::TooManyRequests(
+            Json(json!({
+                "detail": msg.to_string(),
+                "retry_after_seconds": retry_after_secs,
+            })),
+            retry_after_secs,
+        )
+    }
+}
+
+
+#[derive(ApiResponse)]
+pub enum PageResponse<T: Send + Sync + ToJSON> {
+    /// The page of results, `Link` has the url of the next page if there is
+    /// one.
+    #[oai(status = 200)]
+    Ok(Json<T>, #[oai(header = "Link")] Option<String>),
+
+    /// The provided access token has expired.
+    #[oai(status = 401)]
+    Unauthorized,
+}
+
+impl<T: Send + Sync + ToJSON> PageResponse<T> {
+    /// The page, linking to the next page if there is a `next_cursor`.
+    pub fn ok(req: &Request, v: T, next_cursor: Option<&str>) -> Self {
+        Self::Ok(Json(v), next_page_link(req, next_cursor))
+    }
+
+    pub fn unauthorized() -> Self {
+        Self::Unauthorized
+    }
+}
+
+/// Builds a `Link` header pointing at the request's url with the `cursor`
+/// replaced by the given cursor.
+///
+/// Cursor paging can only go forwards so only `rel="next"` is given. The
+/// scheme from `X-Forwarded-Proto` is used if set so links are correct
+/// behind a TLS terminating proxy.
+pub fn next_page_link(req: &Request, next_cursor: Option<&str>) -> Option<String> {
+    let cursor = next_cursor?;
+    let uri = req.original_uri();
+
+    let host = req.headers()
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| uri.authority().map(|v| v.as_str()))?;
+
+    let scheme = req.headers()
+        .get("X-Forwarded-Proto")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| req.scheme().to_string());
+
+    // Cursors are url safe base64 so don't need escaping.
+    let cursor = format!("cursor={}", cursor);
+    let mut query: Vec<&str> = uri.query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|v| !v.is_empty() & !v.starts_with("cursor="))
+        .collect();
+    query.push(&cursor);
+
+    Some(format!("<{}://{}{}?{}>; rel=\"next\"", scheme, host, uri.path(), query.join("&")))
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::{Endpoint, IntoEndpoint, Request};
+    use poem_openapi::{Object, OpenApi, OpenApiService};
+    use poem_openapi::param::Query;
+    use poem_openapi::payload::PlainText;
+
+    use super::*;
+
+    /// Uses every validator the API uses so the messages `field_errors`
+    /// relies on are pinned by these tests.
+    #[derive(Object, Debug)]
+    #[oai(deny_unknown_fields)]
+    struct TestPayload {
+        #[oai(validator(max_length = 4, min_length = 2))]
+        title: String,
+
+        #[oai(validator(pattern = r"^[a-z]+$"))]
+        code: Option<String>,
+
+        #[oai(validator(max_items = 2))]
+        items: Option<Vec<i32>>,
+
+        #[oai(validator(minimum(value = "1"), maximum(value = "10")))]
+        count: Option<i32>,
+
+        #[oai(read_only)]
+        votes: Option<i32>,
+    }
+
+    /// Gets the message poem-openapi gives for rejecting the payload.
+    fn rejection(payload: Value) -> String {
+        TestPayload::parse_from_json(payload).unwrap_err().into_message()
+    }
+
+    fn rule_for(payload: Value) -> (String, String) {
+        let error = parse_field_error(&rejection(payload)).unwrap();
+        (error.field, error.rule)
+    }
+
+    struct TestApi;
+
+    #[OpenApi]
+    impl TestApi {
+        #[oai(path = "/test", method = "get")]
+        async fn test(
+            &self,
+            #[oai(validator(minimum(value = "1"), maximum(value = "100")))]
+            limit: Query<Option<i32>>,
+            #[oai(validator(max_length = 4))]
+            name: Query<Option<String>>,
+        ) -> PlainText<String> {
+            PlainText(format!("{:?} {:?}", limit.0, name.0))
+        }
+    }
+
+    /// Gets the field errors of the error poem-openapi fails the request with.
+    async fn param_rule_for(query: &str) -> (String, String) {
+        let app = OpenApiService::new(TestApi, "Test", "1.0.0").into_endpoint();
+        let req = Request::builder().uri_str(format!("/test?{}", query)).finish();
+        let err = match app.call(req).await {
+            Ok(_) => panic!("expected {} to be rejected", query),
+            Err(err) => err,
+        };
+
+        let mut errors = field_errors(&err).unwrap();
+        let error = errors.remove(0);
+        (error.field, error.rule)
+    }
+
+    #[test]
+    fn js_safe_big_int_accepts_numbers_in_the_safe_range() {
+        let id = JsSafeBigInt::parse_from_json(json!(MAX_SAFE_INTEGER)).unwrap();
+        assert_eq!(id.0, MAX_SAFE_INTEGER);
+
+        let id = JsSafeBigInt::parse_from_json(json!(-MAX_SAFE_INTEGER)).unwrap();
+        assert_eq!(id.0, -MAX_SAFE_INTEGER);
+    }
+
+    #[test]
+    fn js_safe_big_int_rejects_numbers_outside_the_safe_range() {
+        assert!(JsSafeBigInt::parse_from_json(json!(MAX_SAFE_INTEGER + 1)).is_err());
+        assert!(JsSafeBigInt::parse_from_json(json!(-MAX_SAFE_INTEGER - 1)).is_err());
+        assert!(JsSafeBigInt::parse_from_json(json!(i64::MAX)).is_err());
+    }
+
+    #[test]
+    fn js_safe_big_int_accepts_any_i64_as_a_string() {
+        let id = JsSafeBigInt::parse_from_json(json!("9223372036854775807")).unwrap();
+        assert_eq!(id.0, i64::MAX);
+
+        let id = JsSafeBigInt::parse_from_json(json!("-42")).unwrap();
+        assert_eq!(id.0, -42);
+    }
+
+    #[test]
+    fn js_safe_big_int_rejects_non_integers() {
+        assert!(JsSafeBigInt::parse_from_json(json!("12a")).is_err());
+        assert!(JsSafeBigInt::parse_from_json(json!("9223372036854775808")).is_err());
+        assert!(JsSafeBigInt::parse_from_json(json!(1.5)).is_err());
+        assert!(JsSafeBigInt::parse_from_json(json!(null)).is_err());
+    }
+
+    #[test]
+    fn hash_key_is_the_sha256_of_the_key() {
+        let expected = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let hash: String = hash_key("hello").iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hash, expected);
+        assert_eq!(key_fingerprint("hello"), &expected[..16]);
+    }
+
+    #[test]
+    fn superuser_keys_are_split_and_trimmed() {
+        let hashes = parse_key_hashes(" first , ,second,");
+        assert_eq!(hashes, vec![hash_key("first"), hash_key("second")]);
+    }
+
+    #[test]
+    fn any_configured_superuser_key_matches() {
+        let hashes = parse_key_hashes("first,second");
+        assert!(matches_any_key("first", &hashes));
+        assert!(matches_any_key("second", &hashes));
+    }
+
+    #[test]
+    fn other_keys_do_not_match() {
+        let hashes = parse_key_hashes("first,second");
+        assert!(!matches_any_key("firs", &hashes));
+        assert!(!matches_any_key("first,second", &hashes));
+        assert!(!matches_any_key("", &hashes));
+        assert!(!matches_any_key("first", &[]));
+    }
+
+    #[test]
+    fn normalized_strings_are_trimmed_and_collapsed() {
+        let value = NormalizedString::parse_from_json(json!("  My \t  title\n ")).unwrap();
+        assert_eq!(value.into_inner(), "My title");
+    }
+
+    #[test]
+    fn whitespace_only_strings_normalize_to_empty() {
+        let value = NormalizedString::parse_from_json(json!(" \n\t ")).unwrap();
+        assert_eq!(value.into_inner(), "");
+    }
+
+    #[test]
+    fn normalized_strings_must_be_strings() {
+        assert!(NormalizedString::parse_from_json(json!(12)).is_err());
+        assert!(NormalizedString::parse_from_json(json!(null)).is_err());
+    }
+
+    #[test]
+    fn failed_rule_is_the_snake_case_validator_name() {
+        assert_eq!(failed_rule("verification failed. maxLength(32)").as_deref(), Some("max_length"));
+        assert_eq!(failed_rule("verification failed. pattern(\"^a$\")").as_deref(), Some("pattern"));
+        assert_eq!(failed_rule("verification failed. "), None);
+        assert_eq!(failed_rule("failed to parse"), None);
+    }
+
+    #[test]
+    fn field_errors_name_the_field_and_validator() {
+        let error = parse_field_error(&rejection(json!({ "title": "a" }))).unwrap();
+        assert_eq!((error.field.as_str(), error.rule.as_str()), ("title", "min_length"));
+
+        let error = parse_field_error(&rejection(json!({ "title": "abcde" }))).unwrap();
+        assert_eq!((error.field.as_str(), error.rule.as_str()), ("title", "max_length"));
+    }
+
+    #[test]
+    fn field_errors_report_unknown_and_read_only_fields() {
+        let error = parse_field_error(&rejection(json!({ "title": "abc", "other": 1 }))).unwrap();
+        assert_eq!((error.field.as_str(), error.rule.as_str()), ("other", "unknown"));
+
+        let error = parse_field_error(&rejection(json!({ "title": "abc", "votes": 1 }))).unwrap();
+        assert_eq!((error.field.as_str(), error.rule.as_str()), ("votes", "read_only"));
+    }
+
+    #[test]
+    fn field_errors_skip_errors_without_a_rule() {
+        assert!(parse_field_error(&rejection(json!({}))).is_none());
+        assert!(parse_field_error(&rejection(json!({ "title": 1 }))).is_none());
+    }
+
+    #[test]
+    fn field_errors_read_param_and_body_errors() {
+        let err = poem::Error::from(ParseParamError {
+            name: "limit",
+            reason: "verification failed. maximum(100)".to_string(),
+        });
+        let errors = field_errors(&err).unwrap();
+        assert_eq!((errors[0].field.as_str(), errors[0].rule.as_str()), ("limit", "maximum"));
+
+        let err = poem::Error::from(ParseJsonError { reason: rejection(json!({ "title": "a" })) });
+        let errors = field_errors(&err).unwrap();
+        assert_eq!((errors[0].field.as_str(), errors[0].rule.as_str()), ("title", "min_length"));
+
+        let err = poem::Error::from_status(poem::http::StatusCode::BAD_REQUEST);
+        assert!(field_errors(&err).is_none());
+    }
+
+    #[test]
+    fn field_errors_read_min_length() {
+        assert_eq!(rule_for(json!({ "title": "a" })), ("title".into(), "min_length".into()));
+    }
+
+    #[test]
+    fn field_errors_read_max_length() {
+        assert_eq!(rule_for(json!({ "title": "abcde" })), ("title".into(), "max_length".into()));
+    }
+
+    #[test]
+    fn field_errors_read_pattern() {
+        assert_eq!(rule_for(json!({ "title": "abc", "code": "ABC" })), ("code".into(), "pattern".into()));
+    }
+
+    #[test]
+    fn field_errors_read_max_items() {
+        assert_eq!(rule_for(json!({ "title": "abc", "items": [1, 2, 3] })), ("items".into(), "max_items".into()));
+    }
+
+    #[test]
+    fn field_errors_read_minimum() {
+        assert_eq!(rule_for(json!({ "title": "abc", "count": 0 })), ("count".into(), "minimum".into()));
+    }
+
+    #[test]
+    fn field_errors_read_maximum() {
+        assert_eq!(rule_for(json!({ "title": "abc", "count": 11 })), ("count".into(), "maximum".into()));
+    }
+
+    #[tokio::test]
+    async fn field_errors_read_query_param_validators() {
+        assert_eq!(param_rule_for("limit=0").await, ("limit".into(), "minimum".into()));
+        assert_eq!(param_rule_for("limit=101").await, ("limit".into(), "maximum".into()));
+        assert_eq!(param_rule_for("name=abcde").await, ("name".into(), "max_length".into()));
+    }
+}
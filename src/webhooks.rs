@@ -0,0 +1,185 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use serde::Serialize;
+use serde_json::{json, Value};
+use strum::{Display, EnumString};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::config::Config;
+
+
+/// The number of events which can wait to be delivered, events sent while
+/// the queue is full are dropped.
+const QUEUE_SIZE: usize = 256;
+
+/// The number of times a delivery is attempted before it is dropped.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// The delay before the first retry, this doubles after every attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How long the webhook has to respond to a single attempt.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The playlist vote counts which fire a `vote-milestone` event when reached.
+const VOTE_MILESTONES: &[i32] = &[10, 50, 100, 500, 1000, 5000, 10000];
+
+
+#[derive(Serialize, Display, EnumString, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum EventType {
+    PlaylistCreated,
+    ReportFiled,
+    VoteMilestone,
+}
+
+impl EventType {
+    pub const ALL: &'static [Self] = &[Self::PlaylistCreated, Self::ReportFiled, Self::VoteMilestone];
+}
+
+
+#[derive(Serialize)]
+pub struct Event {
+    #[serde(rename = "type")]
+    type_: EventType,
+
+    data: Value,
+}
+
+
+/// Queues events to be delivered to the `WEBHOOK_URL` by the background
+/// task from `spawn`.
+///
+/// Sending never waits on the webhook, if it is disabled or the event type
+/// isn't in `WEBHOOK_EVENTS` the event is ignored.
+#[derive(Clone)]
+pub struct Webhooks {
+    tx: Option<mpsc::Sender<Event>>,
+    events: Arc<HashSet<EventType>>,
+}
+
+impl Webhooks {
+    pub fn playlist_created(&self, playlist_id: Uuid, owner_id: i64) {
+        self.send(EventType::PlaylistCreated, json!({
+            "playlist_id": playlist_id,
+            "owner_id": owner_id.to_string(),
+        }))
+    }
+
+    pub fn report_filed(&self, report_id: Uuid, target_id: Uuid) {
+        self.send(EventType::ReportFiled, json!({
+            "report_id": report_id,
+            "target_id": target_id,
+        }))
+    }
+
+    /// Sends a `vote-milestone` event if the playlist has just reached one
+    /// of the `VOTE_MILESTONES`.
+    pub fn votes_changed(&self, playlist_id: Uuid, votes: i32) {
+        if VOTE_MILESTONES.contains(&votes) {
+            self.send(EventType::VoteMilestone, json!({
+                "playlist_id": playlist_id,
+                "votes": votes,
+            }))
+        }
+    }
+
+    fn send(&self, type_: EventType, data: Value) {
+        let tx = match self.tx.as_ref() {
+            Some(tx) if self.events.contains(&type_) => tx,
+            _ => return,
+        };
+
+        if tx.try_send(Event { type_, data }).is_err() {
+            warn!("webhook queue is full, dropping {} event", type_);
+        }
+    }
+}
+
+
+/// Spawns the task which delivers queued events to the webhook one at a
+/// time, retrying failed deliveries with exponential backoff.
+///
+/// If no `WEBHOOK_URL` is set the task exits straight away and every event
+/// is ignored. The task stops once `shutdown` is set to true, dropping any
+/// events which haven't been delivered yet.
+pub fn spawn(
+    client: reqwest::Client,
+    config: &Config,
+    mut shutdown: watch::Receiver<bool>,
+) -> (Webhooks, JoinHandle<()>) {
+    let (tx, mut rx) = mpsc::channel(QUEUE_SIZE);
+    let url = config.webhook_url.clone();
+
+    let webhooks = Webhooks {
+        tx: url.is_some().then_some(tx),
+        events: Arc::new(config.webhook_events.iter().copied().collect()),
+    };
+
+    let handle = tokio::spawn(async move {
+        let url = match url {
+            None => return,
+            Some(url) => url,
+        };
+
+        loop {
+            let event = tokio::select! {
+                event = rx.recv() => event,
+                _ = shutdown.changed() => break,
+            };
+
+            match event {
+                None => break,
+                Some(event) => deliver(&client, &url, &event, &mut shutdown).await,
+            }
+        }
+
+        info!("webhook deliveries stopped");
+    });
+
+    (webhooks, handle)
+}
+
+async fn deliver(
+    client: &reqwest::Client,
+    url: &str,
+    event: &Event,
+    shutdown: &mut watch::Receiver<bool>,
+) {
+    let mut backoff = INITIAL_BACKOFF;
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        let result = client.post(url)
+            .json(event)
+            .timeout(DELIVERY_TIMEOUT)
+            .send()
+            .await;
+
+        let retry = match result {
+            Ok(resp) if resp.status().is_success() => return,
+            // Other client errors won't succeed by trying again.
+            Ok(resp) if resp.status().is_client_error() & (resp.status() != reqwest::StatusCode::TOO_MANY_REQUESTS) => {
+                warn!("webhook rejected {} event with status {}", event.type_, resp.status());
+                return
+            },
+            Ok(resp) => format!("status {}", resp.status()),
+            Err(e) => e.to_string(),
+        };
+
+        if attempt == MAX_ATTEMPTS {
+            error!("failed to deliver {} event after {} attempts: {}", event.type_, attempt, retry);
+            return
+        }
+
+        warn!("failed to deliver {} event, retrying in {:?}: {}", event.type_, backoff, retry);
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {},
+            _ = shutdown.changed() => return,
+        }
+        backoff *= 2;
+    }
+}